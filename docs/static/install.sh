@@ -147,6 +147,7 @@ WorkingDirectory=$CONFIG_DIR
 Restart=always
 RestartSec=3
 User=root
+Delegate=yes
 
 [Install]
 WantedBy=multi-user.target
@@ -6,8 +6,11 @@ import type {
   ApiResponse,
   ServiceConfig,
   LogEntry,
+  LogQuery,
   ServiceStats,
+  RestartPolicy,
 } from "./types";
+import { goto } from "$app/navigation";
 
 function getBASEURL(): string {
   let BASE;
@@ -22,7 +25,10 @@ function getBASEURL(): string {
 
 async function request<T>(path: string, options?: RequestInit): Promise<T> {
   const url = `${getBASEURL()}${path}`;
-  const res = await fetch(url, options);
+  const res = await fetch(url, { credentials: "include", ...options });
+  if (res.status === 401 && !path.startsWith("/auth/")) {
+    goto("/login");
+  }
   const data = await res.json();
   if (!res.ok) {
     throw new Error(
@@ -32,6 +38,16 @@ async function request<T>(path: string, options?: RequestInit): Promise<T> {
   return data as T;
 }
 
+export async function login(
+  token: string,
+): Promise<ApiResponse & { name?: string }> {
+  return request("/auth/login", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ token }),
+  });
+}
+
 export async function getHealth(): Promise<HealthInfo> {
   return request("/health");
 }
@@ -80,6 +96,7 @@ export async function configureService(
     env?: Record<string, string>;
     args?: string[];
     auto_restart?: boolean;
+    restart_policy?: RestartPolicy;
     restart_limit?: number | null;
     linux_capabilities?: string[];
   },
@@ -175,16 +192,35 @@ export async function deleteConfigTemplate(id: string): Promise<ApiResponse> {
 
 export async function getLogs(
   id: string,
-): Promise<{ service: string; logs: LogEntry[] }> {
-  return request(`/services/${id}/logs`);
+  query: LogQuery = {},
+): Promise<{
+  service: string;
+  logs: LogEntry[];
+  next_cursor?: number | null;
+  dropped_lines?: number;
+}> {
+  const { fields = {}, ...rest } = query;
+  const params = new URLSearchParams();
+  for (const [key, value] of Object.entries(rest)) {
+    if (value !== undefined && value !== "") params.set(key, String(value));
+  }
+  for (const [name, value] of Object.entries(fields)) {
+    params.set(`field.${name}`, value);
+  }
+  const search = params.toString() ? `?${params}` : "";
+  return request(`/services/${id}/logs${search}`);
 }
 
 export async function clearLogs(id: string): Promise<ApiResponse> {
   return request(`/services/${id}/logs/clear`, { method: "POST" });
 }
 
-export function streamLogs(id: string): EventSource {
-  return new EventSource(`${getBASEURL()}/services/${id}/logs/stream`);
+export function streamLogs(id: string, lastSeq?: number): EventSource {
+  const resume = lastSeq ? `?last_event_id=${lastSeq}` : "";
+  return new EventSource(
+    `${getBASEURL()}/services/${id}/logs/stream${resume}`,
+    { withCredentials: true },
+  );
 }
 
 export async function getServiceStats(id: string): Promise<ServiceStats> {
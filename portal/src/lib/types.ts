@@ -1,10 +1,23 @@
+export type DesiredState = "running" | "stopped";
+
 export interface ServiceInfo {
   id: string;
   name: string;
   state: string;
+  desired_state: DesiredState;
+  health: HealthState | null;
   ready: boolean;
 }
 
+export type HealthState = "Starting" | "Healthy" | "Unhealthy";
+
+export interface HealthStatus {
+  status: HealthState;
+  failing_streak: number;
+  last_checked_at: string | null;
+  last_error: string | null;
+}
+
 export interface ServiceDefinition {
   id: string;
   name: string;
@@ -13,12 +26,51 @@ export interface ServiceDefinition {
   args: string[];
   env: Record<string, string>;
   auto_restart: boolean;
+  restart_policy?: RestartPolicy;
   restart_limit: number | null;
+  desired_state?: DesiredState;
   current_version: string | null;
   linux_capabilities: string[];
+  restart_backoff?: RestartBackoff;
+  restarts?: RestartStatus | null;
+  health?: HealthStatus | null;
+  watchdog_secs?: number | null;
+  stop_signal?: string;
+  stop_timeout_secs?: number;
+  kill_mode?: KillMode;
+  orphan_policy?: OrphanPolicy;
+  resources?: Resources | null;
+  process?: ProcessSettings | null;
+  run_as?: RunAs | null;
+  sandbox?: Sandbox | null;
+  log_format?: LogFormat;
+  last_exit?: LastExit | null;
+  status_text?: string | null;
   port?: number;
 }
 
+export type KillMode = "group" | "main";
+
+export type LogFormat = "plain" | "auto" | "json" | "logfmt";
+
+export type OrphanPolicy = "terminate" | "adopt";
+
+export type RestartPolicy = "always" | "on-failure" | "never" | "unless-stopped";
+
+export interface RestartBackoff {
+  initial_delay_ms: number;
+  multiplier: number;
+  max_delay_ms: number;
+  jitter: number;
+  stable_after_secs: number;
+  crash_loop_threshold: number;
+}
+
+export interface RestartStatus {
+  consecutive_failures: number;
+  next_restart_at: string | null;
+}
+
 export interface ServiceView extends ServiceDefinition {
   state: string;
 }
@@ -64,9 +116,29 @@ export interface ServiceConfig {
 }
 
 export interface LogEntry {
+  seq?: number;
   timestamp: string;
   level: string;
   message: string;
+  target?: string;
+  fields?: Record<string, unknown>;
+}
+
+export interface LogQuery {
+  tail?: number;
+  /** RFC 3339 time, or a duration before now such as `15m`. */
+  since?: string;
+  until?: string;
+  /** Comma-separated levels. */
+  level?: string;
+  q?: string;
+  regex?: boolean;
+  /** Target of parsed lines, including the targets nested in it. */
+  target?: string;
+  /** Values fields of parsed lines must have; sent as `field.<name>`. */
+  fields?: Record<string, string>;
+  /** `next_cursor` of the previous page. */
+  cursor?: number;
 }
 
 export interface ServiceStats {
@@ -74,4 +146,65 @@ export interface ServiceStats {
   cpu_usage: number;
   memory_mb: number;
   pid: number | null;
+  cgroup: CgroupStats | null;
+}
+
+export interface ProcessSettings {
+  rlimit_nofile?: number | null;
+  rlimit_core?: string | null;
+  rlimit_as?: string | null;
+  nice?: number | null;
+  ionice_class?: "realtime" | "best-effort" | "idle" | null;
+  ionice_level?: number | null;
+  cpu_affinity?: number[] | null;
+}
+
+export type RunAs =
+  | { type: "user"; user: string; group?: string | null }
+  | { type: "dynamic" };
+
+export interface Sandbox {
+  read_only_root?: boolean;
+  private_tmp?: boolean;
+  no_new_privileges?: boolean;
+}
+
+export type ExitKind =
+  | "exited"
+  | "signaled"
+  | "core_dumped"
+  | "oom_killed"
+  | "unknown";
+
+export interface LastExit {
+  kind: ExitKind;
+  exit_code: number | null;
+  signal: number | null;
+  signal_name: string | null;
+  core_dumped: boolean;
+  oom_killed: boolean;
+  stopped_by: string | null;
+  exited_at: string;
+  ran_for_secs: number;
+  summary: string;
+}
+
+export interface CgroupStats {
+  path: string;
+  memory_current: number | null;
+  memory_max: string | null;
+  memory_high: string | null;
+  cpu_max: string | null;
+  cpu_usage_usec: number | null;
+  pids_current: number | null;
+  pids_max: string | null;
+  io_weight: string | null;
+}
+
+export interface Resources {
+  memory_max?: string | null;
+  memory_high?: string | null;
+  cpu_max?: { quota_us: number; period_us?: number } | null;
+  pids_max?: number | null;
+  io_weight?: number | null;
 }
@@ -0,0 +1,225 @@
+use axum::{
+    Json,
+    body::{Body, HttpBody, to_bytes},
+    extract::{MatchedPath, RawPathParams, Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{Value, json};
+
+use crate::{
+    api::auth::authenticate,
+    audit::{AuditOutcome, AuditRecord, redact},
+    auth::Principal,
+    platform::node::Node,
+};
+
+/// JSON request and response bodies above this size are not buffered.
+const MAX_BUFFERED_BODY: usize = 2 * 1024 * 1024;
+
+/// Records every POST and DELETE to a service route, including the ones
+/// authentication rejects, so it has to wrap [`require_auth`]. The actor is
+/// taken from the [`Principal`] that `require_auth` attaches to the response.
+/// JSON request bodies are redacted before they are written; other bodies are
+/// summarised by content type and length.
+///
+/// [`require_auth`]: crate::api::auth::require_auth
+pub async fn record_audit(
+    State(node): State<Node>,
+    matched_path: MatchedPath,
+    path_params: RawPathParams,
+    req: Request,
+    next: Next,
+) -> Response {
+    if req.method() != Method::POST && req.method() != Method::DELETE {
+        return next.run(req).await;
+    }
+
+    let route = matched_path
+        .as_str()
+        .strip_prefix("/api")
+        .unwrap_or(matched_path.as_str())
+        .to_string();
+    let action = action_name(req.method(), &route);
+    let path_id = path_params
+        .iter()
+        .find(|(key, _)| *key == "id")
+        .map(|(_, value)| value.to_string());
+
+    // The handler and `require_auth` never see a request whose body is too
+    // large to summarise, but the attempt is recorded all the same.
+    let headers = req.headers().clone();
+    let (req, request_summary) = match summarise_request(req).await {
+        Ok(parts) => parts,
+        Err(response) => {
+            let actor = authenticate(&node, &headers)
+                .await
+                .map(|p| p.name)
+                .unwrap_or_else(|| "anonymous".to_string());
+            let record = AuditRecord {
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                actor,
+                service_id: path_id,
+                action,
+                request: json!({ "body": "omitted, too large" }),
+                outcome: AuditOutcome {
+                    status: response.status().as_u16(),
+                    success: false,
+                    message: Some("request body too large".to_string()),
+                },
+            };
+            append(&node, record).await;
+            return response;
+        }
+    };
+
+    let response = next.run(req).await;
+    let status = response.status();
+    let actor = response
+        .extensions()
+        .get::<Principal>()
+        .map(|p| p.name.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+
+    // Only bodies known to be small are read for their message; anything
+    // else goes to the client untouched.
+    let (parts, body) = response.into_parts();
+    let (body, response_json) = match body.size_hint().upper() {
+        Some(len) if len <= MAX_BUFFERED_BODY as u64 => {
+            match to_bytes(body, MAX_BUFFERED_BODY).await {
+                Ok(bytes) => {
+                    let json = serde_json::from_slice::<Value>(&bytes).ok();
+                    (Body::from(bytes), json)
+                }
+                Err(e) => {
+                    tracing::error!("failed to buffer response for audit: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+        _ => (body, None),
+    };
+
+    let message = response_json.as_ref().and_then(|v| {
+        v.get("error")
+            .or_else(|| v.get("message"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    });
+
+    // Routes without an `{id}` segment carry the service id in the request
+    // or response body.
+    let service_id = path_id
+        .or_else(|| {
+            request_summary
+                .get("id")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .or_else(|| {
+            response_json
+                .as_ref()
+                .and_then(|v| v.get("id"))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        });
+
+    let record = AuditRecord {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        actor,
+        service_id,
+        action,
+        request: request_summary,
+        outcome: AuditOutcome {
+            status: status.as_u16(),
+            success: status.is_success(),
+            message,
+        },
+    };
+
+    append(&node, record).await;
+
+    Response::from_parts(parts, body)
+}
+
+/// Writes `record` to the audit log off the async workers.
+async fn append(node: &Node, record: AuditRecord) {
+    let audit = node.audit.clone();
+    match tokio::task::spawn_blocking(move || audit.append(&record)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!(error = ?e, "failed to write audit record"),
+        Err(e) => tracing::error!(error = ?e, "audit writer task failed"),
+    }
+}
+
+async fn summarise_request(req: Request) -> Result<(Request, Value), Response> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_string();
+
+    if !content_type.starts_with("application/json") {
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+
+        let summary = if content_type.is_empty() {
+            Value::Null
+        } else {
+            json!({
+                "content_type": content_type,
+                "bytes": length,
+            })
+        };
+
+        return Ok((req, summary));
+    }
+
+    let (parts, body) = req.into_parts();
+    let bytes = to_bytes(body, MAX_BUFFERED_BODY).await.map_err(|e| {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({
+                "status": false,
+                "error": format!("request body too large: {}", e)
+            })),
+        )
+            .into_response()
+    })?;
+
+    let summary = serde_json::from_slice::<Value>(&bytes)
+        .map(|v| redact(&v))
+        .unwrap_or(Value::Null);
+
+    Ok((Request::from_parts(parts, Body::from(bytes)), summary))
+}
+
+fn action_name(method: &Method, route: &str) -> String {
+    let action = match (method.as_str(), route) {
+        ("POST", "/services") => "service.create",
+        ("POST", "/services/init") => "service.init",
+        ("DELETE", "/services/{id}") => "service.delete",
+        ("POST", "/services/{id}/configure") => "service.configure",
+        ("POST", "/services/{id}/start") => "service.start",
+        ("POST", "/services/{id}/stop") => "service.stop",
+        ("POST", "/services/{id}/restart") => "service.restart",
+        ("POST", "/services/{id}/artifact/upload") => "artifact.upload",
+        ("POST", "/services/{id}/artifact/github") => "artifact.github",
+        ("POST", "/services/{id}/config") => "config.update",
+        ("POST", "/services/{id}/config/template") => "config.template.update",
+        ("DELETE", "/services/{id}/config/template") => "config.template.delete",
+        ("POST", "/services/{id}/logs/clear") => "logs.clear",
+        _ => return format!("{} {}", method, route),
+    };
+
+    action.to_string()
+}
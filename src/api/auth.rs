@@ -0,0 +1,237 @@
+use axum::{
+    Json,
+    extract::{MatchedPath, RawPathParams, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+use crate::{
+    auth::{Principal, Role},
+    platform::node::Node,
+};
+
+pub const SESSION_COOKIE: &str = "dockless_session";
+
+/// Minimum role for every API route, keyed by method and the route pattern as
+/// registered in the route modules. Routes missing from this table are
+/// admin-only.
+const ROUTE_ROLES: &[(&str, &str, Role)] = &[
+    ("GET", "/health", Role::Viewer),
+    ("GET", "/auth/me", Role::Viewer),
+    ("GET", "/services", Role::Viewer),
+    ("POST", "/services", Role::Admin),
+    ("POST", "/services/init", Role::Admin),
+    ("GET", "/services/ports", Role::Admin),
+    ("GET", "/services/{id}", Role::Viewer),
+    ("DELETE", "/services/{id}", Role::Admin),
+    ("POST", "/services/{id}/configure", Role::Admin),
+    ("POST", "/services/{id}/start", Role::Admin),
+    ("POST", "/services/{id}/stop", Role::Admin),
+    ("POST", "/services/{id}/restart", Role::Admin),
+    ("POST", "/services/{id}/artifact/upload", Role::Deployer),
+    ("POST", "/services/{id}/artifact/github", Role::Deployer),
+    ("GET", "/services/{id}/artifact", Role::Viewer),
+    ("GET", "/services/{id}/config", Role::Admin),
+    ("POST", "/services/{id}/config", Role::Admin),
+    ("POST", "/services/{id}/config/template", Role::Admin),
+    ("DELETE", "/services/{id}/config/template", Role::Admin),
+    ("GET", "/services/{id}/logs", Role::Viewer),
+    ("GET", "/services/{id}/logs/stream", Role::Viewer),
+    ("POST", "/services/{id}/logs/clear", Role::Admin),
+    ("GET", "/services/{id}/stats", Role::Viewer),
+    ("GET", "/registry", Role::Admin),
+    ("GET", "/audit", Role::Admin),
+    ("GET", "/tokens", Role::Admin),
+    ("POST", "/tokens", Role::Admin),
+    ("DELETE", "/tokens/{name}", Role::Admin),
+];
+
+/// Rejects any request that carries neither a valid bearer token nor a valid
+/// portal session cookie, then checks the token's role and service scope
+/// against [`ROUTE_ROLES`]. On success the resolved [`Principal`](crate::auth::Principal) is stored in
+/// the request extensions for downstream handlers, and in the response
+/// extensions, also of a rejection, for the audit layer around it.
+pub async fn require_auth(
+    State(node): State<Node>,
+    matched_path: MatchedPath,
+    path_params: RawPathParams,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let Some(principal) = authenticate(&node, req.headers()).await else {
+        return unauthorized();
+    };
+
+    let method = req.method().as_str();
+    let route = matched_path
+        .as_str()
+        .strip_prefix("/api")
+        .unwrap_or(matched_path.as_str());
+
+    let required = required_role(method, route);
+
+    if principal.role < required {
+        let error = format!(
+            "token '{}' has role '{}' but {} {} requires '{}'",
+            principal.name, principal.role, method, route, required
+        );
+        return with_principal(forbidden(error), principal);
+    }
+
+    let service_id = path_params
+        .iter()
+        .find(|(key, _)| *key == "id")
+        .map(|(_, value)| value);
+
+    let denied = match service_id {
+        Some(id) if !principal.can_access_service(id) => Some(format!(
+            "token '{}' is not allowed to access service '{}'",
+            principal.name, id
+        )),
+        None if principal.services.is_some() && required == Role::Admin => Some(format!(
+            "token '{}' is scoped to specific services and cannot {} {}",
+            principal.name, method, route
+        )),
+        _ => None,
+    };
+    if let Some(error) = denied {
+        return with_principal(forbidden(error), principal);
+    }
+
+    req.extensions_mut().insert(principal.clone());
+    with_principal(next.run(req).await, principal)
+}
+
+/// Minimum role for `method` on `route`, per [`ROUTE_ROLES`].
+fn required_role(method: &str, route: &str) -> Role {
+    ROUTE_ROLES
+        .iter()
+        .find(|(m, r, _)| *m == method && *r == route)
+        .map(|(_, _, role)| *role)
+        .unwrap_or(Role::Admin)
+}
+
+/// The principal of the bearer token or portal session cookie in `headers`.
+pub async fn authenticate(node: &Node, headers: &HeaderMap) -> Option<Principal> {
+    let auth = node.auth.read().await;
+
+    if let Some(token) = bearer_token(headers) {
+        auth.authenticate(token)
+    } else if let Some(session) = session_cookie(headers) {
+        auth.verify_session(session)
+    } else {
+        None
+    }
+}
+
+fn with_principal(mut response: Response, principal: Principal) -> Response {
+    response.extensions_mut().insert(principal);
+    response
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then_some(value)
+        })
+}
+
+fn forbidden(error: String) -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "status": false,
+            "error": error
+        })),
+    )
+        .into_response()
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(json!({
+            "status": false,
+            "error": "authentication required"
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn routes_require_their_listed_role() {
+        assert_eq!(required_role("GET", "/health"), Role::Viewer);
+        assert_eq!(required_role("GET", "/services/{id}/logs"), Role::Viewer);
+        assert_eq!(
+            required_role("POST", "/services/{id}/artifact/upload"),
+            Role::Deployer
+        );
+        assert_eq!(required_role("POST", "/services/{id}/start"), Role::Admin);
+    }
+
+    #[test]
+    fn unlisted_routes_are_admin_only() {
+        assert_eq!(required_role("DELETE", "/services/{id}/logs"), Role::Admin);
+        assert_eq!(required_role("GET", "/nonexistent"), Role::Admin);
+        // Matched against the pattern, not the concrete path.
+        assert_eq!(required_role("GET", "/services/web"), Role::Admin);
+    }
+
+    #[test]
+    fn route_table_has_no_duplicates() {
+        for (i, (method, route, _)) in ROUTE_ROLES.iter().enumerate() {
+            assert!(
+                !ROUTE_ROLES[..i]
+                    .iter()
+                    .any(|(m, r, _)| m == method && r == route),
+                "{} {} is listed twice",
+                method,
+                route
+            );
+        }
+    }
+
+    #[test]
+    fn reads_bearer_tokens_and_session_cookies() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+        assert_eq!(session_cookie(&headers), None);
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer  abc "),
+        );
+        headers.append(header::COOKIE, HeaderValue::from_static("theme=dark"));
+        headers.append(
+            header::COOKIE,
+            HeaderValue::from_static("a=1; dockless_session=xyz; b=2"),
+        );
+        assert_eq!(bearer_token(&headers), Some("abc"));
+        assert_eq!(session_cookie(&headers), Some("xyz"));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic abc"));
+        assert_eq!(bearer_token(&headers), None);
+    }
+}
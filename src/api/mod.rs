@@ -1,2 +1,5 @@
+pub mod audit;
+pub mod auth;
 pub mod routes;
 pub mod server;
+pub mod tls;
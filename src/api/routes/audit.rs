@@ -0,0 +1,91 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{audit::AuditFilter, platform::node::Node};
+
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+pub fn routes() -> Router<Node> {
+    Router::new().route("/audit", get(get_audit))
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    #[serde(default)]
+    service: Option<String>,
+    #[serde(default)]
+    actor: Option<String>,
+    #[serde(default)]
+    since: Option<String>,
+    #[serde(default)]
+    until: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+async fn get_audit(State(node): State<Node>, Query(query): Query<AuditQuery>) -> impl IntoResponse {
+    let since = match query.since.as_deref().map(parse_time).transpose() {
+        Ok(t) => t,
+        Err(e) => return bad_request(format!("invalid since: {}", e)),
+    };
+
+    let until = match query.until.as_deref().map(parse_time).transpose() {
+        Ok(t) => t,
+        Err(e) => return bad_request(format!("invalid until: {}", e)),
+    };
+
+    let filter = AuditFilter {
+        service_id: query.service,
+        actor: query.actor,
+        since,
+        until,
+        limit: Some(query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT)),
+    };
+
+    let audit = node.audit.clone();
+    match tokio::task::spawn_blocking(move || audit.query(&filter)).await {
+        Ok(Ok(records)) => Json(json!({
+            "records": records
+        }))
+        .into_response(),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": false,
+                "error": e.to_string()
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": false,
+                "error": format!("audit query failed: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    DateTime::parse_from_rfc3339(value).map(|t| t.with_timezone(&Utc))
+}
+
+fn bad_request(error: String) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "status": false,
+            "error": error
+        })),
+    )
+        .into_response()
+}
@@ -0,0 +1,186 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::{delete, get, post},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    api::auth::SESSION_COOKIE,
+    auth::{Principal, Role, SESSION_TTL_SECS},
+    platform::node::Node,
+};
+
+/// Routes that require an authenticated principal.
+pub fn routes() -> Router<Node> {
+    Router::new()
+        .route("/auth/me", get(whoami))
+        .route("/tokens", get(list_tokens))
+        .route("/tokens", post(create_token))
+        .route("/tokens/{name}", delete(revoke_token))
+}
+
+/// Routes reachable without credentials.
+pub fn public_routes() -> Router<Node> {
+    Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/logout", post(logout))
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    token: String,
+}
+
+async fn login(State(node): State<Node>, Json(req): Json<LoginRequest>) -> impl IntoResponse {
+    let auth = node.auth.read().await;
+
+    let Some(principal) = auth.authenticate(req.token.trim()) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "status": false,
+                "error": "invalid token"
+            })),
+        )
+            .into_response();
+    };
+
+    let mut cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+        SESSION_COOKIE,
+        auth.issue_session(&principal),
+        SESSION_TTL_SECS
+    );
+
+    if node.config.tls.is_some() {
+        cookie.push_str("; Secure");
+    }
+
+    (
+        StatusCode::OK,
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({
+            "status": true,
+            "message": "Logged in",
+            "name": principal.name
+        })),
+    )
+        .into_response()
+}
+
+async fn logout() -> impl IntoResponse {
+    let cookie = format!(
+        "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0",
+        SESSION_COOKIE
+    );
+
+    (
+        StatusCode::OK,
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({
+            "status": true,
+            "message": "Logged out"
+        })),
+    )
+}
+
+async fn whoami(Extension(principal): Extension<Principal>) -> impl IntoResponse {
+    Json(json!({
+        "name": principal.name,
+        "role": principal.role,
+        "services": principal.services
+    }))
+}
+
+#[derive(Serialize)]
+struct TokenInfo {
+    name: String,
+    created_at: String,
+    role: Role,
+    services: Option<Vec<String>>,
+}
+
+async fn list_tokens(State(node): State<Node>) -> impl IntoResponse {
+    let auth = node.auth.read().await;
+
+    let tokens: Vec<TokenInfo> = auth
+        .list()
+        .iter()
+        .map(|t| TokenInfo {
+            name: t.name.clone(),
+            created_at: t.created_at.clone(),
+            role: t.role,
+            services: t.services.clone(),
+        })
+        .collect();
+
+    Json(tokens)
+}
+
+#[derive(Deserialize)]
+struct CreateTokenRequest {
+    name: String,
+    #[serde(default)]
+    role: Option<Role>,
+    #[serde(default)]
+    services: Option<Vec<String>>,
+}
+
+async fn create_token(
+    State(node): State<Node>,
+    Json(req): Json<CreateTokenRequest>,
+) -> impl IntoResponse {
+    let mut auth = node.auth.write().await;
+
+    let role = req.role.unwrap_or(Role::Viewer);
+
+    match auth.create(req.name.trim(), role, req.services.clone()) {
+        Ok(token) => (
+            StatusCode::CREATED,
+            Json(json!({
+                "status": true,
+                "message": "Token created. Store it now, it will not be shown again.",
+                "name": req.name.trim(),
+                "role": role,
+                "services": req.services,
+                "token": token
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": false,
+                "error": e.to_string()
+            })),
+        )
+            .into_response(),
+    }
+}
+
+async fn revoke_token(State(node): State<Node>, Path(name): Path<String>) -> impl IntoResponse {
+    let mut auth = node.auth.write().await;
+
+    match auth.revoke(&name) {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": true,
+                "message": "Token revoked"
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": false,
+                "error": e.to_string()
+            })),
+        )
+            .into_response(),
+    }
+}
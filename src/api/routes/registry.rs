@@ -1,5 +1,9 @@
 use crate::platform::node::Node;
 use crate::platform::port_manager::PortManager;
+use crate::registry::{
+    DesiredState, HealthCheck, KillMode, LogFormat, LogRotationOverride, OrphanPolicy,
+    ProcessSettings, Readiness, Resources, RestartBackoff, RestartPolicy, RunAs, Sandbox,
+};
 use axum::{Json, Router, extract::State, response::IntoResponse, routing::get};
 use serde::Serialize;
 
@@ -16,9 +20,25 @@ struct RegistryEntry {
     args: Vec<String>,
     env: std::collections::HashMap<String, String>,
     auto_restart: bool,
+    restart_policy: RestartPolicy,
+    desired_state: DesiredState,
     restart_limit: Option<u32>,
     current_version: Option<String>,
     linux_capabilities: Vec<String>,
+    restart_backoff: RestartBackoff,
+    health_check: Option<HealthCheck>,
+    readiness: Readiness,
+    watchdog_secs: Option<u64>,
+    stop_signal: String,
+    stop_timeout_secs: u64,
+    kill_mode: KillMode,
+    orphan_policy: OrphanPolicy,
+    resources: Option<Resources>,
+    process: Option<ProcessSettings>,
+    run_as: Option<RunAs>,
+    sandbox: Option<Sandbox>,
+    log_rotation: Option<LogRotationOverride>,
+    log_format: LogFormat,
     port: Option<u16>,
 }
 
@@ -35,22 +55,21 @@ async fn get_registry(State(node): State<Node>) -> impl IntoResponse {
             let port = 'port: {
                 // Look up the PID of the running service synchronously via a blocking read.
                 // We use try_read so we don't deadlock; fall back to None on contention.
-                if let Some(service) = manager.get_service(&def.id) {
-                    if let Ok(pid_guard) = service.pid.try_read() {
-                        if let Some(pid) = *pid_guard {
-                            let listening = PortManager::get_listening_ports_for_pid(pid);
-                            if !listening.is_empty() {
-                                // Prefer the allocated port if the service is listening on it,
-                                // otherwise report the first port the process actually bound.
-                                let allocated = port_manager.get_port(&def.id);
-                                if let Some(a) = allocated {
-                                    if listening.contains(&a) {
-                                        break 'port Some(a);
-                                    }
-                                }
-                                break 'port Some(listening[0]);
-                            }
+                if let Some(service) = manager.get_service(&def.id)
+                    && let Ok(pid_guard) = service.pid.try_read()
+                    && let Some(pid) = *pid_guard
+                {
+                    let listening = PortManager::get_listening_ports_for_pid(pid);
+                    if !listening.is_empty() {
+                        // Prefer the allocated port if the service is listening on it,
+                        // otherwise report the first port the process actually bound.
+                        let allocated = port_manager.get_port(&def.id);
+                        if let Some(a) = allocated
+                            && listening.contains(&a)
+                        {
+                            break 'port Some(a);
                         }
+                        break 'port Some(listening[0]);
                     }
                 }
                 // Service not running or not yet listening — don't expose the allocated port.
@@ -65,9 +84,25 @@ async fn get_registry(State(node): State<Node>) -> impl IntoResponse {
                 args: def.args.clone(),
                 env: def.env.clone(),
                 auto_restart: def.auto_restart,
+                restart_policy: def.effective_restart_policy(),
+                desired_state: def.desired_state,
                 restart_limit: def.restart_limit,
                 current_version: def.current_version.clone(),
                 linux_capabilities: def.linux_capabilities.clone(),
+                restart_backoff: def.restart_backoff.clone(),
+                health_check: def.health_check.clone(),
+                readiness: def.readiness.clone(),
+                watchdog_secs: def.watchdog_secs,
+                stop_signal: def.stop_signal.clone(),
+                stop_timeout_secs: def.stop_timeout_secs,
+                kill_mode: def.kill_mode,
+                orphan_policy: def.orphan_policy,
+                resources: def.resources.clone(),
+                process: def.process.clone(),
+                run_as: def.run_as.clone(),
+                sandbox: def.sandbox,
+                log_rotation: def.log_rotation.clone(),
+                log_format: def.log_format,
                 port,
             }
         })
@@ -1,8 +1,9 @@
+use crate::auth::{Principal, Role};
 use crate::platform::node::Node;
-use axum::extract::{Multipart, Path, State};
+use axum::extract::{Multipart, Path, Query, State};
 use axum::{
-    Json, Router,
-    http::StatusCode,
+    Extension, Json, Router,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Sse},
     routing::{delete, get, post},
 };
@@ -12,12 +13,26 @@ use std::convert::Infallible;
 use std::fs;
 use std::time::Duration;
 use sysinfo::System;
+use tokio::sync::broadcast;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    registry::ServiceDefinition,
-    runtime::service::{Service, ServiceState},
+    registry::{
+        DesiredState, HealthCheck, HealthProbe, KillMode, LogFormat, LogRotationOverride,
+        OrphanPolicy, ProcessSettings, Readiness, Resources, RestartBackoff, RestartPolicy, RunAs,
+        Sandbox, ServiceDefinition, default_stop_signal, default_stop_timeout_secs, parse_signal,
+    },
+    runtime::{
+        capabilities,
+        cgroup::{Cgroup, CgroupStats},
+        credentials,
+        health::HealthState,
+        log_buffer::{LogBuffer, LogEntry},
+        log_query::{self, LogQuery, Pattern},
+        service::{Service, ServiceState},
+        supervisor_manager::SupervisorManager,
+    },
 };
 
 pub fn routes() -> Router<Node> {
@@ -59,22 +74,34 @@ struct ServiceInfo {
     id: String,
     name: String,
     state: ServiceState,
+    desired_state: DesiredState,
+    health: Option<HealthState>,
     ready: bool,
 }
-async fn list_services(State(node): State<Node>) -> impl IntoResponse {
+async fn list_services(
+    State(node): State<Node>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
     let mut services = Vec::new();
 
     let registry = node.registry.read().await;
 
     for service in node.manager.read().await.list().await.iter() {
+        if !principal.can_access_service(&service.id) {
+            continue;
+        }
+
         let state = service.get_state().await;
         let def = registry.get(&service.id);
         let ready = def.map(|d| d.ready).unwrap_or(false);
+        let desired_state = def.map(|d| d.desired_state).unwrap_or_default();
 
         services.push(ServiceInfo {
             id: service.id.clone(),
             name: service.name.clone(),
             state,
+            desired_state,
+            health: service.get_health().await.map(|h| h.status),
             ready,
         });
     }
@@ -90,6 +117,8 @@ struct InitServiceRequest {
     #[serde(default)]
     auto_restart: Option<bool>,
     #[serde(default)]
+    restart_policy: Option<RestartPolicy>,
+    #[serde(default)]
     restart_limit: Option<u32>,
     #[serde(default)]
     linux_capabilities: Vec<String>,
@@ -110,6 +139,17 @@ async fn init_service(
             .into_response();
     }
 
+    if let Err(e) = capabilities::validate(&req.linux_capabilities) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": false,
+                "error": format!("linux_capabilities: {}", e)
+            })),
+        )
+            .into_response();
+    }
+
     let id = req.id.unwrap_or_else(|| {
         req.name
             .to_lowercase()
@@ -148,9 +188,25 @@ async fn init_service(
         args: vec![],
         env: HashMap::new(),
         auto_restart: req.auto_restart.unwrap_or(false),
+        restart_policy: req.restart_policy,
+        desired_state: DesiredState::Running,
         restart_limit: req.restart_limit.or(Some(3)),
         current_version: None,
         linux_capabilities: req.linux_capabilities.clone(),
+        restart_backoff: RestartBackoff::default(),
+        health_check: None,
+        readiness: Readiness::default(),
+        watchdog_secs: None,
+        stop_signal: default_stop_signal(),
+        stop_timeout_secs: default_stop_timeout_secs(),
+        kill_mode: KillMode::default(),
+        orphan_policy: OrphanPolicy::default(),
+        resources: None,
+        process: None,
+        run_as: None,
+        sandbox: None,
+        log_rotation: None,
+        log_format: LogFormat::default(),
         port: None,
     };
 
@@ -204,7 +260,15 @@ async fn init_service(
         .into_response()
 }
 
-async fn get_service(State(node): State<Node>, Path(id): Path<String>) -> impl IntoResponse {
+/// Stands in for environment values and arguments in responses to non-admin
+/// principals.
+const MASKED_VALUE: &str = "********";
+
+async fn get_service(
+    State(node): State<Node>,
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
     let def = {
         let registry = node.registry.read().await;
         match registry.get(&id) {
@@ -222,6 +286,16 @@ async fn get_service(State(node): State<Node>, Path(id): Path<String>) -> impl I
         }
     };
 
+    let (restarts, health, notify, last_exit) = match node.manager.read().await.get_service(&id) {
+        Some(service) => (
+            Some(service.get_restart_status().await),
+            service.get_health().await,
+            Some(service.get_notify_state().await),
+            service.get_last_exit().await,
+        ),
+        None => (None, None, None, None),
+    };
+
     let port = {
         let manager = node.manager.read().await;
         let port_manager = node.port_manager.read().await;
@@ -262,12 +336,33 @@ async fn get_service(State(node): State<Node>, Path(id): Path<String>) -> impl I
         "name": def.name,
         "ready": def.ready,
         "binary_path": def.binary_path,
-        "args": def.args,
-        "env": def.env,
+        "args": visible_args(&def.args, &principal),
+        "env": visible_env(&def.env, &principal),
         "auto_restart": def.auto_restart,
+        "restart_policy": def.effective_restart_policy(),
         "restart_limit": def.restart_limit,
+        "desired_state": def.desired_state,
         "current_version": def.current_version,
         "linux_capabilities": def.linux_capabilities,
+        "restart_backoff": def.restart_backoff,
+        "restarts": restarts,
+        "health_check": visible_health_check(def.health_check.as_ref(), &principal),
+        "readiness": def.readiness,
+        "watchdog_secs": def.watchdog_secs,
+        "stop_signal": def.stop_signal,
+        "stop_timeout_secs": def.stop_timeout_secs,
+        "kill_mode": def.kill_mode,
+        "orphan_policy": def.orphan_policy,
+        "resources": def.resources,
+        "process": def.process,
+        "run_as": def.run_as,
+        "sandbox": def.sandbox,
+        "log_rotation": def.log_rotation,
+        "log_format": def.log_format,
+        "status_text": notify.as_ref().and_then(|n| n.status.clone()),
+        "notify": notify,
+        "health": health,
+        "last_exit": last_exit,
     });
 
     if let Some(port_num) = port {
@@ -277,6 +372,44 @@ async fn get_service(State(node): State<Node>, Path(id): Path<String>) -> impl I
     (StatusCode::OK, Json(response)).into_response()
 }
 
+/// The environment of a service as `principal` may see it. It often holds
+/// secrets, so only admins, who can read and change them anyway through
+/// `/config` and `/configure`, get the values; everyone else gets the names.
+fn visible_env(env: &HashMap<String, String>, principal: &Principal) -> HashMap<String, String> {
+    if principal.role >= Role::Admin {
+        return env.clone();
+    }
+    env.keys()
+        .map(|key| (key.clone(), MASKED_VALUE.to_string()))
+        .collect()
+}
+
+/// Command line arguments as `principal` may see them. They can carry the
+/// same secrets as the environment, so non-admins only see how many there
+/// are.
+fn visible_args(args: &[String], principal: &Principal) -> Vec<String> {
+    if principal.role >= Role::Admin {
+        return args.to_vec();
+    }
+    vec![MASKED_VALUE.to_string(); args.len()]
+}
+
+/// The health check as `principal` may see it, with the arguments of an
+/// exec check masked like [`visible_args`].
+fn visible_health_check(
+    health_check: Option<&HealthCheck>,
+    principal: &Principal,
+) -> Option<HealthCheck> {
+    let mut health_check = health_check?.clone();
+    if let HealthProbe::Exec { command } = &mut health_check.probe
+        && let Some((_, args)) = command.split_first_mut()
+    {
+        let masked = visible_args(args, principal);
+        args.clone_from_slice(&masked);
+    }
+    Some(health_check)
+}
+
 #[derive(Deserialize)]
 pub struct ConfigureServiceRequest {
     #[serde(default)]
@@ -286,9 +419,84 @@ pub struct ConfigureServiceRequest {
     #[serde(default)]
     pub auto_restart: Option<bool>,
     #[serde(default)]
+    pub restart_policy: Option<RestartPolicy>,
+    #[serde(default)]
     pub restart_limit: Option<u32>,
     #[serde(default)]
     pub linux_capabilities: Option<Vec<String>>,
+    #[serde(default)]
+    pub restart_backoff: Option<RestartBackoff>,
+    /// `Some(None)` (an explicit `null`) removes the health check.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub health_check: Option<Option<HealthCheck>>,
+    #[serde(default)]
+    pub readiness: Option<Readiness>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub watchdog_secs: Option<Option<u64>>,
+    #[serde(default)]
+    pub stop_signal: Option<String>,
+    #[serde(default)]
+    pub stop_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub kill_mode: Option<KillMode>,
+    #[serde(default)]
+    pub orphan_policy: Option<OrphanPolicy>,
+    /// `Some(None)` (an explicit `null`) removes the limits.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub resources: Option<Option<Resources>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub process: Option<Option<ProcessSettings>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub run_as: Option<Option<RunAs>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub sandbox: Option<Option<Sandbox>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub log_rotation: Option<Option<LogRotationOverride>>,
+    #[serde(default)]
+    pub log_format: Option<LogFormat>,
+}
+
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+fn validate_supervision(def: &ServiceDefinition) -> anyhow::Result<()> {
+    capabilities::validate(&def.linux_capabilities)
+        .map_err(|e| anyhow::anyhow!("linux_capabilities: {}", e))?;
+    def.restart_backoff.validate()?;
+    parse_signal(&def.stop_signal)?;
+    def.readiness.validate(def.health_check.as_ref())?;
+
+    if def.watchdog_secs == Some(0) {
+        anyhow::bail!("watchdog_secs must be at least 1");
+    }
+
+    if let Some(check) = &def.health_check {
+        check.validate()?;
+    }
+
+    if let Some(resources) = &def.resources {
+        resources.validate()?;
+    }
+
+    if let Some(process) = &def.process {
+        process.validate()?;
+    }
+
+    if let Some(log_rotation) = &def.log_rotation {
+        log_rotation.validate()?;
+    }
+
+    if let Some(run_as) = &def.run_as {
+        run_as.validate()?;
+        credentials::check(run_as)?;
+    }
+
+    Ok(())
 }
 
 async fn configure_service(
@@ -312,15 +520,53 @@ async fn configure_service(
         }
     };
 
+    // An explicit policy wins. A bare `auto_restart` from older clients
+    // replaces the policy with the one it maps to.
+    let restart_policy = match (req.restart_policy, req.auto_restart) {
+        (Some(policy), _) => Some(policy),
+        (None, Some(_)) => None,
+        (None, None) => def.restart_policy,
+    };
+    let auto_restart = match req.restart_policy {
+        Some(policy) => policy != RestartPolicy::Never,
+        None => req.auto_restart.unwrap_or(def.auto_restart),
+    };
+
     let updated_def = ServiceDefinition {
         env: req.env,
         args: req.args,
-        auto_restart: req.auto_restart.unwrap_or(def.auto_restart),
+        auto_restart,
+        restart_policy,
         restart_limit: req.restart_limit,
         linux_capabilities: req.linux_capabilities.unwrap_or(def.linux_capabilities),
+        restart_backoff: req.restart_backoff.unwrap_or(def.restart_backoff),
+        health_check: req.health_check.unwrap_or(def.health_check),
+        readiness: req.readiness.unwrap_or(def.readiness),
+        watchdog_secs: req.watchdog_secs.unwrap_or(def.watchdog_secs),
+        stop_signal: req.stop_signal.unwrap_or(def.stop_signal),
+        stop_timeout_secs: req.stop_timeout_secs.unwrap_or(def.stop_timeout_secs),
+        kill_mode: req.kill_mode.unwrap_or(def.kill_mode),
+        orphan_policy: req.orphan_policy.unwrap_or(def.orphan_policy),
+        resources: req.resources.unwrap_or(def.resources),
+        process: req.process.unwrap_or(def.process),
+        run_as: req.run_as.unwrap_or(def.run_as),
+        sandbox: req.sandbox.unwrap_or(def.sandbox),
+        log_rotation: req.log_rotation.unwrap_or(def.log_rotation),
+        log_format: req.log_format.unwrap_or(def.log_format),
         ..def
     };
 
+    if let Err(e) = validate_supervision(&updated_def) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": false,
+                "error": e.to_string()
+            })),
+        )
+            .into_response();
+    }
+
     if let Err(e) = registry.update(&id, updated_def) {
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -343,11 +589,47 @@ async fn configure_service(
             .into_response();
     }
 
+    let Some(def) = registry.get(&id).cloned() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": false,
+                "error": "service not found"
+            })),
+        )
+            .into_response();
+    };
+    drop(registry);
+
+    // The manager's copy is what the next start runs, so it is rebuilt from
+    // the new definition. A running process keeps its old settings.
+    let registered = node.manager.read().await.get_service(&id).is_some();
+    if registered {
+        let service_root = format!("{}/services/{}", node.config.data_dir, id);
+        let mut env = def.env.clone();
+        if let Some(port) = node.port_manager.read().await.get_port(&id) {
+            env.insert("PORT".to_string(), port.to_string());
+        }
+
+        let log_buffer = log_buffer_for(&node, &def, &service_root).await;
+        let service = Service::new(&def, env, service_root, log_buffer);
+        if let Err(e) = node.manager.write().await.update_service(service) {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "status": false,
+                    "error": e.to_string()
+                })),
+            )
+                .into_response();
+        }
+    }
+
     (
         StatusCode::OK,
         Json(json!({
             "status": true,
-            "message": "Service configured"
+            "message": "Service configured. Restart the service to apply changes."
         })),
     )
         .into_response()
@@ -389,17 +671,21 @@ async fn start_service(State(node): State<Node>, Path(id): Path<String>) -> impl
         .any(|s| s == &id);
 
     if service_exists {
-        let mut manager = node.manager.write().await;
+        let result = node.manager.write().await.start(&id).await;
 
-        match manager.start(&id).await {
-            Ok(_) => (
-                StatusCode::OK,
-                Json(json!({
-                    "status": true,
-                    "message": "Service started"
-                })),
-            )
-                .into_response(),
+        match result {
+            Ok(_) => {
+                set_desired_state(&node, &id, DesiredState::Running).await;
+
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "status": true,
+                        "message": "Service started"
+                    })),
+                )
+                    .into_response()
+            }
 
             Err(e) => (
                 StatusCode::BAD_REQUEST,
@@ -458,27 +744,24 @@ async fn stop_service(State(node): State<Node>, Path(id): Path<String>) -> impl
         .any(|s| s == &id);
 
     if service_exists {
-        let mut manager = node.manager.write().await;
+        // Persisted first, so that a service that is not running, e.g. one
+        // that crashed, also stays stopped at the next boot.
+        set_desired_state(&node, &id, DesiredState::Stopped).await;
 
-        match manager.stop(&id).await {
-            Ok(_) => (
-                StatusCode::OK,
-                Json(json!({
-                    "status": true,
-                    "message": "Service stopped"
-                })),
-            )
-                .into_response(),
+        let message = if SupervisorManager::stop(&node.manager, &id).await {
+            "Service stopped"
+        } else {
+            "Service was not running"
+        };
 
-            Err(e) => (
-                StatusCode::BAD_REQUEST,
-                Json(json!({
-                    "status": false,
-                    "error": e.to_string()
-                })),
-            )
-                .into_response(),
-        }
+        (
+            StatusCode::OK,
+            Json(json!({
+                "status": true,
+                "message": message
+            })),
+        )
+            .into_response()
     } else {
         (
             StatusCode::NOT_FOUND,
@@ -491,6 +774,50 @@ async fn stop_service(State(node): State<Node>, Path(id): Path<String>) -> impl
     }
 }
 
+/// The log buffer for a rebuilt `def`: the registered service's, with the
+/// definition's rotation settings applied, or a new one if it has none.
+async fn log_buffer_for(node: &Node, def: &ServiceDefinition, service_root: &str) -> LogBuffer {
+    let current = node
+        .manager
+        .read()
+        .await
+        .get_service(&def.id)
+        .map(|service| service.log_buffer.clone());
+
+    match current {
+        Some(log_buffer) => {
+            log_buffer
+                .set_rotation(&def.effective_log_rotation(&node.config.log_rotation))
+                .await;
+            log_buffer
+        }
+        None => Service::create_log_buffer(def, service_root, &node.config.log_rotation),
+    }
+}
+
+/// Persists whether the service should be running so the choice survives a
+/// restart of dockless.
+async fn set_desired_state(node: &Node, id: &str, desired_state: DesiredState) {
+    let mut registry = node.registry.write().await;
+
+    let Some(def) = registry
+        .list_definitions_mut()
+        .iter_mut()
+        .find(|d| d.id == id)
+    else {
+        return;
+    };
+
+    if def.desired_state == desired_state {
+        return;
+    }
+    def.desired_state = desired_state;
+
+    if let Err(e) = registry.save() {
+        tracing::error!(error = ?e, "[{}] failed to persist desired state", id);
+    }
+}
+
 async fn restart_service(State(node): State<Node>, Path(id): Path<String>) -> impl IntoResponse {
     {
         let registry = node.registry.read().await;
@@ -527,17 +854,21 @@ async fn restart_service(State(node): State<Node>, Path(id): Path<String>) -> im
         .any(|s| s == &id);
 
     if service_exists {
-        let mut manager = node.manager.write().await;
+        let result = SupervisorManager::restart(&node.manager, &id).await;
 
-        match manager.restart(&id).await {
-            Ok(_) => (
-                StatusCode::OK,
-                Json(json!({
-                    "status": true,
-                    "message": "Service restarted"
-                })),
-            )
-                .into_response(),
+        match result {
+            Ok(_) => {
+                set_desired_state(&node, &id, DesiredState::Running).await;
+
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "status": true,
+                        "message": "Service restarted"
+                    })),
+                )
+                    .into_response()
+            }
 
             Err(e) => (
                 StatusCode::BAD_REQUEST,
@@ -562,7 +893,7 @@ async fn restart_service(State(node): State<Node>, Path(id): Path<String>) -> im
 
 async fn create_service(
     State(node): State<Node>,
-    Json(def): Json<ServiceDefinition>,
+    Json(mut def): Json<ServiceDefinition>,
 ) -> impl IntoResponse {
     if def.id.trim().is_empty() || def.binary_path.trim().is_empty() {
         return (
@@ -599,6 +930,20 @@ async fn create_service(
             .into_response();
     }
 
+    if let Err(e) = validate_supervision(&def) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": false,
+                "error": e.to_string()
+            })),
+        )
+            .into_response();
+    }
+
+    def.restart_limit = def.restart_limit.or(Some(3));
+    def.desired_state = DesiredState::Running;
+
     let service_root = format!("{}/services/{}", node.config.data_dir, def.id);
     let bin_dir = format!("{}/bin", service_root);
     let data_dir = format!("{}/data", service_root);
@@ -664,17 +1009,8 @@ async fn create_service(
     let mut env = def.env.clone();
     env.insert("PORT".to_string(), port.to_string());
 
-    let service = Service::new(
-        def.id.clone(),
-        def.name.clone(),
-        def.binary_path.clone(),
-        def.args.clone(),
-        env,
-        def.auto_restart,
-        def.restart_limit.or(Some(3)),
-        def.linux_capabilities.clone(),
-        service_root.clone(),
-    );
+    let log_buffer = Service::create_log_buffer(&def, &service_root, &node.config.log_rotation);
+    let service = Service::new(&def, env, service_root.clone(), log_buffer);
 
     {
         let mut manager = node.manager.write().await;
@@ -710,30 +1046,30 @@ async fn create_service(
 }
 
 async fn delete_service(State(node): State<Node>, Path(id): Path<String>) -> impl IntoResponse {
-    {
+    let run_as = {
         let registry = node.registry.read().await;
-        if !registry.list_definitions().iter().any(|s| s.id == id) {
-            return (
-                StatusCode::NOT_FOUND,
-                Json(json!({
-                    "status": false,
-                    "error": "service not found"
-                })),
-            )
-                .into_response();
+        match registry.get(&id) {
+            Some(def) => def.run_as.clone(),
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "status": false,
+                        "error": "service not found"
+                    })),
+                )
+                    .into_response();
+            }
         }
-    }
+    };
 
     {
         let mut port_manager = node.port_manager.write().await;
         let _ = port_manager.deallocate(&id);
     }
 
-    {
-        let mut manager = node.manager.write().await;
-        let _ = manager.stop(&id).await;
-        let _ = manager.unregister_service(&id);
-    }
+    SupervisorManager::stop(&node.manager, &id).await;
+    let _ = node.manager.write().await.unregister_service(&id);
 
     {
         let mut registry = node.registry.write().await;
@@ -764,6 +1100,10 @@ async fn delete_service(State(node): State<Node>, Path(id): Path<String>) -> imp
     let service_root = format!("{}/services/{}", node.config.data_dir, id);
     let _ = std::fs::remove_dir_all(&service_root);
 
+    if matches!(run_as, Some(RunAs::Dynamic)) {
+        credentials::remove_dynamic_user(&id);
+    }
+
     (
         StatusCode::OK,
         Json(json!({
@@ -774,6 +1114,24 @@ async fn delete_service(State(node): State<Node>, Path(id): Path<String>) -> imp
         .into_response()
 }
 
+/// Checks that `value`, the artifact's `what`, names a single entry in the
+/// service's directory, so it cannot reach outside of it.
+fn check_path_component(what: &str, value: &str) -> anyhow::Result<()> {
+    if value.is_empty() || value == "." || value == ".." || value.contains(['/', '\0']) {
+        anyhow::bail!("invalid {} '{}'", what, value);
+    }
+    Ok(())
+}
+
+/// Name of the binary in an upload. Clients may send a path, of which only
+/// the last part counts.
+fn upload_file_name(name: &str) -> String {
+    std::path::Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
 pub async fn upload_artifact(
     State(node): State<Node>,
     Path(id): Path<String>,
@@ -853,9 +1211,19 @@ pub async fn upload_artifact(
     }
 
     let version = version.unwrap();
-    let file_name = file_name.unwrap();
+    let file_name = upload_file_name(&file_name.unwrap());
     let file_bytes = file_bytes.unwrap();
 
+    if let Err(e) = check_path_component("version", &version)
+        .and_then(|_| check_path_component("file name", &file_name))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"status": false, "error": e.to_string()})),
+        )
+            .into_response();
+    }
+
     let service_root = format!("{}/services/{}", node.config.data_dir, id);
     let version_dir = format!("{}/versions/{}", service_root, version);
     let bin_dir = format!("{}/bin", service_root);
@@ -889,7 +1257,12 @@ pub async fn upload_artifact(
         registry
             .get(&id)
             .filter(|def| def.ready && !def.binary_path.is_empty())
-            .and_then(|def| def.binary_path.split('/').last().map(|s| s.to_string()))
+            .and_then(|def| {
+                def.binary_path
+                    .split('/')
+                    .next_back()
+                    .map(|s| s.to_string())
+            })
     };
 
     let final_binary_name = if let Some(existing_name) = existing_binary_name {
@@ -967,17 +1340,8 @@ pub async fn upload_artifact(
             }
             drop(port_manager);
 
-            let service = Service::new(
-                def.id.clone(),
-                def.name.clone(),
-                def.binary_path.clone(),
-                def.args.clone(),
-                env,
-                def.auto_restart,
-                def.restart_limit,
-                def.linux_capabilities.clone(),
-                service_root,
-            );
+            let log_buffer = log_buffer_for(&node, def, &service_root).await;
+            let service = Service::new(def, env, service_root, log_buffer);
             drop(registry);
 
             Some((service, service_exists))
@@ -988,20 +1352,27 @@ pub async fn upload_artifact(
     };
 
     if let Some((service, exists)) = service_to_register {
-        let mut manager = node.manager.write().await;
-        if exists {
-            let _ = manager.update_service(service);
-        } else {
-            let _ = manager.register_service(service);
+        {
+            let mut manager = node.manager.write().await;
+            if exists {
+                let _ = manager.update_service(service);
+            } else {
+                let _ = manager.register_service(service);
+            }
         }
 
-        match tokio::time::timeout(tokio::time::Duration::from_secs(30), manager.restart(&id)).await
-        {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => tracing::error!("restart failed: {}", e),
-            Err(_) => tracing::error!("restart timed out after 30s"),
+        // Waiting for the old version is bounded by its stop_timeout.
+        let restarted = match SupervisorManager::restart(&node.manager, &id).await {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::error!("restart failed: {}", e);
+                false
+            }
+        };
+
+        if restarted {
+            set_desired_state(&node, &id, DesiredState::Running).await;
         }
-        drop(manager);
     }
 
     (
@@ -1034,6 +1405,16 @@ pub async fn install_github_artifact(
         }
     }
 
+    if let Err(e) = check_path_component("version", &payload.version)
+        .and_then(|_| check_path_component("asset", &payload.asset))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"status": false, "error": e.to_string()})),
+        )
+            .into_response();
+    }
+
     let url = format!(
         "https://api.github.com/repos/{}/releases/tags/{}",
         payload.repo, payload.version
@@ -1163,7 +1544,12 @@ pub async fn install_github_artifact(
         registry
             .get(&id)
             .filter(|def| def.ready && !def.binary_path.is_empty())
-            .and_then(|def| def.binary_path.split('/').last().map(|s| s.to_string()))
+            .and_then(|def| {
+                def.binary_path
+                    .split('/')
+                    .next_back()
+                    .map(|s| s.to_string())
+            })
     };
 
     let final_binary_name = if let Some(existing_name) = existing_binary_name {
@@ -1244,17 +1630,8 @@ pub async fn install_github_artifact(
             }
             drop(port_manager);
 
-            let service = Service::new(
-                def.id.clone(),
-                def.name.clone(),
-                def.binary_path.clone(),
-                def.args.clone(),
-                env,
-                def.auto_restart,
-                def.restart_limit,
-                def.linux_capabilities.clone(),
-                service_root,
-            );
+            let log_buffer = log_buffer_for(&node, def, &service_root).await;
+            let service = Service::new(def, env, service_root, log_buffer);
             drop(registry);
 
             Some((service, service_exists))
@@ -1265,20 +1642,27 @@ pub async fn install_github_artifact(
     };
 
     if let Some((service, exists)) = service_to_register {
-        let mut manager = node.manager.write().await;
-        if exists {
-            let _ = manager.update_service(service);
-        } else {
-            let _ = manager.register_service(service);
+        {
+            let mut manager = node.manager.write().await;
+            if exists {
+                let _ = manager.update_service(service);
+            } else {
+                let _ = manager.register_service(service);
+            }
         }
 
-        match tokio::time::timeout(tokio::time::Duration::from_secs(30), manager.restart(&id)).await
-        {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => tracing::error!("restart failed: {}", e),
-            Err(_) => tracing::error!("restart timed out after 30s"),
+        // Waiting for the old version is bounded by its stop_timeout.
+        let restarted = match SupervisorManager::restart(&node.manager, &id).await {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::error!("restart failed: {}", e);
+                false
+            }
+        };
+
+        if restarted {
+            set_desired_state(&node, &id, DesiredState::Running).await;
         }
-        drop(manager);
     }
 
     (
@@ -1299,10 +1683,10 @@ pub async fn get_artifact_info(
 
     if let Ok(entries) = std::fs::read_dir(&versions_dir) {
         for entry in entries.flatten() {
-            if entry.path().is_dir() {
-                if let Some(name) = entry.file_name().to_str() {
-                    versions.push(name.to_string());
-                }
+            if entry.path().is_dir()
+                && let Some(name) = entry.file_name().to_str()
+            {
+                versions.push(name.to_string());
             }
         }
     }
@@ -1362,59 +1746,54 @@ pub async fn get_service_config(
 
     let mut fields = Vec::new();
     if has_template {
-        if let Ok(template_content) = fs::read_to_string(&template_path) {
-            if let Ok(template_toml) = toml::from_str(&template_content) {
-                let current_config = if has_config {
-                    fs::read_to_string(&config_path)
-                        .ok()
-                        .and_then(|c| toml::from_str(&c).ok())
-                } else {
-                    None
-                };
+        if let Ok(template_content) = fs::read_to_string(&template_path)
+            && let Ok(template_toml) = toml::from_str(&template_content)
+        {
+            let current_config = if has_config {
+                fs::read_to_string(&config_path)
+                    .ok()
+                    .and_then(|c| toml::from_str(&c).ok())
+            } else {
+                None
+            };
 
-                fields = extract_config_fields(&template_toml, current_config.as_ref());
-            }
+            fields = extract_config_fields(&template_toml, current_config.as_ref());
         }
-    } else if has_config {
-        if let Ok(config_content) = fs::read_to_string(&config_path) {
-            if let Ok(config_toml) = toml::from_str(&config_content) {
-                fn flatten(
-                    prefix: Option<String>,
-                    value: &toml::Value,
-                    out: &mut Vec<ConfigField>,
-                ) {
-                    if let Some(table) = value.as_table() {
-                        for (k, v) in table {
-                            let key = match &prefix {
-                                Some(p) => format!("{}.{}", p, k),
-                                None => k.clone(),
-                            };
-                            match v {
-                                toml::Value::Table(_) => flatten(Some(key), v, out),
-                                _ => {
-                                    let field_type = match v {
-                                        toml::Value::String(_) => "string",
-                                        toml::Value::Integer(_) => "integer",
-                                        toml::Value::Float(_) => "float",
-                                        toml::Value::Boolean(_) => "boolean",
-                                        _ => "string",
-                                    }
-                                    .to_string();
-                                    out.push(ConfigField {
-                                        key,
-                                        value: v.to_string().trim_matches('"').to_string(),
-                                        field_type,
-                                        description: String::new(),
-                                    });
-                                }
+    } else if has_config
+        && let Ok(config_content) = fs::read_to_string(&config_path)
+        && let Ok(config_toml) = toml::from_str(&config_content)
+    {
+        fn flatten(prefix: Option<String>, value: &toml::Value, out: &mut Vec<ConfigField>) {
+            if let Some(table) = value.as_table() {
+                for (k, v) in table {
+                    let key = match &prefix {
+                        Some(p) => format!("{}.{}", p, k),
+                        None => k.clone(),
+                    };
+                    match v {
+                        toml::Value::Table(_) => flatten(Some(key), v, out),
+                        _ => {
+                            let field_type = match v {
+                                toml::Value::String(_) => "string",
+                                toml::Value::Integer(_) => "integer",
+                                toml::Value::Float(_) => "float",
+                                toml::Value::Boolean(_) => "boolean",
+                                _ => "string",
                             }
+                            .to_string();
+                            out.push(ConfigField {
+                                key,
+                                value: v.to_string().trim_matches('"').to_string(),
+                                field_type,
+                                description: String::new(),
+                            });
                         }
                     }
                 }
-
-                flatten(None, &config_toml, &mut fields);
             }
         }
+
+        flatten(None, &config_toml, &mut fields);
     }
 
     Json(json!(ServiceConfig {
@@ -1443,7 +1822,7 @@ fn extract_config_fields(
                     _ => "string",
                 }
                 .to_string();
-                let current_value = get_value_by_path(current, &key)
+                let current_value = get_value_by_path(current, key)
                     .map(|v| v.to_string().trim_matches('"').to_string())
                     .unwrap_or_else(|| value.to_string().trim_matches('"').to_string());
                 fields.push(ConfigField {
@@ -1789,7 +2168,102 @@ pub async fn get_port_allocations(State(node): State<Node>) -> impl IntoResponse
     }))
 }
 
-pub async fn get_logs(State(node): State<Node>, Path(id): Path<String>) -> impl IntoResponse {
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    /// Number of entries to return, the newest that match.
+    #[serde(default)]
+    tail: Option<usize>,
+    /// RFC 3339 time, or a duration before now such as `15m`.
+    #[serde(default)]
+    since: Option<String>,
+    #[serde(default)]
+    until: Option<String>,
+    /// Comma-separated levels.
+    #[serde(default)]
+    level: Option<String>,
+    /// Text the message has to contain.
+    #[serde(default)]
+    q: Option<String>,
+    /// Treat `q` as a regular expression.
+    #[serde(default)]
+    regex: bool,
+    /// Target of parsed lines, including the targets nested in it.
+    #[serde(default)]
+    target: Option<String>,
+    /// `next_cursor` of the previous page, to get older entries.
+    #[serde(default)]
+    cursor: Option<u64>,
+}
+
+/// Query parameters of the form `field.<name>=<value>` filter on the fields
+/// of parsed lines.
+const FIELD_PARAM_PREFIX: &str = "field.";
+
+fn build_log_query(params: LogsQuery, raw: Vec<(String, String)>) -> anyhow::Result<LogQuery> {
+    let tail = params.tail.unwrap_or(log_query::DEFAULT_TAIL);
+    if !(1..=log_query::MAX_TAIL).contains(&tail) {
+        anyhow::bail!("tail: must be between 1 and {}", log_query::MAX_TAIL);
+    }
+
+    let now = chrono::Utc::now();
+    let since = params
+        .since
+        .as_deref()
+        .map(|v| log_query::parse_time(v, now))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("since: {}", e))?;
+    let until = params
+        .until
+        .as_deref()
+        .map(|v| log_query::parse_time(v, now))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("until: {}", e))?;
+
+    let levels = params
+        .level
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|level| level.trim().to_ascii_lowercase())
+        .filter(|level| !level.is_empty())
+        .collect();
+
+    let pattern = match params.q.filter(|q| !q.is_empty()) {
+        Some(q) if params.regex => Some(Pattern::Regex(
+            regex::Regex::new(&q).map_err(|e| anyhow::anyhow!("q: {}", e))?,
+        )),
+        Some(q) => Some(Pattern::Substring(q)),
+        None => None,
+    };
+
+    let fields = raw
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(FIELD_PARAM_PREFIX)?;
+            Some((name.to_string(), value))
+        })
+        .collect();
+
+    Ok(LogQuery {
+        tail,
+        since,
+        until,
+        levels,
+        pattern,
+        target: params.target.filter(|t| !t.is_empty()),
+        fields,
+        before: params.cursor,
+        after: None,
+    })
+}
+
+/// Returns the newest `tail` entries that match the filters, oldest first.
+/// `next_cursor` is set when older entries match too.
+pub async fn get_logs(
+    State(node): State<Node>,
+    Path(id): Path<String>,
+    Query(params): Query<LogsQuery>,
+    Query(raw): Query<Vec<(String, String)>>,
+) -> impl IntoResponse {
     {
         let registry = node.registry.read().await;
         if !registry.list_definitions().iter().any(|s| s.id == id) {
@@ -1804,27 +2278,70 @@ pub async fn get_logs(State(node): State<Node>, Path(id): Path<String>) -> impl
         }
     }
 
-    let manager = node.manager.read().await;
+    let query = match build_log_query(params, raw) {
+        Ok(query) => query,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "status": false,
+                    "error": e.to_string()
+                })),
+            )
+                .into_response();
+        }
+    };
 
-    if let Some(service) = manager.list().await.iter().find(|s| s.id == id) {
-        let logs = service.log_buffer.get_all().await;
-        Json(json!({
-            "service": id,
-            "logs": logs
-        }))
-        .into_response()
+    let service = {
+        let manager = node.manager.read().await;
+        manager.list_cloned().await.into_iter().find(|s| s.id == id)
+    };
+
+    if let Some(service) = service {
+        match service.log_buffer.query(query).await {
+            Ok(page) => Json(json!({
+                "service": id,
+                "logs": page.entries,
+                "next_cursor": page.next_cursor,
+                "dropped_lines": service.log_buffer.dropped_lines()
+            }))
+            .into_response(),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "status": false,
+                    "error": format!("Failed to read logs: {}", e)
+                })),
+            )
+                .into_response(),
+        }
     } else {
         Json(json!({
             "service": id,
-            "logs": []
+            "logs": [],
+            "next_cursor": null
         }))
         .into_response()
     }
 }
 
+#[derive(Deserialize)]
+pub struct StreamLogsQuery {
+    /// Resume after this sequence number, for clients that cannot set the
+    /// `Last-Event-ID` header on their first request.
+    #[serde(default)]
+    last_event_id: Option<u64>,
+}
+
+/// Streams log entries as server-sent events with their sequence number as
+/// the event id. A client that reconnects with `Last-Event-ID` gets the
+/// entries it missed first; without it, the recently buffered ones. A client
+/// that falls behind gets a `missed` event with the number of entries skipped.
 pub async fn stream_logs(
     State(node): State<Node>,
     Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<StreamLogsQuery>,
 ) -> Result<
     Sse<impl futures::Stream<Item = Result<axum::response::sse::Event, Infallible>>>,
     (StatusCode, Json<serde_json::Value>),
@@ -1842,25 +2359,51 @@ pub async fn stream_logs(
         }
     }
 
-    let service_opt = {
-        let manager = node.manager.read().await;
-        let services = manager.list_cloned().await;
-        services.into_iter().find(|s| s.id == id)
-    };
+    // A service keeps its log buffer when it is redeployed, so the stream
+    // carries on with the new version's output, numbered after the old one's.
+    let log_buffer = node
+        .manager
+        .read()
+        .await
+        .get_service(&id)
+        .map(|service| service.log_buffer.clone());
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .or(query.last_event_id);
 
     let stream = async_stream::stream! {
-        if let Some(service) = service_opt {
-            let mut last_count = 0;
-            loop {
-                let logs = service.log_buffer.get_recent().await;
+        if let Some(log_buffer) = log_buffer {
+            // Subscribe before reading the backlog so nothing falls in
+            // between; entries seen in both are skipped by sequence number.
+            let mut live = log_buffer.subscribe();
+            let backlog = match last_event_id {
+                Some(seq) => log_buffer.get_since(seq).await,
+                None => log_buffer.get_recent().await,
+            };
 
-                for log in logs.iter().skip(last_count) {
-                    let data = serde_json::to_string(&log).unwrap_or_default();
-                    yield Ok::<_, Infallible>(axum::response::sse::Event::default().data(data));
-                }
+            let mut last_seq = last_event_id.unwrap_or(0);
+            for entry in backlog {
+                last_seq = entry.seq;
+                yield Ok::<_, Infallible>(log_event(&entry));
+            }
 
-                last_count = logs.len();
-                tokio::time::sleep(Duration::from_millis(500)).await;
+            loop {
+                match live.recv().await {
+                    Ok(entry) if entry.seq <= last_seq => {}
+                    Ok(entry) => {
+                        last_seq = entry.seq;
+                        yield Ok(log_event(&entry));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        yield Ok(axum::response::sse::Event::default()
+                            .event("missed")
+                            .data(json!({ "missed": missed }).to_string()));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
             }
         } else {
             loop {
@@ -1876,6 +2419,12 @@ pub async fn stream_logs(
     ))
 }
 
+fn log_event(entry: &LogEntry) -> axum::response::sse::Event {
+    axum::response::sse::Event::default()
+        .id(entry.seq.to_string())
+        .data(serde_json::to_string(entry).unwrap_or_default())
+}
+
 pub async fn clear_logs(State(node): State<Node>, Path(id): Path<String>) -> impl IntoResponse {
     {
         let registry = node.registry.read().await;
@@ -1927,6 +2476,8 @@ pub struct ServiceStats {
     pub cpu_usage: f32,
     pub memory_mb: f64,
     pub pid: Option<u32>,
+    /// Limits and usage of the service's cgroup, when it has one.
+    pub cgroup: Option<CgroupStats>,
 }
 
 pub async fn get_service_stats(
@@ -1961,12 +2512,15 @@ pub async fn get_service_stats(
                     cpu_usage: 0.0,
                     memory_mb: 0.0,
                     pid: None,
+                    cgroup: None,
                 })
                 .into_response();
             }
         }
     };
 
+    let cgroup = Cgroup::for_service(&service_id).stats();
+
     if let Some(pid) = pid {
         let mut sys = System::new_all();
         sys.refresh_all();
@@ -1977,6 +2531,7 @@ pub async fn get_service_stats(
                 cpu_usage: process.cpu_usage(),
                 memory_mb: process.memory() as f64 / 1024.0 / 1024.0,
                 pid: Some(pid),
+                cgroup,
             };
 
             return Json(stats).into_response();
@@ -1988,6 +2543,79 @@ pub async fn get_service_stats(
         cpu_usage: 0.0,
         memory_mb: 0.0,
         pid,
+        cgroup,
     })
     .into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_components_cannot_leave_the_service_directory() {
+        for valid in ["1.2.3", "v2", "app", "app-linux-arm64.tar.gz", "..hidden"] {
+            assert!(check_path_component("version", valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["", ".", "..", "../other", "a/b", "/abs", "nul\0"] {
+            assert!(
+                check_path_component("version", invalid).is_err(),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    fn principal(role: Role) -> Principal {
+        Principal {
+            name: "ci".to_string(),
+            role,
+            services: None,
+        }
+    }
+
+    #[test]
+    fn secrets_are_masked_for_non_admins() {
+        let env = HashMap::from([("API_KEY".to_string(), "s3cret".to_string())]);
+        let args = vec!["--token".to_string(), "s3cret".to_string()];
+        let health_check: HealthCheck = serde_json::from_value(json!({
+            "type": "exec",
+            "command": ["curl", "-H", "Authorization: s3cret", "localhost"],
+        }))
+        .unwrap();
+
+        for role in [Role::Viewer, Role::Deployer] {
+            let principal = principal(role);
+            let visible = json!({
+                "env": visible_env(&env, &principal),
+                "args": visible_args(&args, &principal),
+                "health_check": visible_health_check(Some(&health_check), &principal),
+            });
+            assert!(!visible.to_string().contains("s3cret"));
+            assert_eq!(visible["env"]["API_KEY"], MASKED_VALUE);
+            assert_eq!(visible["args"].as_array().unwrap().len(), 2);
+            assert_eq!(visible["health_check"]["command"][0], "curl");
+            assert_eq!(visible["health_check"]["command"][3], MASKED_VALUE);
+        }
+
+        let admin = principal(Role::Admin);
+        assert_eq!(visible_env(&env, &admin), env);
+        assert_eq!(visible_args(&args, &admin), args);
+        assert_eq!(
+            json!(visible_health_check(Some(&health_check), &admin)),
+            json!(health_check)
+        );
+        assert!(visible_health_check(None, &admin).is_none());
+    }
+
+    #[test]
+    fn upload_file_names_drop_their_directories() {
+        assert_eq!(upload_file_name("app"), "app");
+        assert_eq!(upload_file_name("build/release/app"), "app");
+        assert_eq!(upload_file_name("../../other/bin/app"), "app");
+        assert_eq!(upload_file_name("/etc/passwd"), "passwd");
+        assert_eq!(upload_file_name(".."), "");
+        assert_eq!(upload_file_name("dir/.."), "");
+        assert_eq!(upload_file_name(""), "");
+    }
+}
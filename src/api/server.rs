@@ -2,20 +2,30 @@ use anyhow::Context;
 use axum::{
     Json, Router,
     extract::DefaultBodyLimit,
-    http::Uri,
-    response::{IntoResponse, Response},
+    http::{HeaderMap, Uri},
+    middleware,
+    response::{IntoResponse, Redirect, Response},
 };
 
 use reqwest::{StatusCode, header};
 
+use futures::{FutureExt, future::BoxFuture};
 use rust_embed::Embed;
 use serde_json::json;
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::watch;
+use tokio_rustls::rustls::ServerConfig;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing::info;
 
 use crate::{
-    api::routes::{health, registry, services},
+    api::{
+        audit::record_audit,
+        auth::require_auth,
+        routes::{audit, auth, health, registry, services},
+        tls::{self, TlsListener},
+    },
+    config::ListenerConfig,
     platform::node::Node,
 };
 
@@ -24,10 +34,22 @@ use crate::{
 struct PortalAssets;
 
 pub async fn start_api(node: &Node) -> anyhow::Result<()> {
-    let api_routes = Router::new()
+    // The audit layer wraps authentication so rejected calls are recorded too.
+    let service_routes = services::routes()
+        .route_layer(middleware::from_fn_with_state(node.clone(), require_auth))
+        .route_layer(middleware::from_fn_with_state(node.clone(), record_audit));
+
+    let protected_routes = Router::new()
         .merge(health::routes())
-        .merge(services::routes())
         .merge(registry::routes())
+        .merge(audit::routes())
+        .merge(auth::routes())
+        .route_layer(middleware::from_fn_with_state(node.clone(), require_auth));
+
+    let api_routes = Router::new()
+        .merge(service_routes)
+        .merge(protected_routes)
+        .merge(auth::public_routes())
         .with_state(node.clone());
 
     let app = Router::new()
@@ -37,30 +59,179 @@ pub async fn start_api(node: &Node) -> anyhow::Result<()> {
         .layer(TraceLayer::new_for_http())
         .layer(DefaultBodyLimit::max(1024 * 1024 * 200));
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], node.config.listen_port));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
 
-    let listener = tokio::net::TcpListener::bind(addr).await.with_context(|| {
-        format!(
-            "failed to bind TCP listener on 0.0.0.0:{}",
-            node.config.listen_port
-        )
-    })?;
+    let tls_server_config = match &node.config.tls {
+        Some(tls_config) => Some(tls::load_server_config(&node.config, tls_config)?),
+        None => None,
+    };
 
-    info!(
-        "listening on {}",
-        listener
-            .local_addr()
-            .context("failed to read local listener address")?
-    );
+    let mut servers: Vec<BoxFuture<'static, anyhow::Result<()>>> = Vec::new();
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .context("HTTP server crashed unexpectedly")?;
+    for listener in node.config.listeners() {
+        match listener {
+            ListenerConfig::Tcp { address, port } => {
+                let addr = SocketAddr::new(address, port.unwrap_or(node.config.listen_port));
+                servers.push(
+                    serve_tcp(
+                        addr,
+                        app.clone(),
+                        tls_server_config.clone(),
+                        shutdown_rx.clone(),
+                    )
+                    .await?,
+                );
+
+                if tls_server_config.is_some()
+                    && let Some(redirect_port) = node.config.tls_redirect_port
+                {
+                    let redirect_addr = SocketAddr::new(address, redirect_port);
+                    servers.push(
+                        serve_https_redirect(redirect_addr, addr.port(), shutdown_rx.clone())
+                            .await?,
+                    );
+                }
+            }
+            ListenerConfig::Unix { path, mode } => {
+                servers.push(serve_unix(path, mode, app.clone(), shutdown_rx.clone()).await?);
+            }
+        }
+    }
+
+    futures::future::try_join_all(servers).await?;
 
     Ok(())
 }
 
+async fn serve_tcp(
+    addr: SocketAddr,
+    app: Router,
+    tls_server_config: Option<Arc<ServerConfig>>,
+    shutdown_rx: watch::Receiver<bool>,
+) -> anyhow::Result<BoxFuture<'static, anyhow::Result<()>>> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind TCP listener on {}", addr))?;
+
+    let Some(server_config) = tls_server_config else {
+        info!("listening on http://{}", addr);
+
+        return Ok(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(wait_for_shutdown(shutdown_rx))
+                .await
+                .with_context(|| format!("HTTP server on {} crashed unexpectedly", addr))
+        }
+        .boxed());
+    };
+
+    let listener =
+        TlsListener::new(listener, server_config).context("failed to start TLS listener")?;
+
+    info!("listening on https://{}", addr);
+
+    Ok(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(wait_for_shutdown(shutdown_rx))
+            .await
+            .with_context(|| format!("HTTPS server on {} crashed unexpectedly", addr))
+    }
+    .boxed())
+}
+
+async fn serve_unix(
+    path: String,
+    mode: u32,
+    app: Router,
+    shutdown_rx: watch::Receiver<bool>,
+) -> anyhow::Result<BoxFuture<'static, anyhow::Result<()>>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(parent) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create socket directory for {}", path))?;
+    }
+
+    // A socket file left behind by an unclean exit would make bind fail.
+    match std::fs::remove_file(&path) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to remove stale socket {}", path));
+        }
+    }
+
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind Unix socket at {}", path))?;
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("failed to set permissions on {}", path))?;
+
+    info!("listening on unix:{} (mode {:o})", path, mode);
+
+    Ok(async move {
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(wait_for_shutdown(shutdown_rx))
+            .await
+            .with_context(|| format!("HTTP server on unix:{} crashed unexpectedly", path));
+
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+    .boxed())
+}
+
+/// Answers every plain HTTP request on `addr` with a permanent redirect to the
+/// same path on the HTTPS listener.
+async fn serve_https_redirect(
+    addr: SocketAddr,
+    https_port: u16,
+    shutdown_rx: watch::Receiver<bool>,
+) -> anyhow::Result<BoxFuture<'static, anyhow::Result<()>>> {
+    let app = Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        redirect_to_https(&headers, &uri, https_port)
+    });
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind TLS redirect listener on {}", addr))?;
+
+    info!("redirecting http://{} to HTTPS", addr);
+
+    Ok(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(wait_for_shutdown(shutdown_rx))
+            .await
+            .with_context(|| format!("TLS redirect server on {} crashed unexpectedly", addr))
+    }
+    .boxed())
+}
+
+fn redirect_to_https(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Redirect {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+
+    // Drop the port from `host:port` and `[v6]:port`.
+    let host = match host.strip_prefix('[') {
+        Some(rest) => format!("[{}]", rest.split(']').next().unwrap_or_default()),
+        None => host.split(':').next().unwrap_or_default().to_string(),
+    };
+
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    if https_port == 443 {
+        Redirect::permanent(&format!("https://{}{}", host, path))
+    } else {
+        Redirect::permanent(&format!("https://{}:{}{}", host, https_port, path))
+    }
+}
+
 async fn api_fallback(uri: Uri) -> Response {
     (
         StatusCode::NOT_FOUND,
@@ -74,18 +245,18 @@ async fn api_fallback(uri: Uri) -> Response {
 async fn serve_console(uri: Uri) -> Response {
     let path = uri.path().trim_start_matches('/');
 
-    if !path.is_empty() {
-        if let Some(content) = PortalAssets::get(path) {
-            let mime = mime_guess::from_path(path)
-                .first_or_octet_stream()
-                .to_string();
-            return (
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, mime)],
-                content.data.to_vec(),
-            )
-                .into_response();
-        }
+    if !path.is_empty()
+        && let Some(content) = PortalAssets::get(path)
+    {
+        let mime = mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string();
+        return (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, mime)],
+            content.data.to_vec(),
+        )
+            .into_response();
     }
 
     if !path.is_empty() && !path.contains('.') {
@@ -128,6 +299,10 @@ async fn serve_console(uri: Uri) -> Response {
         .into_response()
 }
 
+async fn wait_for_shutdown(mut shutdown_rx: watch::Receiver<bool>) {
+    let _ = shutdown_rx.wait_for(|stopped| *stopped).await;
+}
+
 async fn shutdown_signal() {
     if let Err(e) = tokio::signal::ctrl_c().await {
         tracing::error!(error = ?e, "failed to listen for Ctrl+C");
@@ -135,3 +310,72 @@ async fn shutdown_signal() {
         tracing::info!("shutdown signal received");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(headers: &[(&'static str, &str)], uri: &str, https_port: u16) -> String {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, value.parse().unwrap());
+        }
+        let response = redirect_to_https(&map, &uri.parse().unwrap(), https_port).into_response();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn redirects_to_the_https_port_on_the_same_host() {
+        assert_eq!(
+            location(&[("host", "example.com:8080")], "/api/services?x=1", 8443),
+            "https://example.com:8443/api/services?x=1"
+        );
+        assert_eq!(
+            location(&[("host", "[::1]:8080")], "/", 443),
+            "https://[::1]/"
+        );
+        assert_eq!(location(&[], "/login", 443), "https://localhost/login");
+    }
+
+    #[tokio::test]
+    async fn unix_listener_replaces_a_stale_socket_and_cleans_up() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = std::env::temp_dir().join(format!("dockless-unix-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("api.sock").to_string_lossy().to_string();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "stale").unwrap();
+
+        let app = Router::new().fallback(|| async { "ok" });
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(
+            serve_unix(path.clone(), 0o600, app, shutdown_rx)
+                .await
+                .unwrap(),
+        );
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("ok"));
+
+        shutdown_tx.send(true).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
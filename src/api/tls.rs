@@ -0,0 +1,217 @@
+use std::{fs, io, net::SocketAddr, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{Duration, timeout},
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+    server::TlsStream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth::write_private_file,
+    config::{Config, TlsConfig},
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the rustls server config from the configured certificate, or from a
+/// self-signed certificate under `<data_dir>/tls` when none is configured.
+pub fn load_server_config(config: &Config, tls: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let (cert_path, key_path) = match (&tls.cert_path, &tls.key_path) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+        (None, None) => ensure_self_signed(&config.data_dir)?,
+        _ => anyhow::bail!("tls.cert_path and tls.key_path must be set together"),
+    };
+
+    let certs = CertificateDer::pem_file_iter(&cert_path)
+        .with_context(|| format!("failed to open TLS certificate at {}", cert_path))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse TLS certificate at {}", cert_path))?;
+
+    let key = PrivateKeyDer::from_pem_file(&key_path)
+        .with_context(|| format!("failed to parse TLS private key at {}", key_path))?;
+
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+fn ensure_self_signed(data_dir: &str) -> Result<(String, String)> {
+    let tls_dir = format!("{}/tls", data_dir);
+    let cert_path = format!("{}/cert.pem", tls_dir);
+    let key_path = format!("{}/key.pem", tls_dir);
+
+    if Path::new(&cert_path).exists() && Path::new(&key_path).exists() {
+        return Ok((cert_path, key_path));
+    }
+
+    fs::create_dir_all(&tls_dir).context("failed to create TLS directory")?;
+
+    let mut names = vec!["localhost".to_string()];
+    if let Ok(hostname) = fs::read_to_string("/etc/hostname") {
+        let hostname = hostname.trim();
+        if !hostname.is_empty() {
+            names.push(hostname.to_string());
+            names.push(format!("{}.local", hostname));
+        }
+    }
+
+    let certified = rcgen::generate_simple_self_signed(names.clone())
+        .context("failed to generate self-signed certificate")?;
+
+    fs::write(&cert_path, certified.cert.pem())
+        .with_context(|| format!("failed to write TLS certificate at {}", cert_path))?;
+    // A key without its certificate is replaced, and the new one is created
+    // owner-only from the start.
+    match fs::remove_file(&key_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to remove stale key at {}", key_path));
+        }
+        _ => {}
+    }
+    write_private_file(&key_path, certified.signing_key.serialize_pem().as_bytes())
+        .with_context(|| format!("failed to write TLS private key at {}", key_path))?;
+
+    info!(
+        "generated self-signed TLS certificate for {:?} at {}",
+        names, cert_path
+    );
+
+    Ok((cert_path, key_path))
+}
+
+/// A listener that yields TLS streams. Handshakes run on their own tasks so a
+/// slow client cannot stall the accept loop.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, server_config: Arc<ServerConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(server_config);
+        let (tx, rx) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("failed to accept TCP connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+
+                if tx.is_closed() {
+                    break;
+                }
+
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls_stream)) => {
+                            let _ = tx.send((tls_stream, addr)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            incoming: rx,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(conn) => conn,
+            // The accept task only exits once this listener is dropped.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn config(name: &str) -> Config {
+        let dir =
+            std::env::temp_dir().join(format!("dockless-tls-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        toml::from_str(&format!(
+            "listen_port = 8443\ndata_dir = {:?}\nnode_id = \"node_id\"\n",
+            dir.to_string_lossy()
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn generates_a_self_signed_certificate_once() {
+        let config = config("self-signed");
+        let tls = TlsConfig::default();
+        load_server_config(&config, &tls).unwrap();
+
+        let (cert_path, key_path) = ensure_self_signed(&config.data_dir).unwrap();
+        let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let cert = fs::read_to_string(&cert_path).unwrap();
+        load_server_config(&config, &tls).unwrap();
+        assert_eq!(fs::read_to_string(&cert_path).unwrap(), cert);
+    }
+
+    #[test]
+    fn replaces_a_key_left_without_its_certificate() {
+        let config = config("stale-key");
+        let (cert_path, key_path) = ensure_self_signed(&config.data_dir).unwrap();
+        let key = fs::read_to_string(&key_path).unwrap();
+
+        fs::remove_file(&cert_path).unwrap();
+        ensure_self_signed(&config.data_dir).unwrap();
+        assert_ne!(fs::read_to_string(&key_path).unwrap(), key);
+        load_server_config(&config, &TlsConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn requires_both_certificate_and_key() {
+        let config = config("partial");
+        let tls = TlsConfig {
+            cert_path: Some("/nonexistent/cert.pem".to_string()),
+            key_path: None,
+        };
+        let err = load_server_config(&config, &tls).unwrap_err();
+        assert!(err.to_string().contains("must be set together"));
+        assert!(!Path::new(&config.data_dir).exists());
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Request keys whose values never reach the audit log. Object values under
+/// these keys keep their keys so the log still shows *what* was changed.
+const REDACTED_KEYS: &[&str] = &["env", "config", "fields", "token"];
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub actor: String,
+    pub service_id: Option<String>,
+    pub action: String,
+    pub request: Value,
+    pub outcome: AuditOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditOutcome {
+    pub status: u16,
+    pub success: bool,
+    pub message: Option<String>,
+}
+
+#[derive(Default)]
+pub struct AuditFilter {
+    pub service_id: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+/// Append-only JSONL log of mutating API calls.
+pub struct AuditLog {
+    path: String,
+    write_lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, record: &AuditRecord) -> Result<()> {
+        let line = serde_json::to_string(record).context("failed to serialize audit record")?;
+
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open audit log at {}", self.path))?;
+
+        writeln!(file, "{}", line)
+            .with_context(|| format!("failed to write audit log at {}", self.path))?;
+
+        Ok(())
+    }
+
+    /// Returns matching records in chronological order. When `limit` is set,
+    /// only the most recent `limit` matches are returned.
+    pub fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditRecord>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to open audit log at {}", self.path));
+            }
+        };
+
+        let mut records = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("failed to read audit log {}", self.path))?;
+
+            let Ok(record) = serde_json::from_str::<AuditRecord>(&line) else {
+                continue;
+            };
+
+            if filter.matches(&record) {
+                records.push(record);
+            }
+        }
+
+        if let Some(limit) = filter.limit
+            && records.len() > limit
+        {
+            records.drain(..records.len() - limit);
+        }
+
+        Ok(records)
+    }
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        if let Some(service_id) = &self.service_id
+            && record.service_id.as_ref() != Some(service_id)
+        {
+            return false;
+        }
+
+        if let Some(actor) = &self.actor
+            && &record.actor != actor
+        {
+            return false;
+        }
+
+        if self.since.is_some() || self.until.is_some() {
+            let Ok(timestamp) = DateTime::parse_from_rfc3339(&record.timestamp) else {
+                return false;
+            };
+            let timestamp = timestamp.with_timezone(&Utc);
+
+            if self.since.is_some_and(|since| timestamp < since) {
+                return false;
+            }
+
+            if self.until.is_some_and(|until| timestamp > until) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Replaces secrets in a request body with a placeholder.
+pub fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let redacted = if REDACTED_KEYS.contains(&k.as_str()) {
+                        redact_all(v)
+                    } else {
+                        redact(v)
+                    };
+                    (k.clone(), redacted)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        other => other.clone(),
+    }
+}
+
+fn redact_all(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), redact_all(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact_all).collect()),
+        _ => Value::String(REDACTED.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(timestamp: &str, actor: &str, service_id: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            timestamp: timestamp.to_string(),
+            actor: actor.to_string(),
+            service_id: service_id.map(str::to_string),
+            action: "POST /api/services/{id}/start".to_string(),
+            request: Value::Null,
+            outcome: AuditOutcome {
+                status: 200,
+                success: true,
+                message: None,
+            },
+        }
+    }
+
+    #[test]
+    fn redact_hides_secret_values_but_keeps_their_keys() {
+        let request = json!({
+            "id": "web",
+            "env": { "DATABASE_URL": "postgres://secret", "PORT": 8080 },
+            "token": "abc",
+            "nested": [{ "config": { "db": { "password": "hunter2" } } }],
+            "args": ["--verbose"],
+        });
+
+        assert_eq!(
+            redact(&request),
+            json!({
+                "id": "web",
+                "env": { "DATABASE_URL": REDACTED, "PORT": REDACTED },
+                "token": REDACTED,
+                "nested": [{ "config": { "db": { "password": REDACTED } } }],
+                "args": ["--verbose"],
+            })
+        );
+    }
+
+    #[test]
+    fn filter_matches_service_actor_and_time_range() {
+        let record = record("2026-01-01T12:00:00Z", "ci", Some("web"));
+        let time = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        assert!(AuditFilter::default().matches(&record));
+        assert!(
+            AuditFilter {
+                service_id: Some("web".to_string()),
+                actor: Some("ci".to_string()),
+                since: Some(time("2026-01-01T00:00:00Z")),
+                until: Some(time("2026-01-01T12:00:00Z")),
+                ..Default::default()
+            }
+            .matches(&record)
+        );
+        assert!(
+            !AuditFilter {
+                service_id: Some("api".to_string()),
+                ..Default::default()
+            }
+            .matches(&record)
+        );
+        assert!(
+            !AuditFilter {
+                actor: Some("admin".to_string()),
+                ..Default::default()
+            }
+            .matches(&record)
+        );
+        assert!(
+            !AuditFilter {
+                since: Some(time("2026-01-01T12:00:01Z")),
+                ..Default::default()
+            }
+            .matches(&record)
+        );
+    }
+}
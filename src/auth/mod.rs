@@ -0,0 +1,436 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Name reported for requests authenticated with the bootstrap token.
+pub const BOOTSTRAP_TOKEN_NAME: &str = "admin";
+
+/// Lifetime of a portal session cookie.
+pub const SESSION_TTL_SECS: u64 = 60 * 60 * 12;
+
+/// Hex digits of the token hash a session is bound to.
+const SESSION_BINDING_LEN: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Serialize, Deserialize)]
+struct TokensFile {
+    version: u32,
+    tokens: Vec<ApiToken>,
+}
+
+/// What a token is allowed to do. Roles are ordered: every role includes the
+/// permissions of the roles before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read-only access to health, service status and logs.
+    Viewer,
+    /// Viewer plus artifact uploads and GitHub installs.
+    Deployer,
+    /// Full access, including token management.
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Deployer => write!(f, "deployer"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Tokens created before roles existed had full access.
+fn default_role() -> Role {
+    Role::Admin
+}
+
+/// A named API token. Only the SHA-256 hash of the secret is persisted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    pub token_hash: String,
+    pub created_at: String,
+
+    #[serde(default = "default_role")]
+    pub role: Role,
+
+    /// Service ids this token may act on. `None` means every service.
+    #[serde(default)]
+    pub services: Option<Vec<String>>,
+}
+
+/// The identity attached to an authenticated API request.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+    pub services: Option<Vec<String>>,
+}
+
+impl Principal {
+    fn bootstrap() -> Self {
+        Self {
+            name: BOOTSTRAP_TOKEN_NAME.to_string(),
+            role: Role::Admin,
+            services: None,
+        }
+    }
+
+    fn from_token(token: &ApiToken) -> Self {
+        Self {
+            name: token.name.clone(),
+            role: token.role,
+            services: token.services.clone(),
+        }
+    }
+
+    pub fn can_access_service(&self, id: &str) -> bool {
+        match &self.services {
+            Some(allowed) => allowed.iter().any(|s| s == id),
+            None => true,
+        }
+    }
+}
+
+pub struct AuthManager {
+    path: String,
+    tokens: Vec<ApiToken>,
+    bootstrap_hash: String,
+    session_key: Vec<u8>,
+}
+
+impl AuthManager {
+    pub fn load_or_init(path: &str, bootstrap_path: &str) -> Result<Self> {
+        let bootstrap = load_or_create_bootstrap_token(bootstrap_path)?;
+
+        if !Path::new(path).exists() {
+            let empty = TokensFile {
+                version: 1,
+                tokens: vec![],
+            };
+
+            let json = serde_json::to_string_pretty(&empty)
+                .context("failed to serialize initial tokens file")?;
+
+            write_private_file(path, json.as_bytes())
+                .with_context(|| format!("failed to create tokens file at {}", path))?;
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read tokens file at {}", path))?;
+
+        let file: TokensFile = serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse tokens JSON at {}", path))?;
+
+        if file.version != 1 {
+            anyhow::bail!("unsupported tokens file version {}", file.version);
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            tokens: file.tokens,
+            bootstrap_hash: hash_token(&bootstrap),
+            session_key: bootstrap.into_bytes(),
+        })
+    }
+
+    /// Resolves a bearer token to the name of the token that owns it.
+    pub fn authenticate(&self, token: &str) -> Option<Principal> {
+        let hash = hash_token(token);
+
+        if hash == self.bootstrap_hash {
+            return Some(Principal::bootstrap());
+        }
+
+        self.tokens
+            .iter()
+            .find(|t| t.token_hash == hash)
+            .map(Principal::from_token)
+    }
+
+    pub fn list(&self) -> &[ApiToken] {
+        &self.tokens
+    }
+
+    /// Creates a named token and returns its secret. The secret is not stored
+    /// and cannot be recovered afterwards.
+    pub fn create(
+        &mut self,
+        name: &str,
+        role: Role,
+        services: Option<Vec<String>>,
+    ) -> Result<String> {
+        validate_token_name(name)?;
+
+        if name == BOOTSTRAP_TOKEN_NAME {
+            anyhow::bail!("token name '{}' is reserved", name);
+        }
+
+        if self.tokens.iter().any(|t| t.name == name) {
+            anyhow::bail!("token {} already exists", name);
+        }
+
+        let secret = generate_token();
+
+        self.tokens.push(ApiToken {
+            name: name.to_string(),
+            token_hash: hash_token(&secret),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            role,
+            services,
+        });
+        self.save()?;
+
+        Ok(secret)
+    }
+
+    pub fn revoke(&mut self, name: &str) -> Result<()> {
+        let original_len = self.tokens.len();
+        self.tokens.retain(|t| t.name != name);
+
+        if self.tokens.len() == original_len {
+            anyhow::bail!("token {} not found", name);
+        }
+
+        self.save()
+    }
+
+    /// Issues a signed session value of the form
+    /// `<name>.<token>.<expires>.<signature>`, where `<token>` is the start of
+    /// the token's hash. It ties the session to this token, and not to one
+    /// created later under the same name.
+    pub fn issue_session(&self, principal: &Principal) -> String {
+        let expires = unix_now() + SESSION_TTL_SECS;
+        let binding = self.session_binding(&principal.name).unwrap_or_default();
+        let payload = format!("{}.{}.{}", principal.name, binding, expires);
+        format!("{}.{}", payload, self.sign(&payload))
+    }
+
+    /// Verifies a session value produced by [`issue_session`](Self::issue_session).
+    /// Sessions of revoked tokens are rejected, and the role and service scope
+    /// are always taken from the current token record.
+    pub fn verify_session(&self, value: &str) -> Option<Principal> {
+        let (payload, signature) = value.rsplit_once('.')?;
+        let mut parts = payload.splitn(3, '.');
+        let (name, binding, expires) = (parts.next()?, parts.next()?, parts.next()?);
+
+        let signature = decode_hex(signature)?;
+        let mut mac = HmacSha256::new_from_slice(&self.session_key).ok()?;
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+
+        if expires.parse::<u64>().ok()? < unix_now() {
+            return None;
+        }
+
+        if self.session_binding(name) != Some(binding) {
+            return None;
+        }
+
+        if name == BOOTSTRAP_TOKEN_NAME {
+            return Some(Principal::bootstrap());
+        }
+
+        self.tokens
+            .iter()
+            .find(|t| t.name == name)
+            .map(Principal::from_token)
+    }
+
+    /// The part of the hash of the token named `name` that its sessions
+    /// carry, if the token exists.
+    fn session_binding(&self, name: &str) -> Option<&str> {
+        let hash = if name == BOOTSTRAP_TOKEN_NAME {
+            &self.bootstrap_hash
+        } else {
+            &self.tokens.iter().find(|t| t.name == name)?.token_hash
+        };
+        hash.get(..SESSION_BINDING_LEN)
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.session_key).expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = TokensFile {
+            version: 1,
+            tokens: self.tokens.clone(),
+        };
+
+        let json = serde_json::to_string_pretty(&file).context("failed to serialize tokens")?;
+
+        let tmp_path = format!("{}.tmp", self.path);
+
+        // Left behind by an interrupted save.
+        match fs::remove_file(&tmp_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e)
+                    .with_context(|| format!("failed to remove stale temp file {}", tmp_path));
+            }
+            _ => {}
+        }
+        write_private_file(&tmp_path, json.as_bytes())
+            .with_context(|| format!("failed to write temp tokens file {}", tmp_path))?;
+
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to replace tokens file {}", self.path))?;
+
+        Ok(())
+    }
+}
+
+fn load_or_create_bootstrap_token(path: &str) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let token = content.trim().to_string();
+            if token.is_empty() {
+                anyhow::bail!("admin token file at {} is empty", path);
+            }
+            Ok(token)
+        }
+
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let token = generate_token();
+
+            write_private_file(path, token.as_bytes())
+                .with_context(|| format!("failed to write admin token file at {}", path))?;
+
+            tracing::info!("generated admin bootstrap token at {}", path);
+            Ok(token)
+        }
+
+        Err(err) => {
+            Err(err).with_context(|| format!("unexpected error accessing admin token at {}", path))
+        }
+    }
+}
+
+/// Creates `path`, which must not exist yet, readable and writable only by
+/// its owner, and writes `contents` to it. The file is created with those
+/// permissions, so it is never readable by others, not even briefly.
+pub fn write_private_file(path: &str, contents: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn validate_token_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64 {
+        anyhow::bail!("token name must be between 1 and 64 characters");
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("token name may only contain letters, digits, '-' and '_'");
+    }
+
+    Ok(())
+}
+
+fn generate_token() -> String {
+    format!("dl_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(name: &str) -> AuthManager {
+        let dir =
+            std::env::temp_dir().join(format!("dockless-auth-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        AuthManager::load_or_init(
+            &dir.join("tokens.json").to_string_lossy(),
+            &dir.join("admin_token").to_string_lossy(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sessions_resolve_to_their_token() {
+        let mut auth = manager("sessions");
+        let secret = auth.create("ci", Role::Deployer, None).unwrap();
+        let principal = auth.authenticate(&secret).unwrap();
+
+        let session = auth.issue_session(&principal);
+        let verified = auth.verify_session(&session).unwrap();
+        assert_eq!(verified.name, "ci");
+        assert_eq!(verified.role, Role::Deployer);
+
+        let bootstrap = auth.issue_session(&Principal::bootstrap());
+        assert_eq!(auth.verify_session(&bootstrap).unwrap().role, Role::Admin);
+    }
+
+    #[test]
+    fn sessions_do_not_carry_over_to_a_recreated_token() {
+        let mut auth = manager("recreated");
+        let secret = auth.create("ci", Role::Admin, None).unwrap();
+        let session = auth.issue_session(&auth.authenticate(&secret).unwrap());
+
+        auth.revoke("ci").unwrap();
+        assert!(auth.verify_session(&session).is_none());
+
+        auth.create("ci", Role::Admin, None).unwrap();
+        assert!(auth.verify_session(&session).is_none());
+    }
+
+    #[test]
+    fn tampered_sessions_are_rejected() {
+        let mut auth = manager("tampered");
+        auth.create("viewer", Role::Viewer, None).unwrap();
+        let secret = auth.create("ci", Role::Viewer, None).unwrap();
+        let session = auth.issue_session(&auth.authenticate(&secret).unwrap());
+
+        let (payload, signature) = session.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", payload.replacen("ci", "viewer", 1), signature);
+        assert!(auth.verify_session(&forged).is_none());
+        assert!(auth.verify_session(payload).is_none());
+        assert!(auth.verify_session("").is_none());
+    }
+}
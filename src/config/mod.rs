@@ -1,3 +1,5 @@
+use std::net::{IpAddr, Ipv4Addr};
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use tracing::info;
@@ -7,6 +9,119 @@ pub struct Config {
     pub listen_port: u16,
     pub data_dir: String,
     pub node_id: String,
+
+    /// Serve the API over HTTPS on `listen_port` when set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Plain HTTP port that redirects every request to the HTTPS listener.
+    /// Only used when `tls` is set.
+    #[serde(default)]
+    pub tls_redirect_port: Option<u16>,
+
+    /// Addresses the API binds to. Defaults to `0.0.0.0:<listen_port>`.
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+
+    /// Rotation of every service's `logs/service.log`. Services can override
+    /// it with their own `log_rotation`.
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ListenerConfig {
+    /// A TCP socket on a specific IPv4 or IPv6 address. Served over HTTPS
+    /// when `tls` is set.
+    Tcp {
+        address: IpAddr,
+        /// Defaults to `listen_port`.
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    /// A Unix domain socket. Always plain HTTP; access is controlled by the
+    /// socket file's permissions.
+    Unix {
+        path: String,
+        /// File mode applied to the socket after binding, e.g. `0o660`.
+        #[serde(default = "default_socket_mode")]
+        mode: u32,
+    },
+}
+
+fn default_socket_mode() -> u32 {
+    0o660
+}
+
+impl Config {
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        if self.listeners.is_empty() {
+            return vec![ListenerConfig::Tcp {
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                port: None,
+            }];
+        }
+
+        self.listeners.clone()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain. When both paths are omitted a self-signed
+    /// certificate is generated under `<data_dir>/tls` on first boot.
+    #[serde(default)]
+    pub cert_path: Option<String>,
+
+    /// PEM private key matching `cert_path`.
+    #[serde(default)]
+    pub key_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogRotation {
+    /// Rotate once the file would grow past this size, e.g. `"10M"`.
+    pub max_size: String,
+
+    /// Rotate once the file is this old. 0 disables age-based rotation.
+    pub max_age_hours: u64,
+
+    /// Number of rotated files to keep. Older ones are deleted.
+    pub keep: u32,
+
+    pub compression: LogCompression,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_size: "10M".to_string(),
+            max_age_hours: 24,
+            keep: 5,
+            compression: LogCompression::Gzip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl LogCompression {
+    /// File name extension of rotated files, including the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            LogCompression::None => "",
+            LogCompression::Gzip => ".gz",
+            LogCompression::Zstd => ".zst",
+        }
+    }
 }
 
 pub fn load_config() -> Result<Config> {
@@ -30,5 +145,67 @@ fn default_config() -> Config {
             .ok()
             .and_then(|p| p.parse().ok())
             .unwrap_or(8000),
+        tls: None,
+        tls_redirect_port: None,
+        listeners: vec![],
+        log_rotation: LogRotation::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "listen_port = 8000\ndata_dir = \"data\"\nnode_id = \"node_id\"\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn listens_on_all_ipv4_addresses_by_default() {
+        let listeners = parse("").listeners();
+        assert!(matches!(
+            listeners.as_slice(),
+            [ListenerConfig::Tcp { address, port: None }] if *address == IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        ));
+    }
+
+    #[test]
+    fn parses_tcp_and_unix_listeners() {
+        let config = parse(
+            r#"
+            [[listeners]]
+            type = "tcp"
+            address = "::1"
+            port = 9000
+
+            [[listeners]]
+            type = "unix"
+            path = "/run/dockless/api.sock"
+            "#,
+        );
+
+        let listeners = config.listeners();
+        assert_eq!(listeners.len(), 2);
+        assert!(matches!(
+            &listeners[0],
+            ListenerConfig::Tcp { address, port: Some(9000) } if address.to_string() == "::1"
+        ));
+        assert!(matches!(
+            &listeners[1],
+            ListenerConfig::Unix { path, mode: 0o660 } if path == "/run/dockless/api.sock"
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_listener_types() {
+        let result: Result<Config, _> = toml::from_str(
+            "listen_port = 8000\ndata_dir = \"data\"\nnode_id = \"node_id\"\n\
+             [[listeners]]\ntype = \"udp\"\naddress = \"127.0.0.1\"\n",
+        );
+        assert!(result.is_err());
     }
 }
@@ -1,9 +1,12 @@
+use std::time::Duration;
 use tracing::{error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::platform::node::Node;
 
 mod api;
+mod audit;
+mod auth;
 mod config;
 mod identity;
 mod platform;
@@ -49,14 +52,20 @@ async fn run() -> anyhow::Result<()> {
     api::server::start_api(&node).await?;
     info!("dockless shutting down");
 
+    // Services get their whole stop timeout, and flushing their logs
+    // afterwards some more time.
+    let timeout = node.manager.read().await.shutdown_timeout() + Duration::from_secs(10);
     let shutdown_future = async {
         node.manager.write().await.shutdown_all().await;
     };
 
-    match tokio::time::timeout(tokio::time::Duration::from_secs(60), shutdown_future).await {
+    match tokio::time::timeout(timeout, shutdown_future).await {
         Ok(_) => info!("all services stopped gracefully"),
         Err(_) => {
-            error!("shutdown timed out after 60s, forcing exit");
+            error!(
+                "shutdown timed out after {}s, forcing exit",
+                timeout.as_secs()
+            );
         }
     }
 
@@ -1,4 +1,6 @@
 use crate::{
+    audit::AuditLog,
+    auth::AuthManager,
     config::{Config, load_config},
     identity,
     platform::port_manager::PortManager,
@@ -6,7 +8,7 @@ use crate::{
     runtime::{service::Service, supervisor_manager::SupervisorManager},
 };
 use anyhow::{Context, Result};
-use std::{fs, sync::Arc};
+use std::{fs, path::Path, sync::Arc};
 use tokio::sync::RwLock;
 
 #[derive(Clone)]
@@ -16,6 +18,8 @@ pub struct Node {
     pub registry: Arc<RwLock<RegistryManager>>,
     pub manager: Arc<RwLock<SupervisorManager>>,
     pub port_manager: Arc<RwLock<PortManager>>,
+    pub auth: Arc<RwLock<AuthManager>>,
+    pub audit: Arc<AuditLog>,
 }
 
 impl Node {
@@ -27,6 +31,13 @@ impl Node {
         let registry_path = format!("{}/projects.json", config.data_dir);
         let registry = RegistryManager::load_or_init(&registry_path)?;
 
+        let tokens_path = format!("{}/tokens.json", config.data_dir);
+        let bootstrap_path = Path::new(&config.node_id).with_file_name("admin_token");
+        let auth = AuthManager::load_or_init(&tokens_path, &bootstrap_path.to_string_lossy())?;
+
+        let audit_path = format!("{}/audit.jsonl", config.data_dir);
+        let audit = AuditLog::new(&audit_path);
+
         let ports_path = format!("{}/ports.json", config.data_dir);
         let mut port_manager = PortManager::load_or_init(&ports_path)?;
 
@@ -56,17 +67,8 @@ impl Node {
             let port = port_manager.allocate(&def.id)?;
             env.insert("PORT".to_string(), port.to_string());
 
-            let service = Service::new(
-                def.id.clone(),
-                def.name.clone(),
-                def.binary_path.clone(),
-                def.args.clone(),
-                env,
-                def.auto_restart,
-                def.restart_limit,
-                def.linux_capabilities.clone(),
-                service_root,
-            );
+            let log_buffer = Service::create_log_buffer(def, &service_root, &config.log_rotation);
+            let service = Service::new(def, env, service_root, log_buffer);
 
             manager.register_service(service)?;
         }
@@ -74,12 +76,14 @@ impl Node {
         let port_manager_arc = Arc::new(RwLock::new(port_manager));
         manager.set_port_manager(Arc::clone(&port_manager_arc));
 
-        return Ok(Self {
+        Ok(Self {
             node_id,
             config,
             registry: Arc::new(RwLock::new(registry)),
             manager: Arc::new(RwLock::new(manager)),
             port_manager: port_manager_arc,
-        });
+            auth: Arc::new(RwLock::new(auth)),
+            audit: Arc::new(audit),
+        })
     }
 }
@@ -115,10 +115,9 @@ impl PortManager {
                     let s = target.to_string_lossy();
                     if let Some(inner) =
                         s.strip_prefix("socket:[").and_then(|s| s.strip_suffix("]"))
+                        && let Ok(inode) = inner.parse::<u64>()
                     {
-                        if let Ok(inode) = inner.parse::<u64>() {
-                            socket_inodes.insert(inode);
-                        }
+                        socket_inodes.insert(inode);
                     }
                 }
             }
@@ -140,16 +139,15 @@ impl PortManager {
                     if fields[3] != "0A" {
                         continue;
                     }
-                    if let Ok(inode) = fields[9].parse::<u64>() {
-                        if socket_inodes.contains(&inode) {
-                            // local_address format: hex_addr:hex_port
-                            if let Some(port_hex) = fields[1].split(':').nth(1) {
-                                if let Ok(port) = u16::from_str_radix(port_hex, 16) {
-                                    if !ports.contains(&port) {
-                                        ports.push(port);
-                                    }
-                                }
-                            }
+                    if let Ok(inode) = fields[9].parse::<u64>()
+                        && socket_inodes.contains(&inode)
+                    {
+                        // local_address format: hex_addr:hex_port
+                        if let Some(port_hex) = fields[1].split(':').nth(1)
+                            && let Ok(port) = u16::from_str_radix(port_hex, 16)
+                            && !ports.contains(&port)
+                        {
+                            ports.push(port);
                         }
                     }
                 }
@@ -1,8 +1,10 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fs, path::Path, time::Duration};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::config::{LogCompression, LogRotation};
+
 #[derive(Serialize, Deserialize)]
 struct RegistryFile {
     version: u32,
@@ -26,20 +28,87 @@ pub struct ServiceDefinition {
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    /// Legacy switch, used only when `restart_policy` is unset: `true` maps
+    /// to `unless-stopped` and `false` to `never`.
     #[serde(default = "default_auto_restart")]
     pub auto_restart: bool,
 
+    #[serde(default)]
+    pub restart_policy: Option<RestartPolicy>,
+
+    /// Whether the service should be running, as last requested through the
+    /// API. Only services that should be running are started at boot, unless
+    /// their restart policy is `always`.
+    #[serde(default)]
+    pub desired_state: DesiredState,
+
     #[serde(default)]
     pub restart_limit: Option<u32>,
 
     #[serde(default)]
     pub current_version: Option<String>,
 
-    /// Linux capabilities to grant to the binary via `setcap` before starting,
-    /// e.g. `["cap_net_raw+eip", "cap_net_admin+eip"]`.
+    /// Linux capabilities granted to the process as ambient capabilities,
+    /// e.g. `["cap_net_raw", "cap_net_admin"]`.
     #[serde(default)]
     pub linux_capabilities: Vec<String>,
 
+    #[serde(default)]
+    pub restart_backoff: RestartBackoff,
+
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+
+    #[serde(default)]
+    pub readiness: Readiness,
+
+    /// Restart the service when it stops sending `WATCHDOG=1` over its
+    /// notify socket for this long, like systemd's `WatchdogSec`.
+    #[serde(default)]
+    pub watchdog_secs: Option<u64>,
+
+    /// Signal sent to ask the service to stop, e.g. `SIGTERM` or `SIGINT`.
+    #[serde(default = "default_stop_signal")]
+    pub stop_signal: String,
+
+    /// Time to wait after `stop_signal` before sending `SIGKILL`.
+    #[serde(default = "default_stop_timeout_secs")]
+    pub stop_timeout_secs: u64,
+
+    #[serde(default)]
+    pub kill_mode: KillMode,
+
+    /// What to do with a process of this service that outlived a previous
+    /// dockless instance.
+    #[serde(default)]
+    pub orphan_policy: OrphanPolicy,
+
+    /// cgroup v2 limits. `None` leaves the service unconstrained.
+    #[serde(default)]
+    pub resources: Option<Resources>,
+
+    /// rlimits, scheduling priority and CPU affinity of the process.
+    #[serde(default)]
+    pub process: Option<ProcessSettings>,
+
+    /// Unix user the process runs as. `None` runs it as the user dockless
+    /// runs as.
+    #[serde(default)]
+    pub run_as: Option<RunAs>,
+
+    /// Filesystem and privilege restrictions. `None` leaves the service
+    /// unrestricted.
+    #[serde(default)]
+    pub sandbox: Option<Sandbox>,
+
+    /// Overrides of the node's `log_rotation` defaults for this service.
+    #[serde(default)]
+    pub log_rotation: Option<LogRotationOverride>,
+
+    /// How the service's output lines are parsed into log entries.
+    #[serde(default)]
+    pub log_format: LogFormat,
+
     #[serde(skip)]
     pub port: Option<u16>,
 }
@@ -48,6 +117,743 @@ fn default_auto_restart() -> bool {
     true
 }
 
+pub fn default_stop_signal() -> String {
+    "SIGTERM".to_string()
+}
+
+pub fn default_stop_timeout_secs() -> u64 {
+    10
+}
+
+/// Signals accepted for `stop_signal`.
+const STOP_SIGNALS: &[(&str, i32)] = &[
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGINT", libc::SIGINT),
+    ("SIGQUIT", libc::SIGQUIT),
+    ("SIGHUP", libc::SIGHUP),
+    ("SIGUSR1", libc::SIGUSR1),
+    ("SIGUSR2", libc::SIGUSR2),
+    ("SIGWINCH", libc::SIGWINCH),
+    ("SIGKILL", libc::SIGKILL),
+];
+
+/// Resolves a signal name such as `SIGTERM` or `term` to its number.
+pub fn parse_signal(name: &str) -> Result<i32> {
+    let upper = name.trim().to_ascii_uppercase();
+    let full = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{}", upper)
+    };
+
+    STOP_SIGNALS
+        .iter()
+        .find(|(n, _)| *n == full)
+        .map(|(_, signal)| *signal)
+        .ok_or_else(|| {
+            let known: Vec<&str> = STOP_SIGNALS.iter().map(|(n, _)| *n).collect();
+            anyhow::anyhow!(
+                "unknown stop_signal '{}', expected one of {}",
+                name,
+                known.join(", ")
+            )
+        })
+}
+
+/// Which processes receive the stop signal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KillMode {
+    /// The whole process group of the service, including helpers it forked.
+    #[default]
+    Group,
+    /// Only the main process. Leftover processes in the group are killed
+    /// once the main process has exited.
+    Main,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanPolicy {
+    /// Stop the surviving process and start a fresh one.
+    #[default]
+    Terminate,
+    /// Keep the surviving process running and supervise it, unless its
+    /// stdout or stderr is a pipe the previous instance read.
+    Adopt,
+}
+
+impl ServiceDefinition {
+    pub fn effective_restart_policy(&self) -> RestartPolicy {
+        match self.restart_policy {
+            Some(policy) => policy,
+            // Restarts like `always`, but without overriding an explicit
+            // stop at boot, which only an explicit `always` does.
+            None if self.auto_restart => RestartPolicy::UnlessStopped,
+            None => RestartPolicy::Never,
+        }
+    }
+
+    /// Whether the service is started at boot: if it should be running, or
+    /// whatever its desired state if its restart policy is `always`.
+    pub fn starts_on_boot(&self) -> bool {
+        self.desired_state == DesiredState::Running
+            || self.effective_restart_policy() == RestartPolicy::Always
+    }
+
+    /// Rotation settings of this service's log file, given the node's
+    /// defaults.
+    pub fn effective_log_rotation(&self, defaults: &LogRotation) -> LogRotation {
+        match &self.log_rotation {
+            Some(overrides) => overrides.apply(defaults),
+            None => defaults.clone(),
+        }
+    }
+}
+
+/// When the supervisor starts a service again after its process exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Restart after every exit, including a clean one, and start at boot
+    /// even after being stopped through the API.
+    Always,
+    /// Restart only after a non-zero exit status or a signal.
+    OnFailure,
+    /// Never restart.
+    Never,
+    /// Like `always`, except that a service stopped through the API stays
+    /// stopped across reboots, see [`DesiredState`].
+    UnlessStopped,
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, clean_exit: bool) -> bool {
+        match self {
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+            RestartPolicy::OnFailure => !clean_exit,
+            RestartPolicy::Never => false,
+        }
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestartPolicy::Always => write!(f, "always"),
+            RestartPolicy::OnFailure => write!(f, "on-failure"),
+            RestartPolicy::Never => write!(f, "never"),
+            RestartPolicy::UnlessStopped => write!(f, "unless-stopped"),
+        }
+    }
+}
+
+/// Periodic probe run against a running service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub probe: HealthProbe,
+
+    #[serde(default = "default_health_interval_secs")]
+    pub interval_secs: u64,
+
+    #[serde(default = "default_health_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// Consecutive failures after which the service is reported unhealthy.
+    #[serde(default = "default_health_retries")]
+    pub retries: u32,
+
+    /// Grace period after start during which failed probes are not counted.
+    #[serde(default)]
+    pub start_period_secs: u64,
+
+    /// Restart the service after this many consecutive failures. Unset means
+    /// an unhealthy service is only reported, never restarted.
+    #[serde(default)]
+    pub restart_after_failures: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HealthProbe {
+    /// GET `path` on the service port and compare the response status.
+    Http {
+        #[serde(default = "default_health_path")]
+        path: String,
+        #[serde(default = "default_expected_status")]
+        expected_status: u16,
+        /// Defaults to the port allocated to the service.
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    /// Open a TCP connection to the service port.
+    Tcp {
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    /// Run a command in the service directory; exit code 0 means healthy.
+    Exec { command: Vec<String> },
+}
+
+/// Limits applied through the service's cgroup. Unset fields are not limited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Resources {
+    /// Hard memory limit, e.g. `"256M"`. The service is OOM-killed above it.
+    #[serde(default)]
+    pub memory_max: Option<String>,
+
+    /// Memory level above which the service is throttled and reclaimed.
+    #[serde(default)]
+    pub memory_high: Option<String>,
+
+    #[serde(default)]
+    pub cpu_max: Option<CpuMax>,
+
+    /// Maximum number of processes and threads.
+    #[serde(default)]
+    pub pids_max: Option<u64>,
+
+    /// Relative IO weight, 1 to 10000. The kernel default is 100.
+    #[serde(default)]
+    pub io_weight: Option<u16>,
+}
+
+/// The service may use `quota_us` of CPU time every `period_us`, so a quota of
+/// twice the period allows two full CPUs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuMax {
+    pub quota_us: u64,
+
+    #[serde(default = "default_cpu_period_us")]
+    pub period_us: u64,
+}
+
+fn default_cpu_period_us() -> u64 {
+    100_000
+}
+
+impl Resources {
+    pub fn validate(&self) -> Result<()> {
+        let memory_max = self
+            .memory_max
+            .as_deref()
+            .map(parse_size)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("resources.memory_max: {}", e))?;
+        let memory_high = self
+            .memory_high
+            .as_deref()
+            .map(parse_size)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("resources.memory_high: {}", e))?;
+
+        if memory_max == Some(0) || memory_high == Some(0) {
+            anyhow::bail!("resources memory limits must be greater than 0");
+        }
+
+        if let (Some(max), Some(high)) = (memory_max, memory_high)
+            && high > max
+        {
+            anyhow::bail!("resources.memory_high must not exceed resources.memory_max");
+        }
+
+        if let Some(cpu) = &self.cpu_max {
+            if !(1_000..=1_000_000).contains(&cpu.period_us) {
+                anyhow::bail!("resources.cpu_max.period_us must be between 1000 and 1000000");
+            }
+            if cpu.quota_us < 1_000 {
+                anyhow::bail!("resources.cpu_max.quota_us must be at least 1000");
+            }
+        }
+
+        if self.pids_max == Some(0) {
+            anyhow::bail!("resources.pids_max must be at least 1");
+        }
+
+        if let Some(weight) = self.io_weight
+            && !(1..=10_000).contains(&weight)
+        {
+            anyhow::bail!("resources.io_weight must be between 1 and 10000");
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-process settings applied between fork and exec. Unset fields are
+/// inherited from dockless.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessSettings {
+    /// Maximum number of open file descriptors.
+    #[serde(default)]
+    pub rlimit_nofile: Option<u64>,
+
+    /// Maximum core dump size, e.g. `"0"` to disable core dumps or
+    /// `"unlimited"`.
+    #[serde(default)]
+    pub rlimit_core: Option<String>,
+
+    /// Maximum virtual address space, e.g. `"2G"` or `"unlimited"`.
+    #[serde(default)]
+    pub rlimit_as: Option<String>,
+
+    /// Scheduling niceness, from -20 (highest priority) to 19 (lowest).
+    #[serde(default)]
+    pub nice: Option<i32>,
+
+    #[serde(default)]
+    pub ionice_class: Option<IoniceClass>,
+
+    /// Priority within the `realtime` and `best-effort` classes, from 0
+    /// (highest) to 7. Defaults to 4.
+    #[serde(default)]
+    pub ionice_level: Option<u8>,
+
+    /// CPUs the process may run on, e.g. `[3]` to pin it to the fourth core.
+    #[serde(default)]
+    pub cpu_affinity: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoniceClass {
+    Realtime,
+    BestEffort,
+    /// Only gets disk time when no other process needs it.
+    Idle,
+}
+
+impl ProcessSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.rlimit_nofile == Some(0) {
+            anyhow::bail!("process.rlimit_nofile must be at least 1");
+        }
+
+        if let Some(core) = &self.rlimit_core {
+            parse_rlimit(core).map_err(|e| anyhow::anyhow!("process.rlimit_core: {}", e))?;
+        }
+
+        if let Some(address_space) = &self.rlimit_as {
+            parse_rlimit(address_space).map_err(|e| anyhow::anyhow!("process.rlimit_as: {}", e))?;
+        }
+
+        if let Some(nice) = self.nice
+            && !(-20..=19).contains(&nice)
+        {
+            anyhow::bail!("process.nice must be between -20 and 19");
+        }
+
+        if let Some(level) = self.ionice_level {
+            if level > 7 {
+                anyhow::bail!("process.ionice_level must be between 0 and 7");
+            }
+            if self.ionice_class.is_none_or(|c| c == IoniceClass::Idle) {
+                anyhow::bail!(
+                    "process.ionice_level requires a realtime or best-effort ionice_class"
+                );
+            }
+        }
+
+        if let Some(cpus) = &self.cpu_affinity {
+            if cpus.is_empty() {
+                anyhow::bail!("process.cpu_affinity must list at least one CPU");
+            }
+
+            let available = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as usize;
+            if let Some(cpu) = cpus.iter().find(|c| **c >= libc::CPU_SETSIZE as usize) {
+                anyhow::bail!(
+                    "process.cpu_affinity: CPU {} is beyond the {} CPUs an affinity mask can hold",
+                    cpu,
+                    libc::CPU_SETSIZE
+                );
+            }
+            if let Some(cpu) = cpus.iter().find(|c| **c >= available) {
+                anyhow::bail!(
+                    "process.cpu_affinity: CPU {} does not exist, this machine has {} CPUs",
+                    cpu,
+                    available
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Identity a service process runs as.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RunAs {
+    /// An existing user. `group` defaults to the user's primary group.
+    User {
+        user: String,
+        #[serde(default)]
+        group: Option<String>,
+    },
+    /// A system user dockless creates for the service, named after its id.
+    Dynamic,
+}
+
+impl RunAs {
+    pub fn validate(&self) -> Result<()> {
+        if let RunAs::User { user, group } = self {
+            if user.trim().is_empty() {
+                anyhow::bail!("run_as.user must not be empty");
+            }
+            if group.as_ref().is_some_and(|g| g.trim().is_empty()) {
+                anyhow::bail!("run_as.group must not be empty");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Opt-in hardening without containers, similar to systemd's
+/// `ProtectSystem=strict`, `PrivateTmp` and `NoNewPrivileges`. Each
+/// protection is on unless turned off.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sandbox {
+    /// Make the whole filesystem read-only for the service, except its own
+    /// `data/` and `logs/` directories and `/dev`.
+    #[serde(default = "default_true")]
+    pub read_only_root: bool,
+
+    /// Give the service an empty `/tmp` of its own.
+    #[serde(default = "default_true")]
+    pub private_tmp: bool,
+
+    /// Keep the service and its children from gaining privileges through
+    /// setuid or file-capability binaries.
+    #[serde(default = "default_true")]
+    pub no_new_privileges: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// How lines a service writes to stdout and stderr become log entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Each line is the message, with the level taken from the stream:
+    /// `info` for stdout and `error` for stderr.
+    #[default]
+    Plain,
+    /// Each line is checked for JSON, then logfmt, and kept as plain text
+    /// if it is neither.
+    Auto,
+    /// Lines are JSON objects, e.g. from `tracing_subscriber`'s JSON format.
+    Json,
+    /// Lines are `key=value` pairs.
+    Logfmt,
+}
+
+/// Rotation settings of a service. Unset fields keep the node's defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogRotationOverride {
+    #[serde(default)]
+    pub max_size: Option<String>,
+
+    #[serde(default)]
+    pub max_age_hours: Option<u64>,
+
+    #[serde(default)]
+    pub keep: Option<u32>,
+
+    #[serde(default)]
+    pub compression: Option<LogCompression>,
+}
+
+impl LogRotationOverride {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(max_size) = &self.max_size {
+            let size = parse_size(max_size)
+                .map_err(|e| anyhow::anyhow!("log_rotation.max_size: {}", e))?;
+            if size == 0 {
+                anyhow::bail!("log_rotation.max_size must be greater than 0");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The node's `defaults` with these overrides applied.
+    pub fn apply(&self, defaults: &LogRotation) -> LogRotation {
+        LogRotation {
+            max_size: self
+                .max_size
+                .clone()
+                .unwrap_or_else(|| defaults.max_size.clone()),
+            max_age_hours: self.max_age_hours.unwrap_or(defaults.max_age_hours),
+            keep: self.keep.unwrap_or(defaults.keep),
+            compression: self.compression.unwrap_or(defaults.compression),
+        }
+    }
+}
+
+/// Parses an rlimit value: a byte size or `unlimited`.
+pub fn parse_rlimit(value: &str) -> Result<u64> {
+    if value.trim().eq_ignore_ascii_case("unlimited") {
+        return Ok(libc::RLIM_INFINITY);
+    }
+
+    parse_size(value)
+}
+
+/// Parses a byte size such as `1048576`, `512K`, `256M` or `1G`.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let multiplier: u64 = match c.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => anyhow::bail!("unknown size suffix in '{}'", value),
+            };
+            (&value[..i], multiplier)
+        }
+        _ => (value, 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| anyhow::anyhow!("invalid size '{}'", value))
+}
+
+impl HealthCheck {
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_secs == 0 {
+            anyhow::bail!("health_check.interval_secs must be at least 1");
+        }
+
+        if self.timeout_secs == 0 {
+            anyhow::bail!("health_check.timeout_secs must be at least 1");
+        }
+
+        if self.retries == 0 {
+            anyhow::bail!("health_check.retries must be at least 1");
+        }
+
+        if self.restart_after_failures == Some(0) {
+            anyhow::bail!("health_check.restart_after_failures must be at least 1");
+        }
+
+        match &self.probe {
+            HealthProbe::Http { path, .. } if !path.starts_with('/') => {
+                anyhow::bail!("health_check.path must start with '/'");
+            }
+            HealthProbe::Exec { command } if command.is_empty() => {
+                anyhow::bail!("health_check.command must not be empty");
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn default_health_interval_secs() -> u64 {
+    30
+}
+
+fn default_health_timeout_secs() -> u64 {
+    5
+}
+
+fn default_health_retries() -> u32 {
+    3
+}
+
+fn default_health_path() -> String {
+    "/".to_string()
+}
+
+fn default_expected_status() -> u16 {
+    200
+}
+
+/// How the supervisor decides that a freshly started process is up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Readiness {
+    #[serde(default)]
+    pub mode: ReadinessMode,
+
+    /// A process that is not ready within this time is stopped and the
+    /// service is marked `Failed`.
+    #[serde(default = "default_start_timeout_secs")]
+    pub start_timeout_secs: u64,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            mode: ReadinessMode::default(),
+            start_timeout_secs: default_start_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessMode {
+    /// Ready once the process listens on the port allocated to it.
+    #[default]
+    Port,
+    /// Ready once the health check passes for the first time.
+    Health,
+    /// Ready once the process sends `READY=1` over `NOTIFY_SOCKET`.
+    Notify,
+    /// Ready as soon as the process has been spawned.
+    None,
+}
+
+impl Readiness {
+    pub fn validate(&self, health_check: Option<&HealthCheck>) -> Result<()> {
+        if self.start_timeout_secs == 0 {
+            anyhow::bail!("readiness.start_timeout_secs must be at least 1");
+        }
+
+        if self.mode == ReadinessMode::Health && health_check.is_none() {
+            anyhow::bail!("readiness mode 'health' requires a health_check");
+        }
+
+        Ok(())
+    }
+}
+
+fn default_start_timeout_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DesiredState {
+    #[default]
+    Running,
+    Stopped,
+}
+
+/// Delay between automatic restarts. The delay starts at `initial_delay_ms`
+/// and is multiplied by `multiplier` after every exit that happens before the
+/// process has been up for `stable_after_secs`, up to `max_delay_ms`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestartBackoff {
+    #[serde(default = "default_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+
+    /// Fraction of the delay, between 0 and 1, added or subtracted at random
+    /// so that services sharing a dependency don't restart in lockstep.
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+
+    /// Uptime after which the process is considered healthy and the backoff
+    /// starts over from `initial_delay_ms`.
+    #[serde(default = "default_stable_after_secs")]
+    pub stable_after_secs: u64,
+
+    /// Number of consecutive short-lived runs after which the service is
+    /// reported as `CrashLoop`.
+    #[serde(default = "default_crash_loop_threshold")]
+    pub crash_loop_threshold: u32,
+}
+
+impl Default for RestartBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: default_initial_delay_ms(),
+            multiplier: default_multiplier(),
+            max_delay_ms: default_max_delay_ms(),
+            jitter: default_jitter(),
+            stable_after_secs: default_stable_after_secs(),
+            crash_loop_threshold: default_crash_loop_threshold(),
+        }
+    }
+}
+
+impl RestartBackoff {
+    /// Delay before restart number `attempt` (1-based) of the current streak
+    /// of short-lived runs.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        // Definitions saved before a zero delay was rejected may still have
+        // one.
+        let initial_delay_ms = self.initial_delay_ms.max(1);
+        let base = (initial_delay_ms as f64 * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_delay_ms as f64);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            1.0 + rand::random_range(-jitter..=jitter)
+        } else {
+            1.0
+        };
+
+        Duration::from_millis((base * factor).max(0.0) as u64)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.initial_delay_ms == 0 {
+            anyhow::bail!("restart_backoff.initial_delay_ms must be at least 1");
+        }
+
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            anyhow::bail!("restart_backoff.multiplier must be at least 1.0");
+        }
+
+        if !(0.0..=1.0).contains(&self.jitter) {
+            anyhow::bail!("restart_backoff.jitter must be between 0.0 and 1.0");
+        }
+
+        if self.max_delay_ms < self.initial_delay_ms {
+            anyhow::bail!("restart_backoff.max_delay_ms must not be below initial_delay_ms");
+        }
+
+        if self.crash_loop_threshold == 0 {
+            anyhow::bail!("restart_backoff.crash_loop_threshold must be at least 1");
+        }
+
+        Ok(())
+    }
+}
+
+fn default_initial_delay_ms() -> u64 {
+    1000
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+fn default_max_delay_ms() -> u64 {
+    5 * 60 * 1000
+}
+
+fn default_jitter() -> f64 {
+    0.1
+}
+
+fn default_stable_after_secs() -> u64 {
+    60
+}
+
+fn default_crash_loop_threshold() -> u32 {
+    5
+}
+
 pub struct RegistryManager {
     path: String,
     definitions: Vec<ServiceDefinition>,
@@ -147,3 +953,228 @@ impl RegistryManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(fields: serde_json::Value) -> ServiceDefinition {
+        let mut definition = serde_json::json!({ "id": "web", "name": "web" });
+        definition
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(definition).unwrap()
+    }
+
+    #[test]
+    fn default_and_legacy_definitions_stay_stopped_at_boot() {
+        for fields in [
+            serde_json::json!({}),
+            serde_json::json!({ "auto_restart": true }),
+        ] {
+            let mut def = definition(fields);
+            assert_eq!(def.effective_restart_policy(), RestartPolicy::UnlessStopped);
+            assert!(def.starts_on_boot());
+
+            def.desired_state = DesiredState::Stopped;
+            assert!(!def.starts_on_boot());
+        }
+
+        let def =
+            definition(serde_json::json!({ "auto_restart": false, "desired_state": "stopped" }));
+        assert_eq!(def.effective_restart_policy(), RestartPolicy::Never);
+        assert!(!def.starts_on_boot());
+    }
+
+    #[test]
+    fn only_an_explicit_always_overrides_a_stop_at_boot() {
+        let def = definition(
+            serde_json::json!({ "restart_policy": "always", "desired_state": "stopped" }),
+        );
+        assert!(def.starts_on_boot());
+
+        for policy in ["unless-stopped", "on-failure", "never"] {
+            let def = definition(
+                serde_json::json!({ "restart_policy": policy, "desired_state": "stopped" }),
+            );
+            assert!(!def.starts_on_boot(), "{}", policy);
+            let def = definition(serde_json::json!({ "restart_policy": policy }));
+            assert!(def.starts_on_boot(), "{}", policy);
+        }
+    }
+
+    #[test]
+    fn restart_policies_decide_on_the_exit_status() {
+        let cases = [
+            (RestartPolicy::Always, true, true),
+            (RestartPolicy::UnlessStopped, true, true),
+            (RestartPolicy::OnFailure, false, true),
+            (RestartPolicy::Never, false, false),
+        ];
+        for (policy, after_clean_exit, after_failure) in cases {
+            assert_eq!(policy.should_restart(true), after_clean_exit, "{}", policy);
+            assert_eq!(policy.should_restart(false), after_failure, "{}", policy);
+        }
+    }
+
+    #[test]
+    fn restart_policy_uses_kebab_case_names() {
+        for name in ["always", "on-failure", "never", "unless-stopped"] {
+            let policy: RestartPolicy = serde_json::from_value(serde_json::json!(name)).unwrap();
+            assert_eq!(policy.to_string(), name);
+            assert_eq!(
+                serde_json::to_value(policy).unwrap(),
+                serde_json::json!(name)
+            );
+        }
+        assert!(serde_json::from_value::<RestartPolicy>(serde_json::json!("on_failure")).is_err());
+    }
+
+    #[test]
+    fn restart_policy_takes_precedence_over_auto_restart() {
+        let def = definition(
+            serde_json::json!({ "auto_restart": false, "restart_policy": "on-failure" }),
+        );
+        assert_eq!(def.effective_restart_policy(), RestartPolicy::OnFailure);
+
+        let def =
+            definition(serde_json::json!({ "auto_restart": true, "restart_policy": "never" }));
+        assert_eq!(def.effective_restart_policy(), RestartPolicy::Never);
+    }
+
+    #[test]
+    fn health_checks_are_validated() {
+        let check =
+            |fields: serde_json::Value| -> HealthCheck { serde_json::from_value(fields).unwrap() };
+
+        let http = check(serde_json::json!({ "type": "http" }));
+        assert!(matches!(
+            &http.probe,
+            HealthProbe::Http { path, expected_status: 200, port: None } if path == "/"
+        ));
+        assert!(http.validate().is_ok());
+
+        for invalid in [
+            serde_json::json!({ "type": "http", "path": "healthz" }),
+            serde_json::json!({ "type": "exec", "command": [] }),
+            serde_json::json!({ "type": "tcp", "interval_secs": 0 }),
+            serde_json::json!({ "type": "tcp", "retries": 0 }),
+            serde_json::json!({ "type": "tcp", "restart_after_failures": 0 }),
+        ] {
+            assert!(check(invalid.clone()).validate().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn health_readiness_needs_a_health_check() {
+        let readiness: Readiness =
+            serde_json::from_value(serde_json::json!({ "mode": "health" })).unwrap();
+        assert!(readiness.validate(None).is_err());
+
+        let check: HealthCheck =
+            serde_json::from_value(serde_json::json!({ "type": "tcp" })).unwrap();
+        assert!(readiness.validate(Some(&check)).is_ok());
+
+        let readiness = Readiness {
+            start_timeout_secs: 0,
+            ..Readiness::default()
+        };
+        assert_eq!(readiness.mode, ReadinessMode::Port);
+        assert!(readiness.validate(None).is_err());
+    }
+
+    #[test]
+    fn parses_sizes_with_binary_suffixes() {
+        assert_eq!(parse_size("1048576").unwrap(), 1048576);
+        assert_eq!(parse_size("512K").unwrap(), 512 << 10);
+        assert_eq!(parse_size(" 256m ").unwrap(), 256 << 20);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("").is_err());
+        assert!(parse_size("99999999T").is_err());
+    }
+
+    #[test]
+    fn resource_limits_are_validated() {
+        let resources =
+            |fields: serde_json::Value| -> Resources { serde_json::from_value(fields).unwrap() };
+
+        let valid = resources(serde_json::json!({
+            "memory_max": "512M",
+            "memory_high": "256M",
+            "cpu_max": { "quota_us": 50000 },
+            "pids_max": 64,
+            "io_weight": 500,
+        }));
+        assert!(valid.validate().is_ok());
+        assert_eq!(valid.cpu_max.unwrap().period_us, 100_000);
+
+        for invalid in [
+            serde_json::json!({ "memory_max": "0" }),
+            serde_json::json!({ "memory_max": "256M", "memory_high": "512M" }),
+            serde_json::json!({ "memory_high": "lots" }),
+            serde_json::json!({ "cpu_max": { "quota_us": 999 } }),
+            serde_json::json!({ "cpu_max": { "quota_us": 50000, "period_us": 500 } }),
+            serde_json::json!({ "pids_max": 0 }),
+            serde_json::json!({ "io_weight": 0 }),
+            serde_json::json!({ "io_weight": 10001 }),
+        ] {
+            assert!(
+                resources(invalid.clone()).validate().is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn restart_backoff_needs_a_delay() {
+        let backoff = RestartBackoff {
+            initial_delay_ms: 0,
+            ..RestartBackoff::default()
+        };
+        assert!(backoff.validate().is_err());
+        assert!(RestartBackoff::default().validate().is_ok());
+    }
+
+    #[test]
+    fn restart_backoff_grows_up_to_the_maximum() {
+        let backoff = RestartBackoff {
+            initial_delay_ms: 100,
+            multiplier: 2.0,
+            max_delay_ms: 500,
+            jitter: 0.0,
+            ..RestartBackoff::default()
+        };
+        let delays: Vec<u64> = (1..=5)
+            .map(|attempt| backoff.delay_for(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+
+        let legacy = RestartBackoff {
+            initial_delay_ms: 0,
+            ..backoff
+        };
+        assert!(legacy.delay_for(1) > Duration::ZERO);
+    }
+
+    #[test]
+    fn parse_signal_accepts_short_and_full_names() {
+        assert_eq!(parse_signal("SIGTERM").unwrap(), libc::SIGTERM);
+        assert_eq!(parse_signal("term").unwrap(), libc::SIGTERM);
+        assert_eq!(parse_signal(" sigquit ").unwrap(), libc::SIGQUIT);
+        assert_eq!(parse_signal("Usr1").unwrap(), libc::SIGUSR1);
+        assert_eq!(parse_signal("KILL").unwrap(), libc::SIGKILL);
+    }
+
+    #[test]
+    fn parse_signal_rejects_unknown_signals() {
+        assert!(parse_signal("SIGSTOP").is_err());
+        assert!(parse_signal("15").is_err());
+        let error = parse_signal("bogus").unwrap_err().to_string();
+        assert!(error.contains("'bogus'"));
+        assert!(error.contains("SIGTERM"));
+    }
+}
@@ -0,0 +1,228 @@
+use anyhow::Result;
+
+/// Capability names in kernel order, so that a name's index is its number.
+const CAPABILITIES: &[&str] = &[
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+/// Parses a capability name such as `cap_net_raw` or `CAP_NET_RAW` into its
+/// number. A `setcap` style suffix like `+eip` is ignored.
+pub fn parse(name: &str) -> Result<u32> {
+    let bare = name
+        .split(['+', '=', '-'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let bare = if bare.starts_with("cap_") {
+        bare
+    } else {
+        format!("cap_{}", bare)
+    };
+
+    CAPABILITIES
+        .iter()
+        .position(|c| *c == bare)
+        .map(|i| i as u32)
+        .ok_or_else(|| anyhow::anyhow!("unknown capability '{}'", name))
+}
+
+/// Checks that every name in `names` is a capability the running kernel
+/// supports.
+pub fn validate(names: &[String]) -> Result<()> {
+    let last = last_cap();
+    for name in names {
+        if parse(name)? > last {
+            anyhow::bail!("capability '{}' is not supported by this kernel", name);
+        }
+    }
+    Ok(())
+}
+
+/// Highest capability number the running kernel knows about.
+pub fn last_cap() -> u32 {
+    std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(CAPABILITIES.len() as u32 - 1)
+}
+
+/// Parses a list of capability names into a bit set.
+pub fn parse_set(names: &[String]) -> Result<u64> {
+    names
+        .iter()
+        .try_fold(0u64, |set, name| Ok(set | 1 << parse(name)?))
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Capability sets of the calling thread, as 64-bit masks.
+pub struct CapSets {
+    pub effective: u64,
+    pub permitted: u64,
+    pub inheritable: u64,
+}
+
+impl CapSets {
+    /// Reads the capability sets of the calling thread. Async-signal-safe.
+    pub fn get() -> std::io::Result<Self> {
+        let mut header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapData::default(); 2];
+        if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let join = |lo: u32, hi: u32| lo as u64 | (hi as u64) << 32;
+        Ok(Self {
+            effective: join(data[0].effective, data[1].effective),
+            permitted: join(data[0].permitted, data[1].permitted),
+            inheritable: join(data[0].inheritable, data[1].inheritable),
+        })
+    }
+
+    /// Replaces the capability sets of the calling thread. Async-signal-safe.
+    pub fn set(&self) -> std::io::Result<()> {
+        let mut header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let split = |set: u64, half: usize| (set >> (32 * half)) as u32;
+        let data = [0, 1].map(|half| CapData {
+            effective: split(self.effective, half),
+            permitted: split(self.permitted, half),
+            inheritable: split(self.inheritable, half),
+        });
+        if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Removes every capability up to `last` that is not in `keep` from the
+/// bounding set of the calling thread, so that no exec can gain it again.
+/// Requires `CAP_SETPCAP`. Async-signal-safe.
+pub fn restrict_bounding_set(keep: u64, last: u32) -> std::io::Result<()> {
+    for cap in (0..=last).filter(|cap| keep & (1 << cap) == 0) {
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Adds every capability in `set` to the ambient set of the calling thread,
+/// so that it survives exec of a binary without file capabilities. Each one
+/// must already be permitted and inheritable. Async-signal-safe.
+pub fn raise_ambient(set: u64) -> std::io::Result<()> {
+    for cap in (0..64).filter(|cap| set & (1 << cap) != 0) {
+        let raised = unsafe {
+            libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+                cap as libc::c_ulong,
+                0 as libc::c_ulong,
+                0 as libc::c_ulong,
+            )
+        };
+        if raised != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_names_in_any_spelling() {
+        assert_eq!(parse("cap_chown").unwrap(), 0);
+        assert_eq!(parse("CAP_NET_BIND_SERVICE").unwrap(), 10);
+        assert_eq!(parse("net_bind_service").unwrap(), 10);
+        assert_eq!(parse("cap_net_raw+eip").unwrap(), 13);
+        assert_eq!(parse(" cap_sys_admin=ep ").unwrap(), 21);
+        assert_eq!(parse("cap_checkpoint_restore").unwrap(), 40);
+        assert!(parse("cap_fly").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn parse_set_combines_bits() {
+        assert_eq!(parse_set(&[]).unwrap(), 0);
+        assert_eq!(
+            parse_set(&names(&["cap_chown", "cap_kill", "cap_kill"])).unwrap(),
+            1 | 1 << 5
+        );
+        assert!(parse_set(&names(&["cap_chown", "bogus"])).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_unknown_names() {
+        assert!(validate(&names(&["cap_chown", "net_raw"])).is_ok());
+        let error = validate(&names(&["cap_chown", "cap_bogus"])).unwrap_err();
+        assert!(error.to_string().contains("cap_bogus"));
+    }
+}
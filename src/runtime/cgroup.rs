@@ -0,0 +1,358 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::registry::{Resources, parse_size};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Leaf of dockless's own cgroup that dockless moves itself into. A cgroup
+/// that hands controllers to its children cannot hold processes itself.
+const SUPERVISOR_LEAF: &str = "supervisor";
+
+/// Parent of all service cgroups, within dockless's own cgroup.
+const SERVICES: &str = "services";
+
+/// The cgroup dockless was started in, e.g. the one systemd created for its
+/// unit. Read once, before dockless moves into its leaf.
+static OWN_CGROUP: OnceLock<PathBuf> = OnceLock::new();
+
+fn own_cgroup() -> &'static Path {
+    OWN_CGROUP.get_or_init(|| {
+        // cgroup v2 has a single line, `0::<path>`.
+        let path = std::fs::read_to_string("/proc/self/cgroup")
+            .ok()
+            .and_then(|content| {
+                content
+                    .lines()
+                    .find_map(|line| line.strip_prefix("0::").map(str::to_string))
+            })
+            .unwrap_or_else(|| "/".to_string());
+        Path::new(CGROUP_ROOT).join(path.trim_start_matches('/'))
+    })
+}
+
+/// Moves dockless into the `supervisor` leaf of the cgroup it was started in,
+/// so that the cgroup can pass controllers on to the service cgroups. Has to
+/// happen before any service is started, since services not limited by a
+/// cgroup of their own stay in dockless's.
+pub fn init() -> Result<()> {
+    let own = own_cgroup();
+    if !own.join("cgroup.controllers").exists() {
+        anyhow::bail!("cgroup v2 is not mounted at {}", CGROUP_ROOT);
+    }
+
+    let leaf = own.join(SUPERVISOR_LEAF);
+    std::fs::create_dir_all(&leaf)
+        .with_context(|| format!("failed to create {}", leaf.display()))?;
+    std::fs::write(leaf.join("cgroup.procs"), std::process::id().to_string())
+        .with_context(|| format!("failed to move dockless into {}", leaf.display()))
+}
+
+/// The cgroup v2 group a service process is placed in,
+/// `<dockless's cgroup>/services/<id>`.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+/// Effective limits and current usage, as reported by the kernel.
+#[derive(Debug, Serialize)]
+pub struct CgroupStats {
+    pub path: String,
+    pub memory_current: Option<u64>,
+    pub memory_max: Option<String>,
+    pub memory_high: Option<String>,
+    pub cpu_max: Option<String>,
+    pub cpu_usage_usec: Option<u64>,
+    pub pids_current: Option<u64>,
+    pub pids_max: Option<String>,
+    pub io_weight: Option<String>,
+}
+
+impl Cgroup {
+    pub fn for_service(id: &str) -> Self {
+        Self {
+            path: own_cgroup().join(SERVICES).join(id),
+        }
+    }
+
+    /// Creates the cgroup if needed and writes `resources` to it. Limits that
+    /// are not set are reset to `max`, so removing one takes effect on the
+    /// next start.
+    pub fn apply(&self, resources: &Resources) -> Result<()> {
+        let own = own_cgroup();
+        if !own.join("cgroup.controllers").exists() {
+            anyhow::bail!("cgroup v2 is not mounted at {}", CGROUP_ROOT);
+        }
+
+        let mut controllers = Vec::new();
+        if resources.memory_max.is_some() || resources.memory_high.is_some() {
+            controllers.push("memory");
+        }
+        if resources.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        if resources.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        if resources.io_weight.is_some() {
+            controllers.push("io");
+        }
+
+        let services = own.join(SERVICES);
+        std::fs::create_dir_all(&services)
+            .with_context(|| format!("failed to create {}", services.display()))?;
+        enable_controllers(own, &controllers)?;
+        enable_controllers(&services, &controllers)?;
+
+        std::fs::create_dir_all(&self.path)
+            .with_context(|| format!("failed to create {}", self.path.display()))?;
+
+        let memory_max = resources
+            .memory_max
+            .as_deref()
+            .map(parse_size)
+            .transpose()?;
+        let memory_high = resources
+            .memory_high
+            .as_deref()
+            .map(parse_size)
+            .transpose()?;
+
+        self.write_limit("memory.max", memory_max.map(|v| v.to_string()))?;
+        self.write_limit("memory.high", memory_high.map(|v| v.to_string()))?;
+        self.write_limit(
+            "cpu.max",
+            resources
+                .cpu_max
+                .as_ref()
+                .map(|c| format!("{} {}", c.quota_us, c.period_us)),
+        )?;
+        self.write_limit("pids.max", resources.pids_max.map(|v| v.to_string()))?;
+
+        match resources.io_weight {
+            Some(weight) => self.write("io.weight", &format!("default {}", weight))?,
+            None => self.reset("io.weight", "default 100"),
+        }
+
+        Ok(())
+    }
+
+    /// `cgroup.procs` of this cgroup. Writing `0` to it moves the writer in.
+    pub fn procs_path(&self) -> PathBuf {
+        self.path.join("cgroup.procs")
+    }
+
+    /// Kills every process in the cgroup, wherever in the process tree it is.
+    /// Does nothing if the cgroup does not exist.
+    pub fn kill(&self) {
+        if !self.path.exists() || std::fs::write(self.path.join("cgroup.kill"), "1").is_ok() {
+            return;
+        }
+
+        // `cgroup.kill` is new in Linux 5.14.
+        for pid in self.procs() {
+            unsafe {
+                libc::kill(pid as i32, libc::SIGKILL);
+            }
+        }
+    }
+
+    /// Whether `pid` is in this cgroup.
+    pub fn contains(&self, pid: u32) -> bool {
+        self.path.exists() && self.procs().contains(&pid)
+    }
+
+    fn procs(&self) -> Vec<u32> {
+        self.read("cgroup.procs")
+            .map(|procs| procs.lines().filter_map(|l| l.parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes the cgroup. This only succeeds once no process is left in it.
+    pub fn remove(&self) {
+        let _ = std::fs::remove_dir(&self.path);
+    }
+
+    /// Number of processes in this cgroup killed by the OOM killer so far.
+    pub fn oom_kills(&self) -> Option<u64> {
+        self.read("memory.events")?
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    pub fn stats(&self) -> Option<CgroupStats> {
+        if !self.path.exists() {
+            return None;
+        }
+
+        let cpu_usage_usec = self.read("cpu.stat").and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .and_then(|v| v.trim().parse().ok())
+        });
+
+        Some(CgroupStats {
+            path: self.path.display().to_string(),
+            memory_current: self.read("memory.current").and_then(|v| v.parse().ok()),
+            memory_max: self.read("memory.max"),
+            memory_high: self.read("memory.high"),
+            cpu_max: self.read("cpu.max"),
+            cpu_usage_usec,
+            pids_current: self.read("pids.current").and_then(|v| v.parse().ok()),
+            pids_max: self.read("pids.max"),
+            io_weight: self.read("io.weight"),
+        })
+    }
+
+    fn write_limit(&self, file: &str, value: Option<String>) -> Result<()> {
+        match value {
+            Some(value) => self.write(file, &value),
+            None => {
+                self.reset(file, "max");
+                Ok(())
+            }
+        }
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        let path = self.path.join(file);
+        std::fs::write(&path, value)
+            .with_context(|| format!("failed to write '{}' to {}", value, path.display()))
+    }
+
+    /// Best-effort reset of a limit whose controller may not be enabled.
+    fn reset(&self, file: &str, value: &str) {
+        let path = self.path.join(file);
+        if path.exists() {
+            let _ = std::fs::write(path, value);
+        }
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        std::fs::read_to_string(self.path.join(file))
+            .ok()
+            .map(|v| v.trim().to_string())
+    }
+}
+
+/// Makes `controllers` available to the children of `dir`.
+fn enable_controllers(dir: &Path, controllers: &[&str]) -> Result<()> {
+    let available = std::fs::read_to_string(dir.join("cgroup.controllers")).unwrap_or_default();
+    let subtree_control = dir.join("cgroup.subtree_control");
+    let enabled = std::fs::read_to_string(&subtree_control).unwrap_or_default();
+
+    for controller in controllers {
+        if enabled.split_whitespace().any(|c| c == *controller) {
+            continue;
+        }
+
+        if !available.split_whitespace().any(|c| c == *controller) {
+            anyhow::bail!(
+                "the {} controller is not available in {}",
+                controller,
+                dir.display()
+            );
+        }
+
+        std::fs::write(&subtree_control, format!("+{}", controller)).with_context(|| {
+            format!(
+                "failed to enable the {} controller in {}",
+                controller,
+                dir.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory standing in for a cgroup, with the given interface files.
+    fn fake_cgroup(name: &str, files: &[(&str, &str)]) -> Cgroup {
+        let path =
+            std::env::temp_dir().join(format!("dockless-cgroup-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        for (file, content) in files {
+            std::fs::write(path.join(file), content).unwrap();
+        }
+        Cgroup { path }
+    }
+
+    #[test]
+    fn reads_usage_and_oom_kills() {
+        let cgroup = fake_cgroup(
+            "stats",
+            &[
+                ("cgroup.procs", "41\n42\n"),
+                ("memory.events", "low 0\nhigh 3\nmax 5\noom 2\noom_kill 2\n"),
+                ("memory.current", "1048576\n"),
+                ("memory.max", "max\n"),
+                ("cpu.stat", "usage_usec 1500\nuser_usec 1000\n"),
+                ("pids.current", "2\n"),
+            ],
+        );
+
+        assert!(cgroup.contains(42));
+        assert!(!cgroup.contains(43));
+        assert_eq!(cgroup.oom_kills(), Some(2));
+
+        let stats = cgroup.stats().unwrap();
+        assert_eq!(stats.memory_current, Some(1048576));
+        assert_eq!(stats.memory_max.as_deref(), Some("max"));
+        assert_eq!(stats.cpu_usage_usec, Some(1500));
+        assert_eq!(stats.pids_current, Some(2));
+        assert_eq!(stats.io_weight, None);
+
+        std::fs::remove_dir_all(&cgroup.path).unwrap();
+        assert!(cgroup.stats().is_none());
+        assert!(!cgroup.contains(42));
+    }
+
+    #[test]
+    fn resets_limits_that_are_no_longer_set() {
+        let cgroup = fake_cgroup("limits", &[("memory.max", "268435456"), ("pids.max", "64")]);
+
+        cgroup
+            .write_limit("memory.max", Some("536870912".to_string()))
+            .unwrap();
+        cgroup.write_limit("pids.max", None).unwrap();
+        cgroup.write_limit("cpu.max", None).unwrap();
+
+        assert_eq!(cgroup.read("memory.max").as_deref(), Some("536870912"));
+        assert_eq!(cgroup.read("pids.max").as_deref(), Some("max"));
+        assert!(!cgroup.path.join("cpu.max").exists());
+    }
+
+    #[test]
+    fn only_enables_available_controllers() {
+        let cgroup = fake_cgroup(
+            "controllers",
+            &[
+                ("cgroup.controllers", "cpu memory pids"),
+                ("cgroup.subtree_control", "cpu"),
+            ],
+        );
+
+        enable_controllers(&cgroup.path, &["cpu", "memory"]).unwrap();
+        assert_eq!(
+            cgroup.read("cgroup.subtree_control").as_deref(),
+            Some("+memory")
+        );
+
+        let error = enable_controllers(&cgroup.path, &["io"]).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("the io controller is not available")
+        );
+    }
+}
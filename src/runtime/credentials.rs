@@ -0,0 +1,474 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::{CStr, CString},
+    fs::OpenOptions,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::{OpenOptionsExt, fchown, lchown},
+        },
+    },
+    path::Path,
+};
+use tokio::process::Command;
+
+use crate::{registry::RunAs, runtime::capabilities};
+
+/// Linux limits user names to 32 bytes.
+const MAX_USER_NAME_LEN: usize = 32;
+const DYNAMIC_USER_PREFIX: &str = "dockless-";
+
+/// The user and groups a service process runs as.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Supplementary groups of the user.
+    pub groups: Vec<libc::gid_t>,
+    pub home: String,
+}
+
+impl Credentials {
+    /// Resolves `run_as` for the service `service_id`. The dynamic user of
+    /// the service is created on first use, with `home` as its home
+    /// directory.
+    pub fn resolve(run_as: &RunAs, service_id: &str, home: &str) -> Result<Self> {
+        let (user, group) = match run_as {
+            RunAs::User { user, group } => (user.clone(), group.clone()),
+            RunAs::Dynamic => {
+                let user = dynamic_user_name(service_id);
+                if lookup_user(&user)?.is_none() {
+                    create_dynamic_user(&user, home)?;
+                }
+                (user, None)
+            }
+        };
+
+        let passwd =
+            lookup_user(&user)?.ok_or_else(|| anyhow::anyhow!("user '{}' does not exist", user))?;
+        let gid = match &group {
+            Some(group) => lookup_group(group)?
+                .ok_or_else(|| anyhow::anyhow!("group '{}' does not exist", group))?,
+            None => passwd.gid,
+        };
+        let groups = group_list(&user, gid)?;
+
+        Ok(Self {
+            user,
+            uid: passwd.uid,
+            gid,
+            groups,
+            home: passwd.home,
+        })
+    }
+
+    /// Gives the user ownership of `path` and, for a directory, everything in
+    /// it. Symlinks are not followed.
+    ///
+    /// Once the top directory belongs to the user, a process running as the
+    /// user can swap anything below it for a symlink while the walk is under
+    /// way. So the walk goes through directory descriptors opened without
+    /// following symlinks, and never through paths.
+    pub fn chown_tree(&self, path: &Path) -> io::Result<()> {
+        if !std::fs::symlink_metadata(path)?.is_dir() {
+            return lchown(path, Some(self.uid), Some(self.gid));
+        }
+
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+            .open(path)?;
+        self.chown_dir(&dir.into())
+    }
+
+    fn chown_dir(&self, dir: &OwnedFd) -> io::Result<()> {
+        fchown(dir, Some(self.uid), Some(self.gid))?;
+
+        // Listed through the descriptor, so it is the directory just opened
+        // whatever its path now leads to.
+        for entry in std::fs::read_dir(format!("/proc/self/fd/{}", dir.as_raw_fd()))? {
+            let entry = entry?;
+            let name = CString::new(entry.file_name().as_bytes())?;
+
+            let chowned = unsafe {
+                libc::fchownat(
+                    dir.as_raw_fd(),
+                    name.as_ptr(),
+                    self.uid,
+                    self.gid,
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            if chowned != 0 {
+                let e = io::Error::last_os_error();
+                // Removed since it was listed.
+                if e.raw_os_error() == Some(libc::ENOENT) {
+                    continue;
+                }
+                return Err(e);
+            }
+
+            if entry.file_type()?.is_dir() {
+                let fd = unsafe {
+                    libc::openat(
+                        dir.as_raw_fd(),
+                        name.as_ptr(),
+                        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                    )
+                };
+                if fd < 0 {
+                    let e = io::Error::last_os_error();
+                    // Replaced by a symlink or something else, or removed,
+                    // since it was listed.
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(libc::ELOOP | libc::ENOTDIR | libc::ENOENT)
+                    ) {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                self.chown_dir(&unsafe { OwnedFd::from_raw_fd(fd) })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the user and group named by `run_as` exist. A dynamic user is
+/// created when the service starts, so it always passes.
+pub fn check(run_as: &RunAs) -> Result<()> {
+    if let RunAs::User { user, group } = run_as {
+        if lookup_user(user)?.is_none() {
+            anyhow::bail!("run_as.user: user '{}' does not exist", user);
+        }
+        if let Some(group) = group
+            && lookup_group(group)?.is_none()
+        {
+            anyhow::bail!("run_as.group: group '{}' does not exist", group);
+        }
+    }
+
+    Ok(())
+}
+
+/// Name of the dynamic user of a service: `dockless-<id>`, or for ids too
+/// long for a user name, a prefix of the id followed by a hash of all of it.
+pub fn dynamic_user_name(service_id: &str) -> String {
+    let name = format!("{}{}", DYNAMIC_USER_PREFIX, service_id);
+    if name.len() <= MAX_USER_NAME_LEN {
+        return name;
+    }
+
+    let hash: String = Sha256::digest(service_id.as_bytes())[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let prefix_len = MAX_USER_NAME_LEN - DYNAMIC_USER_PREFIX.len() - hash.len() - 1;
+    let prefix: String = service_id
+        .chars()
+        .scan(0, |len, c| {
+            *len += c.len_utf8();
+            (*len <= prefix_len).then_some(c)
+        })
+        .collect();
+
+    format!("{}{}-{}", DYNAMIC_USER_PREFIX, prefix, hash)
+}
+
+/// Deletes the dynamic user of a deleted service, if it was ever created.
+pub fn remove_dynamic_user(service_id: &str) {
+    let user = dynamic_user_name(service_id);
+    if !matches!(lookup_user(&user), Ok(Some(_))) {
+        return;
+    }
+
+    match std::process::Command::new("userdel").arg(&user).output() {
+        Ok(out) if out.status.success() => tracing::info!("removed dynamic user {}", user),
+        Ok(out) => tracing::warn!(
+            "failed to remove dynamic user {}: {}",
+            user,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ),
+        Err(e) => tracing::warn!("failed to run userdel for {}: {}", user, e),
+    }
+}
+
+fn create_dynamic_user(user: &str, home: &str) -> Result<()> {
+    let out = std::process::Command::new("useradd")
+        .args(["--system", "--user-group", "--no-create-home"])
+        .args(["--home-dir", home])
+        .args(["--shell", "/usr/sbin/nologin"])
+        .args(["--comment", "dockless dynamic user"])
+        .arg(user)
+        .output()
+        .context("failed to run useradd")?;
+
+    if !out.status.success() {
+        anyhow::bail!(
+            "failed to create dynamic user {} ({}): {}",
+            user,
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+
+    tracing::info!("created dynamic user {}", user);
+    Ok(())
+}
+
+/// Arranges for the process spawned by `cmd` to run as `credentials`, when
+/// given, with `capabilities` as ambient capabilities. A process that drops
+/// privileges also loses every other capability from its bounding set, so it
+/// cannot regain them through a setuid or file-capability binary. This has to
+/// be the last `pre_exec` hook that needs privileges.
+pub fn configure(
+    cmd: &mut Command,
+    credentials: Option<&Credentials>,
+    capabilities: &[String],
+) -> Result<()> {
+    let caps = capabilities::parse_set(capabilities)?;
+    if credentials.is_none() && caps == 0 {
+        return Ok(());
+    }
+
+    if let Some(credentials) = credentials {
+        cmd.env("USER", &credentials.user);
+        cmd.env("LOGNAME", &credentials.user);
+        cmd.env("HOME", &credentials.home);
+    }
+
+    let ids = credentials.map(|c| (c.uid, c.gid, c.groups.clone()));
+    let last_cap = capabilities::last_cap();
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some((uid, gid, groups)) = &ids {
+                capabilities::restrict_bounding_set(caps, last_cap)?;
+
+                // Keep the permitted set across setuid so the capabilities
+                // can still be passed on below.
+                if caps != 0 && libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::setgroups(groups.len(), groups.as_ptr()) != 0
+                    || libc::setgid(*gid) != 0
+                    || libc::setuid(*uid) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if caps != 0 {
+                let mut sets = capabilities::CapSets::get()?;
+                if ids.is_some() {
+                    sets.permitted &= caps;
+                    sets.effective = sets.permitted;
+                }
+                sets.inheritable |= caps;
+                sets.set()?;
+                capabilities::raise_ambient(caps)?;
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+struct Passwd {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    home: String,
+}
+
+fn lookup_user(name: &str) -> Result<Option<Passwd>> {
+    let c_name = CString::new(name).context("invalid user name")?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+
+    let rc = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc))
+            .with_context(|| format!("failed to look up user '{}'", name));
+    }
+    if result.is_null() {
+        return Ok(None);
+    }
+
+    Ok(Some(Passwd {
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+        home: unsafe { CStr::from_ptr(passwd.pw_dir) }
+            .to_string_lossy()
+            .into_owned(),
+    }))
+}
+
+fn lookup_group(name: &str) -> Result<Option<libc::gid_t>> {
+    let c_name = CString::new(name).context("invalid group name")?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+
+    let rc = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc))
+            .with_context(|| format!("failed to look up group '{}'", name));
+    }
+
+    Ok((!result.is_null()).then_some(group.gr_gid))
+}
+
+/// Groups `user` belongs to, including `gid`.
+fn group_list(user: &str, gid: libc::gid_t) -> Result<Vec<libc::gid_t>> {
+    let c_user = CString::new(user).context("invalid user name")?;
+    let mut count: libc::c_int = 32;
+
+    loop {
+        let mut groups = vec![0 as libc::gid_t; count as usize];
+        let capacity = count;
+        let rc =
+            unsafe { libc::getgrouplist(c_user.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if rc >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        // `count` now holds the number of groups needed.
+        if count <= capacity {
+            count = capacity * 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+
+    fn is_root() -> bool {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    fn nobody() -> Credentials {
+        Credentials::resolve(
+            &RunAs::User {
+                user: "nobody".to_string(),
+                group: None,
+            },
+            "unused",
+            "/nonexistent",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dynamic_user_names_fit_the_length_limit() {
+        assert_eq!(dynamic_user_name("web"), "dockless-web");
+
+        let long = "a-service-id-that-is-far-too-long-for-a-user-name";
+        let name = dynamic_user_name(long);
+        assert!(name.len() <= MAX_USER_NAME_LEN, "{}", name);
+        assert!(name.starts_with("dockless-a-service-id"));
+        assert_eq!(dynamic_user_name(long), name);
+        assert_ne!(dynamic_user_name(&format!("{}-2", long)), name);
+
+        let name = dynamic_user_name(&"é".repeat(20));
+        assert!(name.len() <= MAX_USER_NAME_LEN, "{}", name);
+    }
+
+    #[test]
+    fn checks_that_users_and_groups_exist() {
+        let run_as = |user: &str, group: Option<&str>| RunAs::User {
+            user: user.to_string(),
+            group: group.map(str::to_string),
+        };
+
+        assert!(check(&run_as("root", Some("root"))).is_ok());
+        assert!(check(&RunAs::Dynamic).is_ok());
+        let error = check(&run_as("dockless-no-such-user", None)).unwrap_err();
+        assert!(error.to_string().contains("does not exist"));
+        assert!(check(&run_as("root", Some("dockless-no-such-group"))).is_err());
+
+        let root = Credentials::resolve(&run_as("root", None), "unused", "/").unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert!(root.groups.contains(&0));
+    }
+
+    #[test]
+    fn chown_tree_does_not_follow_symlinks() {
+        if !is_root() {
+            return;
+        }
+
+        let base = std::env::temp_dir().join(format!("dockless-chown-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let tree = base.join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("sub").join("file"), "").unwrap();
+        let outside = base.join("outside");
+        std::fs::write(&outside, "").unwrap();
+        std::os::unix::fs::symlink(&outside, tree.join("link")).unwrap();
+        std::os::unix::fs::symlink(&base, tree.join("sub").join("up")).unwrap();
+
+        let nobody = nobody();
+        nobody.chown_tree(&tree).unwrap();
+
+        for path in [
+            tree.clone(),
+            tree.join("sub"),
+            tree.join("sub").join("file"),
+            tree.join("link"),
+        ] {
+            let metadata = std::fs::symlink_metadata(&path).unwrap();
+            assert_eq!(metadata.uid(), nobody.uid, "{}", path.display());
+        }
+        assert_eq!(std::fs::metadata(&outside).unwrap().uid(), 0);
+        assert_eq!(std::fs::metadata(&base).unwrap().uid(), 0);
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn runs_the_process_as_the_user() {
+        if !is_root() {
+            return;
+        }
+
+        let nobody = nobody();
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("id -u; id -g; echo $USER");
+        configure(&mut cmd, Some(&nobody), &[]).unwrap();
+
+        let output = cmd.output().await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("{}\n{}\nnobody\n", nobody.uid, nobody.gid)
+        );
+    }
+}
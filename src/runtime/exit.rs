@@ -0,0 +1,323 @@
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::Read,
+    os::{fd::AsRawFd, unix::process::ExitStatusExt},
+    process::ExitStatus,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitKind {
+    /// The process exited on its own with `exit_code`.
+    Exited,
+    /// The process was terminated by `signal`.
+    Signaled,
+    /// The process was terminated by `signal` and dumped core, e.g. a
+    /// segfault or an abort.
+    CoreDumped,
+    /// The kernel killed the process because it ran out of memory.
+    OomKilled,
+    /// The process was adopted from a previous dockless instance, so its exit
+    /// status is not available.
+    Unknown,
+}
+
+/// How the last process of a service ended.
+#[derive(Debug, Clone, Serialize)]
+pub struct LastExit {
+    pub kind: ExitKind,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub signal_name: Option<&'static str>,
+    pub core_dumped: bool,
+    /// The OOM killer killed this process or, with a cgroup, any process of
+    /// the service.
+    pub oom_killed: bool,
+    /// Why dockless stopped the process itself, if it did.
+    pub stopped_by: Option<String>,
+    pub exited_at: String,
+    pub ran_for_secs: u64,
+    /// One-line description, e.g. `killed by the OOM killer (SIGKILL)`.
+    pub summary: String,
+}
+
+impl LastExit {
+    /// Classifies an exit. `oom_killed` tells whether the OOM killer struck
+    /// during the run, see [`oom_killed`].
+    pub fn classify(
+        status: Option<ExitStatus>,
+        oom_killed: bool,
+        stopped_by: Option<String>,
+        ran_for: Duration,
+    ) -> Self {
+        let exit_code = status.and_then(|s| s.code());
+        let signal = status.and_then(|s| s.signal());
+        let core_dumped = status.is_some_and(|s| s.core_dumped());
+
+        let killed = signal == Some(libc::SIGKILL) && stopped_by.is_none();
+
+        let signal_name = signal.and_then(signal_name);
+        let signal_label = match (signal, signal_name) {
+            (_, Some(name)) => name.to_string(),
+            (Some(signal), None) => format!("signal {}", signal),
+            (None, None) => String::new(),
+        };
+
+        let (kind, mut summary) = if oom_killed && killed {
+            (
+                ExitKind::OomKilled,
+                format!("killed by the OOM killer ({})", signal_label),
+            )
+        } else if core_dumped {
+            (
+                ExitKind::CoreDumped,
+                format!("killed by {} (core dumped)", signal_label),
+            )
+        } else if signal.is_some() {
+            (ExitKind::Signaled, format!("killed by {}", signal_label))
+        } else if let Some(code) = exit_code {
+            (ExitKind::Exited, format!("exited with code {}", code))
+        } else {
+            (ExitKind::Unknown, "exited, status unknown".to_string())
+        };
+
+        // The OOM killer may have picked a helper rather than the main
+        // process, which then exited on its own.
+        if oom_killed && kind != ExitKind::OomKilled {
+            summary.push_str(", after the OOM killer killed a process in its cgroup");
+        }
+
+        if let Some(reason) = &stopped_by {
+            summary.push_str(&format!(", stopped by {}", reason));
+        }
+
+        Self {
+            kind,
+            exit_code,
+            signal,
+            signal_name,
+            core_dumped,
+            oom_killed,
+            stopped_by,
+            exited_at: chrono::Utc::now().to_rfc3339(),
+            ran_for_secs: ran_for.as_secs(),
+            summary,
+        }
+    }
+}
+
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Whether the OOM killer struck during a run of `ran_for` that ended with
+/// `status`. With the service's cgroup, `oom_kills` holds its OOM kill count
+/// from before the run and now, which covers every process of the service.
+/// Otherwise only the main process can be checked, in the kernel log.
+pub async fn oom_killed(
+    status: Option<ExitStatus>,
+    pid: Option<u32>,
+    oom_kills: Option<(u64, Option<u64>)>,
+    stopped_by: Option<&str>,
+    ran_for: Duration,
+) -> bool {
+    if let Some((before, after)) = oom_kills {
+        return after.is_some_and(|after| after > before);
+    }
+
+    // The OOM killer sends SIGKILL, and dockless only sends it when stopping.
+    let killed = status.and_then(|s| s.signal()) == Some(libc::SIGKILL) && stopped_by.is_none();
+    let Some(pid) = pid.filter(|_| killed) else {
+        return false;
+    };
+
+    // Reading the kernel log blocks.
+    tokio::task::spawn_blocking(move || kernel_log_reports_oom_kill(pid, ran_for))
+        .await
+        .unwrap_or(false)
+}
+
+/// Searches the kernel log for an OOM kill of `pid` in the last `ran_for`,
+/// so that an earlier process with the same PID does not count. The kernel
+/// reports it as `Out of memory: Killed process <pid> (<name>) ...`, or with
+/// a `Memory cgroup out of memory:` prefix for cgroup limits. Blocking.
+fn kernel_log_reports_oom_kill(pid: u32, ran_for: Duration) -> bool {
+    let Ok(mut kmsg) = File::open("/dev/kmsg") else {
+        return false;
+    };
+
+    // Records carry the time since boot they were logged at, in
+    // microseconds.
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return false;
+    }
+    let now_usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    let started_usec = now_usec.saturating_sub(ran_for.as_micros() as u64);
+
+    // Read the records already in the buffer without waiting for new ones.
+    unsafe {
+        libc::fcntl(kmsg.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK);
+    }
+
+    let needle = format!("Killed process {} (", pid);
+    let mut record = vec![0u8; 8192];
+    let mut found = false;
+
+    loop {
+        match kmsg.read(&mut record) {
+            Ok(0) => break,
+            Ok(len) => {
+                // `<priority>,<sequence>,<timestamp>,<flags>;<message>`
+                let record = String::from_utf8_lossy(&record[..len]);
+                let logged_usec = record.split(',').nth(2).and_then(|t| t.parse::<u64>().ok());
+                if logged_usec.is_some_and(|t| t >= started_usec) && record.contains(&needle) {
+                    found = true;
+                }
+            }
+            // EPIPE means records were overwritten while reading; keep going.
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(_) => break,
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exited(code: i32) -> Option<ExitStatus> {
+        Some(ExitStatus::from_raw(code << 8))
+    }
+
+    fn signaled(signal: i32) -> Option<ExitStatus> {
+        Some(ExitStatus::from_raw(signal))
+    }
+
+    fn classify(
+        status: Option<ExitStatus>,
+        oom_killed: bool,
+        stopped_by: Option<&str>,
+    ) -> LastExit {
+        LastExit::classify(
+            status,
+            oom_killed,
+            stopped_by.map(str::to_string),
+            Duration::from_secs(5),
+        )
+    }
+
+    #[test]
+    fn tells_exits_signals_and_core_dumps_apart() {
+        let exit = classify(exited(3), false, None);
+        assert_eq!(exit.kind, ExitKind::Exited);
+        assert_eq!(exit.exit_code, Some(3));
+        assert_eq!(exit.summary, "exited with code 3");
+        assert_eq!(exit.ran_for_secs, 5);
+
+        let exit = classify(signaled(libc::SIGTERM), false, None);
+        assert_eq!(exit.kind, ExitKind::Signaled);
+        assert_eq!(exit.signal_name, Some("SIGTERM"));
+        assert_eq!(exit.summary, "killed by SIGTERM");
+
+        let exit = classify(signaled(libc::SIGSEGV | 0x80), false, None);
+        assert_eq!(exit.kind, ExitKind::CoreDumped);
+        assert!(exit.core_dumped);
+        assert_eq!(exit.summary, "killed by SIGSEGV (core dumped)");
+
+        let exit = classify(signaled(libc::SIGRTMIN() + 1), false, None);
+        assert_eq!(exit.signal_name, None);
+        assert!(exit.summary.starts_with("killed by signal "));
+
+        let exit = classify(None, false, None);
+        assert_eq!(exit.kind, ExitKind::Unknown);
+        assert_eq!(exit.summary, "exited, status unknown");
+    }
+
+    #[test]
+    fn only_an_unrequested_sigkill_counts_as_an_oom_kill() {
+        let exit = classify(signaled(libc::SIGKILL), true, None);
+        assert_eq!(exit.kind, ExitKind::OomKilled);
+        assert_eq!(exit.summary, "killed by the OOM killer (SIGKILL)");
+
+        let exit = classify(signaled(libc::SIGKILL), true, Some("stop request"));
+        assert_eq!(exit.kind, ExitKind::Signaled);
+        assert_eq!(
+            exit.summary,
+            "killed by SIGKILL, after the OOM killer killed a process in its cgroup, stopped by stop request"
+        );
+
+        let exit = classify(exited(1), true, None);
+        assert_eq!(exit.kind, ExitKind::Exited);
+        assert!(exit.oom_killed);
+        assert!(
+            exit.summary
+                .ends_with("after the OOM killer killed a process in its cgroup")
+        );
+    }
+
+    #[tokio::test]
+    async fn cgroup_oom_kill_count_decides_when_available() {
+        let ran_for = Duration::from_secs(1);
+        assert!(oom_killed(exited(0), Some(1), Some((2, Some(3))), None, ran_for).await);
+        assert!(
+            !oom_killed(
+                signaled(libc::SIGKILL),
+                Some(1),
+                Some((2, Some(2))),
+                None,
+                ran_for
+            )
+            .await
+        );
+        assert!(
+            !oom_killed(
+                signaled(libc::SIGKILL),
+                Some(1),
+                Some((2, None)),
+                None,
+                ran_for
+            )
+            .await
+        );
+        assert!(
+            !oom_killed(
+                signaled(libc::SIGKILL),
+                Some(1),
+                None,
+                Some("stop"),
+                ran_for
+            )
+            .await
+        );
+        assert!(!oom_killed(signaled(libc::SIGTERM), Some(1), None, None, ran_for).await);
+    }
+}
@@ -0,0 +1,280 @@
+use serde::Serialize;
+use std::process::Stdio;
+use tokio::{
+    process::Command,
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Duration, Instant, sleep, timeout},
+};
+
+use crate::{
+    registry::{HealthCheck, HealthProbe},
+    runtime::service::Service,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum HealthState {
+    /// No probe has passed yet and the start period has not run out.
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthStatus {
+    pub status: HealthState,
+    pub failing_streak: u32,
+    pub last_checked_at: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// Probe task for one run of a service process. The task is aborted when the
+/// monitor is dropped, i.e. when the process exits.
+pub struct HealthMonitor {
+    handle: JoinHandle<()>,
+}
+
+impl HealthMonitor {
+    /// Starts probing `service`. Once the configured number of consecutive
+    /// failures is reached a message is sent on `restart_tx`.
+    pub fn spawn(service: Service, check: HealthCheck, restart_tx: mpsc::Sender<()>) -> Self {
+        Self {
+            handle: tokio::spawn(monitor(service, check, restart_tx)),
+        }
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn monitor(service: Service, check: HealthCheck, restart_tx: mpsc::Sender<()>) {
+    let started_at = Instant::now();
+    let start_period = Duration::from_secs(check.start_period_secs);
+
+    let mut status = HealthStatus {
+        status: HealthState::Starting,
+        failing_streak: 0,
+        last_checked_at: None,
+        last_error: None,
+    };
+    service.set_health(Some(status.clone())).await;
+
+    loop {
+        sleep(Duration::from_secs(check.interval_secs)).await;
+
+        let result = match timeout(
+            Duration::from_secs(check.timeout_secs),
+            probe(&service, &check.probe),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {}s", check.timeout_secs)),
+        };
+
+        status.last_checked_at = Some(chrono::Utc::now().to_rfc3339());
+
+        match result {
+            Ok(()) => {
+                if status.status == HealthState::Unhealthy {
+                    service
+                        .log_buffer
+                        .push("info".to_string(), "health check passing again".to_string())
+                        .await;
+                }
+                status.status = HealthState::Healthy;
+                status.failing_streak = 0;
+                status.last_error = None;
+            }
+            Err(e) => {
+                status.last_error = Some(e.clone());
+
+                // Failures while the app is still starting up do not count.
+                if status.status == HealthState::Starting && started_at.elapsed() < start_period {
+                    service.set_health(Some(status.clone())).await;
+                    continue;
+                }
+
+                status.failing_streak += 1;
+
+                if status.failing_streak >= check.retries && status.status != HealthState::Unhealthy
+                {
+                    status.status = HealthState::Unhealthy;
+                    let msg = format!(
+                        "health check failed {} times in a row: {}",
+                        status.failing_streak, e
+                    );
+                    tracing::warn!("[{}] {}", service.id, msg);
+                    service.log_buffer.push("error".to_string(), msg).await;
+                }
+
+                if let Some(limit) = check.restart_after_failures
+                    && status.failing_streak >= limit
+                {
+                    service.set_health(Some(status.clone())).await;
+                    let _ = restart_tx.send(()).await;
+                    return;
+                }
+            }
+        }
+
+        service.set_health(Some(status.clone())).await;
+    }
+}
+
+async fn probe(service: &Service, probe: &HealthProbe) -> Result<(), String> {
+    match probe {
+        HealthProbe::Http {
+            path,
+            expected_status,
+            port,
+        } => {
+            let port = resolve_port(service, *port)?;
+            let url = format!("http://127.0.0.1:{}{}", port, path);
+
+            let response = reqwest::Client::new()
+                .get(&url)
+                .send()
+                .await
+                .map_err(|e| format!("GET {} failed: {}", url, e))?;
+
+            if response.status().as_u16() != *expected_status {
+                return Err(format!(
+                    "GET {} returned {}, expected {}",
+                    url,
+                    response.status().as_u16(),
+                    expected_status
+                ));
+            }
+
+            Ok(())
+        }
+
+        HealthProbe::Tcp { port } => {
+            let port = resolve_port(service, *port)?;
+
+            tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("connect to port {} failed: {}", port, e))
+        }
+
+        HealthProbe::Exec { command } => {
+            let (program, args) = command
+                .split_first()
+                .ok_or_else(|| "empty health check command".to_string())?;
+
+            let status = Command::new(program)
+                .args(args)
+                .current_dir(&service.working_dir)
+                .envs(&service.env)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .kill_on_drop(true)
+                .status()
+                .await
+                .map_err(|e| format!("failed to run {}: {}", program, e))?;
+
+            if !status.success() {
+                return Err(format!("{} exited with {}", program, status));
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn resolve_port(service: &Service, port: Option<u16>) -> Result<u16, String> {
+    port.or_else(|| service.env.get("PORT").and_then(|p| p.parse().ok()))
+        .ok_or_else(|| "service has no port to probe".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::LogRotation, registry::ServiceDefinition};
+
+    fn service(name: &str, port: Option<u16>) -> Service {
+        let mut definition = serde_json::json!({ "id": name, "name": name });
+        if let Some(port) = port {
+            definition["env"] = serde_json::json!({ "PORT": port.to_string() });
+        }
+        let def: ServiceDefinition = serde_json::from_value(definition).unwrap();
+        let working_dir = std::env::temp_dir()
+            .join(format!("dockless-health-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned();
+        std::fs::create_dir_all(&working_dir).unwrap();
+        let log_buffer = Service::create_log_buffer(&def, &working_dir, &LogRotation::default());
+        Service::new(&def, def.env.clone(), working_dir, log_buffer)
+    }
+
+    /// A local port nothing listens on.
+    async fn closed_port() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn tcp_probe_connects_to_the_service_port() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = service("tcp", Some(port));
+
+        assert!(
+            probe(&service, &HealthProbe::Tcp { port: None })
+                .await
+                .is_ok()
+        );
+
+        drop(listener);
+        let error = probe(&service, &HealthProbe::Tcp { port: None })
+            .await
+            .unwrap_err();
+        assert!(error.contains(&port.to_string()));
+    }
+
+    #[tokio::test]
+    async fn exec_probe_checks_the_exit_status() {
+        let service = service("exec", None);
+        let command = |program: &str| HealthProbe::Exec {
+            command: vec![program.to_string()],
+        };
+
+        assert!(probe(&service, &command("true")).await.is_ok());
+        assert!(probe(&service, &command("false")).await.is_err());
+        assert!(
+            probe(&service, &HealthProbe::Tcp { port: None })
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn restarts_after_the_configured_number_of_failures() {
+        let service = service("failing", Some(closed_port().await));
+        let check: HealthCheck = serde_json::from_value(serde_json::json!({
+            "type": "tcp",
+            "interval_secs": 1,
+            "retries": 1,
+            "restart_after_failures": 2,
+        }))
+        .unwrap();
+
+        let (restart_tx, mut restart_rx) = mpsc::channel(1);
+        let _monitor = HealthMonitor::spawn(service.clone(), check, restart_tx);
+
+        timeout(Duration::from_secs(10), restart_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let health = service.get_health().await.unwrap();
+        assert_eq!(health.status, HealthState::Unhealthy);
+        assert_eq!(health.failing_streak, 2);
+        assert!(health.last_error.is_some());
+    }
+}
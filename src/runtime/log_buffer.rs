@@ -1,70 +1,190 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::VecDeque;
+use std::io::{BufRead, Read, Seek, SeekFrom};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{RwLock, broadcast, mpsc, oneshot};
+use tokio::time::{Duration, MissedTickBehavior, interval};
+
+use crate::config::LogRotation;
+use crate::registry::LogFormat;
+use crate::runtime::log_parser::{self, ParsedLine};
+use crate::runtime::log_query::{LogPage, LogQuery, MAX_TAIL};
+use crate::runtime::log_rotation::{self, RotationPolicy};
 
 const MAX_BUFFER_LINES: usize = 100;
 
+/// Lines waiting to be written. Further lines are dropped, and counted, until
+/// the writer catches up, so a slow disk never stalls a service's output.
+const WRITE_QUEUE_LINES: usize = 4096;
+
+/// Lines written before the file is flushed regardless of the interval.
+const FLUSH_BATCH_LINES: usize = 256;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Entries a live subscriber may fall behind by before it misses some.
+const LIVE_CAPACITY: usize = 1024;
+
+/// How much of the end of the log file is read to find the last sequence
+/// number on startup.
+const LAST_SEQ_SCAN_BYTES: u64 = 64 * 1024;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
+    /// Increases by one with every entry of the service, across dockless
+    /// restarts. Entries written before it was introduced have 0.
+    #[serde(default)]
+    pub seq: u64,
     pub timestamp: String,
     pub level: String,
     pub message: String,
+    /// Module or logger the line came from, for services whose output is
+    /// parsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Structured fields of a parsed line other than the ones above.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
 }
 
 #[derive(Clone)]
 pub struct LogBuffer {
     logs: Arc<RwLock<VecDeque<LogEntry>>>,
     log_file_path: PathBuf,
+    writer: mpsc::Sender<WriterCommand>,
+    dropped: Arc<AtomicU64>,
+    last_seq: Arc<AtomicU64>,
+    live: broadcast::Sender<LogEntry>,
+}
+
+enum WriterCommand {
+    Write(LogEntry),
+    /// Flush everything written so far, so that reads see it.
+    Flush(oneshot::Sender<()>),
+    /// Empty the log file and delete its rotated files.
+    Clear(oneshot::Sender<std::io::Result<()>>),
+    /// Rotate by these settings from now on.
+    SetPolicy(RotationPolicy),
 }
 
 impl LogBuffer {
-    pub fn new(log_file_path: PathBuf) -> Self {
+    /// Creates the buffer and starts the task that writes its log file. The
+    /// task ends once every clone of the buffer is dropped.
+    pub fn new(log_file_path: PathBuf, rotation: &LogRotation) -> Self {
+        let (writer, commands) = mpsc::channel(WRITE_QUEUE_LINES);
+        let last_seq = last_seq(&log_file_path);
+        let log_file = LogFile::new(log_file_path.clone(), RotationPolicy::new(rotation));
+        tokio::spawn(log_file.run(commands));
+
         Self {
             logs: Arc::new(RwLock::new(VecDeque::with_capacity(MAX_BUFFER_LINES))),
             log_file_path,
+            writer,
+            dropped: Arc::new(AtomicU64::new(0)),
+            last_seq: Arc::new(AtomicU64::new(last_seq)),
+            live: broadcast::channel(LIVE_CAPACITY).0,
         }
     }
 
     pub async fn push(&self, level: String, message: String) {
-        let entry = LogEntry {
-            timestamp: chrono::Utc::now().to_rfc3339(),
-            level: level.clone(),
-            message: message.clone(),
-        };
+        self.append(
+            level,
+            ParsedLine {
+                message,
+                ..ParsedLine::default()
+            },
+        )
+        .await;
+    }
 
-        {
+    /// Pushes a line the service wrote, parsed according to `format`.
+    /// `level` is used unless the line carries its own.
+    pub async fn push_output(&self, level: &str, line: String, format: LogFormat) {
+        match log_parser::parse(&line, format) {
+            Some(parsed) => self.append(level.to_string(), parsed).await,
+            None => self.push(level.to_string(), line).await,
+        }
+    }
+
+    async fn append(&self, default_level: String, line: ParsedLine) {
+        let entry = {
+            // Numbering under the lock keeps the buffer and the live
+            // subscribers in sequence order.
             let mut logs = self.logs.write().await;
+            let entry = LogEntry {
+                seq: self.last_seq.fetch_add(1, Ordering::Relaxed) + 1,
+                timestamp: line
+                    .timestamp
+                    .unwrap_or_else(|| chrono::Utc::now().to_rfc3339()),
+                level: line.level.unwrap_or(default_level),
+                message: line.message,
+                target: line.target,
+                fields: line.fields,
+            };
+
             if logs.len() >= MAX_BUFFER_LINES {
                 logs.pop_front();
             }
             logs.push_back(entry.clone());
-        }
+            // Fails only when nobody is subscribed.
+            let _ = self.live.send(entry.clone());
+            entry
+        };
 
-        if let Err(e) = self.write_to_file(&entry).await {
-            eprintln!("Failed to write log to file: {}", e);
+        if self.writer.try_send(WriterCommand::Write(entry)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
         }
     }
 
-    async fn write_to_file(&self, entry: &LogEntry) -> std::io::Result<()> {
-        let json_line = serde_json::to_string(entry)?;
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.log_file_path)
-            .await?;
-        file.write_all(format!("{}\n", json_line).as_bytes())
-            .await?;
-        file.flush().await?;
-        Ok(())
+    /// Lines that were not written to the log file because the writer fell
+    /// behind.
+    pub fn dropped_lines(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Runs `query` against the log file and its rotated files, once every
+    /// line pushed so far has been written.
+    pub async fn query(&self, query: LogQuery) -> std::io::Result<LogPage> {
+        self.flush().await;
+
+        let path = self.log_file_path.clone();
+        tokio::task::spawn_blocking(move || query.run(&path))
+            .await
+            .map_err(std::io::Error::other)?
     }
 
-    pub async fn get_all(&self) -> Vec<LogEntry> {
-        match self.read_from_file().await {
-            Ok(logs) => logs,
+    pub async fn get_recent(&self) -> Vec<LogEntry> {
+        self.logs.read().await.iter().cloned().collect()
+    }
+
+    /// Entries after `seq`, from memory when they are still buffered and
+    /// from the log file otherwise. At most the newest [`MAX_TAIL`] are read
+    /// from the file.
+    pub async fn get_since(&self, seq: u64) -> Vec<LogEntry> {
+        if seq >= self.last_seq.load(Ordering::Relaxed) {
+            return vec![];
+        }
+
+        {
+            let logs = self.logs.read().await;
+            if logs.front().is_some_and(|e| e.seq <= seq + 1) {
+                return logs.iter().filter(|e| e.seq > seq).cloned().collect();
+            }
+        }
+
+        let query = LogQuery {
+            tail: MAX_TAIL,
+            after: Some(seq),
+            ..LogQuery::default()
+        };
+        match self.query(query).await {
+            Ok(page) => page.entries,
             Err(e) => {
                 eprintln!("Failed to read logs from file: {}", e);
                 vec![]
@@ -72,38 +192,392 @@ impl LogBuffer {
         }
     }
 
-    pub async fn get_recent(&self) -> Vec<LogEntry> {
-        self.logs.read().await.iter().cloned().collect()
+    /// Receives every entry pushed from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
+        self.live.subscribe()
+    }
+
+    /// Waits until every line pushed so far is in the log file.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.writer.send(WriterCommand::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Applies new rotation settings, for a service whose definition changed.
+    pub async fn set_rotation(&self, rotation: &LogRotation) {
+        let _ = self
+            .writer
+            .send(WriterCommand::SetPolicy(RotationPolicy::new(rotation)))
+            .await;
+    }
+
+    pub async fn clear(&self) -> std::io::Result<()> {
+        self.logs.write().await.clear();
+
+        let (done, cleared) = oneshot::channel();
+        self.writer
+            .send(WriterCommand::Clear(done))
+            .await
+            .map_err(|_| std::io::Error::other("log writer stopped"))?;
+        cleared
+            .await
+            .map_err(|_| std::io::Error::other("log writer stopped"))?
+    }
+}
+
+/// The log file as seen by the writer task, which owns its handle.
+struct LogFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    handle: Option<BufWriter<File>>,
+    size: u64,
+    started_at: SystemTime,
+    unflushed: usize,
+}
+
+impl LogFile {
+    fn new(path: PathBuf, policy: RotationPolicy) -> Self {
+        let metadata = std::fs::metadata(&path).ok();
+        Self {
+            size: metadata.as_ref().map_or(0, |m| m.len()),
+            started_at: metadata
+                .and_then(|m| m.created().ok())
+                .unwrap_or_else(SystemTime::now),
+            path,
+            policy,
+            handle: None,
+            unflushed: 0,
+        }
     }
 
-    async fn read_from_file(&self) -> std::io::Result<Vec<LogEntry>> {
-        use tokio::io::AsyncBufReadExt;
+    async fn run(mut self, mut commands: mpsc::Receiver<WriterCommand>) {
+        let mut ticker = interval(FLUSH_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-        let file = match tokio::fs::File::open(&self.log_file_path).await {
-            Ok(f) => f,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                return Ok(vec![]);
+        loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(WriterCommand::Write(entry)) => {
+                        if let Err(e) = self.write(&entry).await {
+                            eprintln!("Failed to write log to file: {}", e);
+                            // Reopen on the next line.
+                            self.handle = None;
+                        }
+                    }
+                    Some(WriterCommand::Flush(done)) => {
+                        self.flush().await;
+                        let _ = done.send(());
+                    }
+                    Some(WriterCommand::Clear(done)) => {
+                        let _ = done.send(self.clear().await);
+                    }
+                    Some(WriterCommand::SetPolicy(policy)) => self.policy = policy,
+                    None => break,
+                },
+                _ = ticker.tick() => self.flush().await,
             }
-            Err(e) => return Err(e),
-        };
+        }
 
-        let reader = tokio::io::BufReader::new(file);
-        let mut lines = reader.lines();
-        let mut logs = Vec::new();
+        self.flush().await;
+    }
+
+    async fn write(&mut self, entry: &LogEntry) -> std::io::Result<()> {
+        let line = format!("{}\n", serde_json::to_string(entry)?);
 
-        while let Some(line) = lines.next_line().await? {
-            if let Ok(entry) = serde_json::from_str::<LogEntry>(&line) {
-                logs.push(entry);
+        if self
+            .policy
+            .is_due(self.size, line.len() as u64, self.started_at)
+        {
+            self.flush().await;
+            self.handle = None;
+            match log_rotation::rotate(&self.path, &self.policy) {
+                Ok(()) => {
+                    self.size = 0;
+                    self.started_at = SystemTime::now();
+                }
+                Err(e) => eprintln!("Failed to rotate log file: {}", e),
             }
         }
 
-        Ok(logs)
+        let handle = match &mut self.handle {
+            Some(handle) => handle,
+            None => self.handle.insert(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?,
+            )),
+        };
+        handle.write_all(line.as_bytes()).await?;
+        self.size += line.len() as u64;
+        self.unflushed += 1;
+
+        if self.unflushed >= FLUSH_BATCH_LINES {
+            self.flush().await;
+        }
+        Ok(())
     }
 
-    pub async fn clear(&self) -> std::io::Result<()> {
-        self.logs.write().await.clear();
+    async fn flush(&mut self) {
+        if self.unflushed == 0 {
+            return;
+        }
+        self.unflushed = 0;
+
+        if let Some(handle) = &mut self.handle
+            && let Err(e) = handle.flush().await
+        {
+            eprintln!("Failed to flush log file: {}", e);
+            self.handle = None;
+        }
+    }
+
+    async fn clear(&mut self) -> std::io::Result<()> {
+        self.flush().await;
+        self.handle = None;
 
-        tokio::fs::write(&self.log_file_path, "").await?;
+        tokio::fs::write(&self.path, "").await?;
+        log_rotation::remove_all(&self.path);
+        self.size = 0;
+        self.started_at = SystemTime::now();
         Ok(())
     }
 }
+
+/// Sequence number of the last entry in the log at `path`, or in its newest
+/// rotated file when the log itself is empty.
+fn last_seq(path: &std::path::Path) -> u64 {
+    let from_tail = std::fs::File::open(path).ok().and_then(|mut file| {
+        let len = file.metadata().ok()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(LAST_SEQ_SCAN_BYTES)))
+            .ok()?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).ok()?;
+        String::from_utf8_lossy(&tail)
+            .lines()
+            .rev()
+            .find_map(|line| serde_json::from_str::<LogEntry>(line).ok())
+            .map(|entry| entry.seq)
+    });
+
+    from_tail
+        .or_else(|| {
+            let segment = log_rotation::segments(path).pop()?;
+            let mut entries = Vec::new();
+            read_entries(segment.open().ok()?, &mut entries).ok()?;
+            entries.last().map(|entry| entry.seq)
+        })
+        .unwrap_or(0)
+}
+
+fn read_entries(reader: impl BufRead, logs: &mut Vec<LogEntry>) -> std::io::Result<()> {
+    for line in reader.lines() {
+        if let Ok(entry) = serde_json::from_str::<LogEntry>(&line?) {
+            logs.push(entry);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogCompression;
+
+    fn log_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "dockless-log-buffer-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("service.log")
+    }
+
+    fn rotation(max_size: &str, compression: LogCompression) -> LogRotation {
+        LogRotation {
+            max_size: max_size.to_string(),
+            compression,
+            ..LogRotation::default()
+        }
+    }
+
+    async fn push_lines(buffer: &LogBuffer, count: usize) {
+        for i in 0..count {
+            buffer.push("info".to_string(), format!("line {}", i)).await;
+        }
+    }
+
+    fn seqs(entries: &[LogEntry]) -> Vec<u64> {
+        entries.iter().map(|e| e.seq).collect()
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers_continue_across_restarts_and_rotation() {
+        let path = log_path("resume");
+        let rotation = rotation("10M", LogCompression::Gzip);
+
+        let buffer = LogBuffer::new(path.clone(), &rotation);
+        push_lines(&buffer, 3).await;
+        buffer.flush().await;
+        drop(buffer);
+
+        let buffer = LogBuffer::new(path.clone(), &rotation);
+        push_lines(&buffer, 1).await;
+        assert_eq!(seqs(&buffer.get_recent().await), vec![4]);
+        buffer.flush().await;
+        drop(buffer);
+
+        // With the live file rotated away, the numbering continues from the
+        // newest rotated file once it is compressed.
+        log_rotation::rotate(&path, &RotationPolicy::new(&rotation)).unwrap();
+        for _ in 0..100 {
+            if log_rotation::segments(&path)
+                .first()
+                .is_some_and(|s| s.is_compressed())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let buffer = LogBuffer::new(path.clone(), &rotation);
+        push_lines(&buffer, 1).await;
+        assert_eq!(seqs(&buffer.get_recent().await), vec![5]);
+
+        let page = buffer
+            .query(LogQuery {
+                tail: 100,
+                ..LogQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(seqs(&page.entries), vec![1, 2, 3, 4, 5]);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotates_while_writing_without_losing_lines() {
+        let path = log_path("rotate");
+        let buffer = LogBuffer::new(path.clone(), &rotation("2K", LogCompression::None));
+        push_lines(&buffer, 100).await;
+
+        let page = buffer
+            .query(LogQuery {
+                tail: 1000,
+                ..LogQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(seqs(&page.entries), (1..=100).collect::<Vec<_>>());
+        assert!(!log_rotation::segments(&path).is_empty());
+        assert!(std::fs::metadata(&path).unwrap().len() <= 2048);
+        assert_eq!(buffer.dropped_lines(), 0);
+
+        buffer.clear().await.unwrap();
+        assert!(log_rotation::segments(&path).is_empty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        assert!(buffer.get_recent().await.is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_since_falls_back_to_the_file() {
+        let path = log_path("since");
+        let buffer = LogBuffer::new(path.clone(), &LogRotation::default());
+        push_lines(&buffer, MAX_BUFFER_LINES + 50).await;
+
+        let last = (MAX_BUFFER_LINES + 50) as u64;
+        assert_eq!(seqs(&buffer.get_recent().await)[0], 51);
+        assert_eq!(
+            seqs(&buffer.get_since(last - 2).await),
+            vec![last - 1, last]
+        );
+        assert_eq!(
+            seqs(&buffer.get_since(10).await),
+            (11..=last).collect::<Vec<_>>()
+        );
+        assert!(buffer.get_since(last).await.is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_every_entry_in_order() {
+        let path = log_path("live");
+        let buffer = LogBuffer::new(path.clone(), &LogRotation::default());
+        push_lines(&buffer, 2).await;
+
+        let mut live = buffer.subscribe();
+        push_lines(&buffer, 3).await;
+        for seq in 3..=5 {
+            assert_eq!(live.recv().await.unwrap().seq, seq);
+        }
+        assert!(live.try_recv().is_err());
+
+        // A reconnecting client reads what it missed, then carries on live.
+        let mut live = buffer.subscribe();
+        push_lines(&buffer, 1).await;
+        assert_eq!(seqs(&buffer.get_since(3).await), vec![4, 5, 6]);
+        assert_eq!(live.recv().await.unwrap().seq, 6);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_learn_how_much_they_missed() {
+        let path = log_path("lagged");
+        let buffer = LogBuffer::new(path.clone(), &LogRotation::default());
+        let mut live = buffer.subscribe();
+        push_lines(&buffer, LIVE_CAPACITY + 10).await;
+
+        assert!(matches!(
+            live.recv().await,
+            Err(broadcast::error::RecvError::Lagged(10))
+        ));
+        assert_eq!(live.recv().await.unwrap().seq, 11);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parsed_output_keeps_its_level_and_fields() {
+        let path = log_path("parsed");
+        let buffer = LogBuffer::new(path.clone(), &LogRotation::default());
+        buffer
+            .push_output(
+                "info",
+                r#"{"level":"error","target":"app::db","msg":"query failed","table":"users"}"#
+                    .to_string(),
+                LogFormat::Json,
+            )
+            .await;
+        buffer
+            .push_output("stderr", "not json".to_string(), LogFormat::Json)
+            .await;
+
+        let recent = buffer.get_recent().await;
+        assert_eq!(recent[0].level, "error");
+        assert_eq!(recent[0].target.as_deref(), Some("app::db"));
+        assert_eq!(recent[0].message, "query failed");
+        assert_eq!(recent[1].level, "stderr");
+        assert_eq!(recent[1].message, "not json");
+
+        let page = buffer
+            .query(LogQuery {
+                tail: 10,
+                fields: vec![("table".to_string(), "users".to_string())],
+                ..LogQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(seqs(&page.entries), vec![1]);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
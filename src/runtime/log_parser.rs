@@ -0,0 +1,320 @@
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::registry::LogFormat;
+
+const MESSAGE_KEYS: &[&str] = &["message", "msg"];
+const LEVEL_KEYS: &[&str] = &["level", "lvl", "severity"];
+const TIMESTAMP_KEYS: &[&str] = &["timestamp", "time", "ts"];
+const TARGET_KEYS: &[&str] = &["target", "logger"];
+
+/// What a structured line says about itself. Everything it carries besides
+/// the message, level, timestamp and target ends up in `fields`.
+#[derive(Debug, Default)]
+pub struct ParsedLine {
+    pub level: Option<String>,
+    /// RFC 3339, in UTC.
+    pub timestamp: Option<String>,
+    pub target: Option<String>,
+    pub message: String,
+    pub fields: Map<String, Value>,
+}
+
+/// Parses `line` according to `format`. `None` means the line is to be kept
+/// as plain text.
+pub fn parse(line: &str, format: LogFormat) -> Option<ParsedLine> {
+    match format {
+        LogFormat::Plain => None,
+        LogFormat::Json => parse_json(line),
+        LogFormat::Logfmt => parse_logfmt(line),
+        LogFormat::Auto => parse_json(line).or_else(|| parse_logfmt(line)),
+    }
+}
+
+fn parse_json(line: &str) -> Option<ParsedLine> {
+    let line = line.trim();
+    if !line.starts_with('{') {
+        return None;
+    }
+    let Ok(Value::Object(mut map)) = serde_json::from_str(line) else {
+        return None;
+    };
+
+    // `tracing_subscriber` nests the event's fields, message included, under
+    // `fields` unless it is set up to flatten them.
+    match map.remove("fields") {
+        Some(Value::Object(nested)) => {
+            for (key, value) in nested {
+                map.entry(key).or_insert(value);
+            }
+        }
+        Some(other) => {
+            map.insert("fields".to_string(), other);
+        }
+        None => {}
+    }
+
+    let message = take(&mut map, MESSAGE_KEYS, |v| v.as_str().map(str::to_string));
+
+    Some(ParsedLine {
+        level: take(&mut map, LEVEL_KEYS, |v| match v {
+            Value::String(s) => Some(normalize_level(s)),
+            // pino and bunyan number their levels.
+            Value::Number(n) => n.as_u64().and_then(numeric_level),
+            _ => None,
+        }),
+        timestamp: take(&mut map, TIMESTAMP_KEYS, |v| match v {
+            Value::String(s) => parse_timestamp(s),
+            Value::Number(n) => n.as_f64().and_then(epoch_timestamp),
+            _ => None,
+        }),
+        target: take(&mut map, TARGET_KEYS, |v| v.as_str().map(str::to_string)),
+        // Without a message, the line itself is the best summary.
+        message: message.unwrap_or_else(|| line.to_string()),
+        fields: map,
+    })
+}
+
+fn parse_logfmt(line: &str) -> Option<ParsedLine> {
+    let pairs = logfmt_pairs(line)?;
+    // Plain text can happen to look like `key=value` pairs, so only lines
+    // that name a level or a message count.
+    if !pairs
+        .iter()
+        .any(|(key, _)| MESSAGE_KEYS.contains(&key.as_str()) || LEVEL_KEYS.contains(&key.as_str()))
+    {
+        return None;
+    }
+
+    let mut map: Map<String, Value> = pairs
+        .into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect();
+    let mut take_str = |keys: &[&str], convert: fn(&str) -> Option<String>| {
+        take(&mut map, keys, |v| v.as_str().and_then(convert))
+    };
+
+    let message = take_str(MESSAGE_KEYS, |s| Some(s.to_string()));
+    let level = take_str(LEVEL_KEYS, |s| Some(normalize_level(s)));
+    let timestamp = take_str(TIMESTAMP_KEYS, parse_timestamp);
+    let target = take_str(TARGET_KEYS, |s| Some(s.to_string()));
+
+    Some(ParsedLine {
+        level,
+        timestamp,
+        target,
+        message: message.unwrap_or_default(),
+        fields: map,
+    })
+}
+
+/// Splits a logfmt line into its pairs, or returns `None` if any part of it
+/// is not a `key=value` pair.
+fn logfmt_pairs(line: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while chars.peek().is_some() {
+        let key: String =
+            std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '"'))
+                .collect();
+        if key.is_empty() || chars.next() != Some('=') {
+            return None;
+        }
+
+        let value = if chars.next_if_eq(&'"').is_some() {
+            let mut value = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        c => value.push(c),
+                    },
+                    c => value.push(c),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return None;
+            }
+            value
+        } else {
+            std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect()
+        };
+        pairs.push((key, value));
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    (!pairs.is_empty()).then_some(pairs)
+}
+
+/// Removes the first of `keys` whose value `convert` accepts, and returns the
+/// converted value. Values it does not accept stay in `map` as fields.
+fn take<T>(
+    map: &mut Map<String, Value>,
+    keys: &[&str],
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Option<T> {
+    keys.iter().find_map(|key| {
+        let converted = convert(map.get(*key)?)?;
+        map.remove(*key);
+        Some(converted)
+    })
+}
+
+/// Maps the spellings loggers use onto `trace`, `debug`, `info`, `warn` and
+/// `error`. Anything else is kept, in lowercase.
+fn normalize_level(level: &str) -> String {
+    let level = level.trim().to_ascii_lowercase();
+    match level.as_str() {
+        "warning" => "warn".to_string(),
+        "err" | "fatal" | "critical" | "crit" | "panic" | "alert" | "emerg" => "error".to_string(),
+        _ => level,
+    }
+}
+
+fn numeric_level(level: u64) -> Option<String> {
+    let level = match level {
+        10 => "trace",
+        20 => "debug",
+        30 => "info",
+        40 => "warn",
+        50 | 60 => "error",
+        _ => return None,
+    };
+    Some(level.to_string())
+}
+
+fn parse_timestamp(value: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc).to_rfc3339())
+}
+
+/// Unix time in seconds, or in milliseconds as JavaScript loggers write it.
+fn epoch_timestamp(value: f64) -> Option<String> {
+    let millis = if value > 1e11 { value } else { value * 1000.0 };
+    DateTime::<Utc>::from_timestamp_millis(millis as i64).map(|t| t.to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn logfmt_pairs_handles_quotes_and_escapes() {
+        assert_eq!(
+            logfmt_pairs(r#"level=info msg="hello \"world\"\n" empty= path=/a/b"#).unwrap(),
+            owned(&[
+                ("level", "info"),
+                ("msg", "hello \"world\"\n"),
+                ("empty", ""),
+                ("path", "/a/b"),
+            ])
+        );
+    }
+
+    #[test]
+    fn logfmt_pairs_rejects_plain_text() {
+        assert!(logfmt_pairs("").is_none());
+        assert!(logfmt_pairs("just some words").is_none());
+        assert!(logfmt_pairs("key=value trailing").is_none());
+        assert!(logfmt_pairs("=value").is_none());
+        assert!(logfmt_pairs(r#"msg="unterminated"#).is_none());
+        assert!(logfmt_pairs(r#"msg="quoted"junk"#).is_none());
+    }
+
+    #[test]
+    fn parses_json_lines() {
+        let line = r#"{"timestamp":"2026-01-01T01:00:00+01:00","level":"WARNING","target":"app::db","fields":{"message":"slow query","ms":250},"request_id":"abc"}"#;
+        let parsed = parse(line, LogFormat::Json).unwrap();
+
+        assert_eq!(parsed.level.as_deref(), Some("warn"));
+        assert_eq!(
+            parsed.timestamp.as_deref(),
+            Some("2026-01-01T00:00:00+00:00")
+        );
+        assert_eq!(parsed.target.as_deref(), Some("app::db"));
+        assert_eq!(parsed.message, "slow query");
+        assert_eq!(
+            Value::Object(parsed.fields),
+            json!({ "ms": 250, "request_id": "abc" })
+        );
+    }
+
+    #[test]
+    fn parses_numeric_levels_and_epoch_timestamps() {
+        let parsed = parse(
+            r#"{"level":50,"time":1767225600000,"msg":"boom"}"#,
+            LogFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(parsed.level.as_deref(), Some("error"));
+        assert_eq!(
+            parsed.timestamp.as_deref(),
+            Some("2026-01-01T00:00:00+00:00")
+        );
+
+        let parsed = parse(r#"{"ts":1767225600.5,"message":"x"}"#, LogFormat::Json).unwrap();
+        assert_eq!(
+            parsed.timestamp.as_deref(),
+            Some("2026-01-01T00:00:00.500+00:00")
+        );
+    }
+
+    #[test]
+    fn keeps_values_it_cannot_interpret_as_fields() {
+        let parsed = parse(r#"{"level":{"n":1},"msg":"m"}"#, LogFormat::Json).unwrap();
+        assert_eq!(parsed.level, None);
+        assert_eq!(Value::Object(parsed.fields), json!({ "level": { "n": 1 } }));
+
+        let parsed = parse(r#"{"no":"message"}"#, LogFormat::Json).unwrap();
+        assert_eq!(parsed.message, r#"{"no":"message"}"#);
+    }
+
+    #[test]
+    fn parses_logfmt_lines() {
+        let line = r#"time=2026-01-01T00:00:00Z level=ERR logger=worker msg="job failed" job=42"#;
+        let parsed = parse(line, LogFormat::Logfmt).unwrap();
+
+        assert_eq!(parsed.level.as_deref(), Some("error"));
+        assert_eq!(
+            parsed.timestamp.as_deref(),
+            Some("2026-01-01T00:00:00+00:00")
+        );
+        assert_eq!(parsed.target.as_deref(), Some("worker"));
+        assert_eq!(parsed.message, "job failed");
+        assert_eq!(Value::Object(parsed.fields), json!({ "job": "42" }));
+    }
+
+    #[test]
+    fn logfmt_needs_a_level_or_message() {
+        assert!(parse("a=1 b=2", LogFormat::Logfmt).is_none());
+    }
+
+    #[test]
+    fn auto_tries_json_then_logfmt() {
+        assert_eq!(
+            parse(r#"{"msg":"from json"}"#, LogFormat::Auto)
+                .unwrap()
+                .message,
+            "from json"
+        );
+        assert_eq!(
+            parse("msg=from_logfmt", LogFormat::Auto).unwrap().message,
+            "from_logfmt"
+        );
+        assert!(parse("Listening on :8080", LogFormat::Auto).is_none());
+        assert!(parse(r#"{"msg":"x"}"#, LogFormat::Plain).is_none());
+    }
+}
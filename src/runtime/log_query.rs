@@ -0,0 +1,648 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::runtime::{log_buffer::LogEntry, log_rotation};
+
+/// Entries returned when a query does not set `tail`.
+pub const DEFAULT_TAIL: usize = 1000;
+
+/// Most entries one query returns, which bounds its memory.
+pub const MAX_TAIL: usize = 10_000;
+
+/// How much of an uncompressed log file is read at a time, from the end.
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Text an entry's message has to contain.
+pub enum Pattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Substring(needle) => text.contains(needle.as_str()),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Selects the newest `tail` entries of a service log that pass every filter.
+#[derive(Default)]
+pub struct LogQuery {
+    pub tail: usize,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Levels to keep, in lowercase. Empty keeps every level.
+    pub levels: Vec<String>,
+    pub pattern: Option<Pattern>,
+    /// Target the entry has to equal or be nested in, e.g. `app::db` also
+    /// matches `app::db::pool`.
+    pub target: Option<String>,
+    /// Fields the entry has to carry with these values. A name with dots
+    /// looks into nested objects.
+    pub fields: Vec<(String, String)>,
+    /// Only entries before this sequence number, i.e. the cursor of the
+    /// previous page.
+    pub before: Option<u64>,
+    /// Only entries after this sequence number.
+    pub after: Option<u64>,
+}
+
+/// One page of a query, oldest entry first.
+pub struct LogPage {
+    pub entries: Vec<LogEntry>,
+    /// Passed as `before` to get the page of older entries, if there is one.
+    pub next_cursor: Option<u64>,
+}
+
+enum Verdict {
+    Keep,
+    Skip,
+    /// The entry and everything older is out of range.
+    Stop,
+}
+
+impl LogQuery {
+    fn check(&self, entry: &LogEntry) -> Verdict {
+        // Entries from before sequence numbers were introduced have 0 and
+        // count as older than every numbered one.
+        if entry.seq > 0 {
+            if self.after.is_some_and(|after| entry.seq <= after) {
+                return Verdict::Stop;
+            }
+            if self.before.is_some_and(|before| entry.seq >= before) {
+                return Verdict::Skip;
+            }
+        }
+
+        if self.since.is_some() || self.until.is_some() {
+            let Ok(timestamp) = DateTime::parse_from_rfc3339(&entry.timestamp) else {
+                return Verdict::Skip;
+            };
+            // Timestamps of parsed lines come from the service, so they are
+            // not necessarily in order and cannot end the search.
+            if self.since.is_some_and(|since| timestamp < since) {
+                return Verdict::Skip;
+            }
+            if self.until.is_some_and(|until| timestamp > until) {
+                return Verdict::Skip;
+            }
+        }
+
+        if !self.levels.is_empty() && !self.levels.contains(&entry.level.to_ascii_lowercase()) {
+            return Verdict::Skip;
+        }
+
+        if let Some(pattern) = &self.pattern
+            && !pattern.matches(&entry.message)
+        {
+            return Verdict::Skip;
+        }
+
+        if let Some(target) = &self.target
+            && !entry.target.as_deref().is_some_and(|t| {
+                t.strip_prefix(target.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+        {
+            return Verdict::Skip;
+        }
+
+        if !self
+            .fields
+            .iter()
+            .all(|(name, value)| field_matches(&entry.fields, name, value))
+        {
+            return Verdict::Skip;
+        }
+
+        Verdict::Keep
+    }
+
+    /// Runs the query against the log at `log_path` and its rotated files.
+    /// Uncompressed files are read backwards from the end, and reading stops
+    /// as soon as the page is full, so only the entries returned are held in
+    /// memory. Rotated files last written before `since` are not read at all.
+    /// Blocking.
+    pub fn run(&self, log_path: &Path) -> io::Result<LogPage> {
+        let mut page = PageBuilder {
+            query: self,
+            // One more than asked for tells whether there is another page.
+            wanted: self.tail + 1,
+            found: Vec::new(),
+            stopped: false,
+        };
+
+        page.read_backwards(log_path)?;
+
+        for segment in log_rotation::segments(log_path).iter().rev() {
+            if page.is_done()
+                || self
+                    .since
+                    .is_some_and(|since| modified_before(&segment.path, since))
+            {
+                break;
+            }
+            let read = if segment.is_compressed() {
+                segment.open().and_then(|reader| page.read_forwards(reader))
+            } else {
+                page.read_backwards(&segment.path)
+            };
+            match read {
+                Ok(()) => {}
+                // Deleted by retention since it was listed.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(page.finish())
+    }
+}
+
+/// Collects matching entries newest first.
+struct PageBuilder<'a> {
+    query: &'a LogQuery,
+    wanted: usize,
+    found: Vec<LogEntry>,
+    stopped: bool,
+}
+
+impl PageBuilder<'_> {
+    fn is_done(&self) -> bool {
+        self.stopped || self.found.len() >= self.wanted
+    }
+
+    /// Offers the entry that precedes every entry offered so far.
+    fn offer(&mut self, entry: LogEntry) {
+        match self.query.check(&entry) {
+            Verdict::Keep => self.found.push(entry),
+            Verdict::Skip => {}
+            Verdict::Stop => self.stopped = true,
+        }
+    }
+
+    fn read_backwards(&mut self, path: &Path) -> io::Result<()> {
+        let lines = match ReverseLines::open(path) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        for line in lines {
+            if self.is_done() {
+                break;
+            }
+            if let Some(entry) = parse(&line?) {
+                self.offer(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a file that can only be read from the start, such as a
+    /// compressed segment, keeping just the newest entries that could still
+    /// make it onto the page.
+    fn read_forwards(&mut self, reader: impl BufRead) -> io::Result<()> {
+        let room = self.wanted - self.found.len();
+        let mut window = VecDeque::with_capacity(room.min(MAX_TAIL + 1));
+        let mut stopped = false;
+
+        for line in reader.split(b'\n') {
+            let Some(entry) = parse(&line?) else {
+                continue;
+            };
+            match self.query.check(&entry) {
+                Verdict::Keep => {
+                    if window.len() == room {
+                        window.pop_front();
+                    }
+                    window.push_back(entry);
+                }
+                Verdict::Skip => {}
+                // Read backwards, this is where the search would have ended,
+                // so nothing before it counts.
+                Verdict::Stop => {
+                    window.clear();
+                    stopped = true;
+                }
+            }
+        }
+
+        self.found.extend(window.into_iter().rev());
+        self.stopped |= stopped;
+        Ok(())
+    }
+
+    fn finish(mut self) -> LogPage {
+        let next_cursor = if self.found.len() > self.query.tail {
+            self.found.truncate(self.query.tail);
+            // An entry without a sequence number cannot be paged past.
+            self.found
+                .last()
+                .map(|entry| entry.seq)
+                .filter(|seq| *seq > 0)
+        } else {
+            None
+        };
+
+        self.found.reverse();
+        LogPage {
+            entries: self.found,
+            next_cursor,
+        }
+    }
+}
+
+/// Whether the file at `path` was last written before `time`. Rotated files
+/// are sorted by when they were rotated, so every older one was too.
+fn modified_before(path: &Path, time: DateTime<Utc>) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| DateTime::<Utc>::from(modified) < time)
+}
+
+/// Whether the field `name` of `fields` is `expected`. For values that are not
+/// strings, `expected` is read as JSON, e.g. `404` or `true`.
+fn field_matches(fields: &Map<String, Value>, name: &str, expected: &str) -> bool {
+    let value = fields.get(name).or_else(|| {
+        let mut parts = name.split('.');
+        let first = fields.get(parts.next()?)?;
+        parts.try_fold(first, |value, part| value.get(part))
+    });
+
+    match value {
+        Some(Value::String(s)) => s == expected,
+        Some(value) => serde_json::from_str::<Value>(expected).is_ok_and(|e| e == *value),
+        None => false,
+    }
+}
+
+fn parse(line: &[u8]) -> Option<LogEntry> {
+    serde_json::from_slice(line).ok()
+}
+
+/// Lines of a file from last to first. The file is read in blocks from the
+/// end, so only one block is held at a time. Lines appended after it was
+/// opened are not seen.
+struct ReverseLines {
+    file: File,
+    /// Where the part of the file not read yet ends.
+    pos: u64,
+    /// The start of the file's earliest line read so far, which may continue
+    /// in the block before.
+    partial: Vec<u8>,
+    /// Complete lines of the current block, in file order.
+    lines: Vec<Vec<u8>>,
+}
+
+impl ReverseLines {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let pos = file.metadata()?.len();
+        Ok(Self {
+            file,
+            pos,
+            partial: Vec::new(),
+            lines: Vec::new(),
+        })
+    }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        let len = self.pos.min(BLOCK_SIZE);
+        self.pos -= len;
+
+        let mut block = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        self.file.read_exact(&mut block)?;
+        block.extend_from_slice(&self.partial);
+
+        let mut pieces = block.split(|b| *b == b'\n');
+        self.partial = pieces.next().unwrap_or_default().to_vec();
+        self.lines = pieces
+            .filter(|line| !line.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        Ok(())
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.pop() {
+                return Some(Ok(line));
+            }
+            if self.pos == 0 {
+                return (!self.partial.is_empty()).then(|| Ok(std::mem::take(&mut self.partial)));
+            }
+            if let Err(e) = self.read_block() {
+                self.pos = 0;
+                self.partial.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Parses `value` as an RFC 3339 time, or as a duration before `now` such as
+/// `30s`, `15m`, `2h` or `7d`.
+pub fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .context("expected an RFC 3339 time or a duration like 15m")?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("invalid time '{}'", value))?;
+    let ago = match unit {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        _ => anyhow::bail!("invalid time '{}': unknown unit '{}'", value, unit),
+    }
+    .with_context(|| format!("invalid time '{}'", value))?;
+
+    Ok(now - ago)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_time_accepts_rfc3339_and_durations() {
+        let now = DateTime::parse_from_rfc3339("2026-01-02T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let exact = parse_time("2026-01-01T00:00:00+02:00", now).unwrap();
+        assert_eq!(exact.to_rfc3339(), "2025-12-31T22:00:00+00:00");
+
+        assert_eq!(
+            parse_time("30s", now).unwrap(),
+            now - chrono::Duration::seconds(30)
+        );
+        assert_eq!(
+            parse_time("15m", now).unwrap(),
+            now - chrono::Duration::minutes(15)
+        );
+        assert_eq!(
+            parse_time(" 2h ", now).unwrap(),
+            now - chrono::Duration::hours(2)
+        );
+        assert_eq!(
+            parse_time("7d", now).unwrap(),
+            now - chrono::Duration::days(7)
+        );
+    }
+
+    #[test]
+    fn parse_time_rejects_garbage() {
+        let now = Utc::now();
+        assert!(parse_time("15", now).is_err());
+        assert!(parse_time("m", now).is_err());
+        assert!(parse_time("15w", now).is_err());
+        assert!(parse_time("yesterday", now).is_err());
+        assert!(parse_time("99999999999999999d", now).is_err());
+    }
+
+    #[test]
+    fn field_matches_strings_json_and_nested_names() {
+        let fields = fields(json!({
+            "user": "alice",
+            "status": 404,
+            "cached": true,
+            "http": { "method": "GET", "route": { "name": "index" } },
+            "a.b": "flat",
+        }));
+
+        assert!(field_matches(&fields, "user", "alice"));
+        assert!(!field_matches(&fields, "user", "bob"));
+        assert!(field_matches(&fields, "status", "404"));
+        assert!(!field_matches(&fields, "status", "\"404\""));
+        assert!(field_matches(&fields, "cached", "true"));
+        assert!(field_matches(&fields, "http.method", "GET"));
+        assert!(field_matches(&fields, "http.route.name", "index"));
+        assert!(!field_matches(&fields, "http.route.missing", "index"));
+        assert!(field_matches(&fields, "a.b", "flat"));
+        assert!(!field_matches(&fields, "missing", "anything"));
+    }
+
+    fn reverse_lines(name: &str, content: &[u8]) -> Vec<Vec<u8>> {
+        let path = std::env::temp_dir().join(format!(
+            "dockless-log-query-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, content).unwrap();
+        let lines = ReverseLines::open(&path)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        lines
+    }
+
+    #[test]
+    fn reverse_lines_reads_last_line_first() {
+        assert_eq!(
+            reverse_lines("small", b"one\ntwo\n\nthree"),
+            vec![b"three".to_vec(), b"two".to_vec(), b"one".to_vec()]
+        );
+        assert!(reverse_lines("empty", b"").is_empty());
+    }
+
+    #[test]
+    fn reverse_lines_joins_lines_across_blocks() {
+        // Lines of different lengths put the block boundaries mid-line.
+        let lines: Vec<String> = (0..20_000)
+            .map(|i| format!("line {} {}", i, "x".repeat(i % 17)))
+            .collect();
+        let content = lines.join("\n") + "\n";
+        assert!(content.len() as u64 > 2 * BLOCK_SIZE);
+
+        let read = reverse_lines("blocks", content.as_bytes());
+        let expected: Vec<Vec<u8>> = lines.iter().rev().map(|l| l.as_bytes().to_vec()).collect();
+        assert_eq!(read, expected);
+    }
+
+    /// A log whose entries 1 to 12 are spread over a gzip segment, a zstd
+    /// segment, an uncompressed segment and the live file. Odd entries are
+    /// errors.
+    fn rotated_log(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "dockless-log-query-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let lines = |seqs: std::ops::RangeInclusive<u64>| -> Vec<u8> {
+            seqs.map(|seq| {
+                let entry = LogEntry {
+                    seq,
+                    timestamp: format!("2026-01-01T00:00:{:02}Z", seq),
+                    level: if seq % 2 == 1 { "error" } else { "info" }.to_string(),
+                    message: format!("request {}", seq),
+                    target: Some(if seq > 6 { "app::db" } else { "app" }.to_string()),
+                    fields: Map::new(),
+                };
+                format!("{}\n", serde_json::to_string(&entry).unwrap())
+            })
+            .collect::<String>()
+            .into_bytes()
+        };
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gzip, &lines(1..=3)).unwrap();
+        std::fs::write(
+            dir.join("service.log.20260101T000000000Z.gz"),
+            gzip.finish().unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("service.log.20260102T000000000Z.zst"),
+            zstd::encode_all(&lines(4..=6)[..], 0).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("service.log.20260103T000000000Z"), lines(7..=9)).unwrap();
+        std::fs::write(dir.join("service.log"), lines(10..=12)).unwrap();
+
+        dir.join("service.log")
+    }
+
+    fn seqs(page: &LogPage) -> Vec<u64> {
+        page.entries.iter().map(|e| e.seq).collect()
+    }
+
+    #[test]
+    fn pages_back_through_rotated_and_compressed_segments() {
+        let log = rotated_log("pages");
+        let page = |before: Option<u64>| {
+            LogQuery {
+                tail: 5,
+                before,
+                ..LogQuery::default()
+            }
+            .run(&log)
+            .unwrap()
+        };
+
+        let newest = page(None);
+        assert_eq!(seqs(&newest), vec![8, 9, 10, 11, 12]);
+        assert_eq!(newest.next_cursor, Some(8));
+
+        let older = page(newest.next_cursor);
+        assert_eq!(seqs(&older), vec![3, 4, 5, 6, 7]);
+        assert_eq!(older.next_cursor, Some(3));
+
+        let oldest = page(older.next_cursor);
+        assert_eq!(seqs(&oldest), vec![1, 2]);
+        assert_eq!(oldest.next_cursor, None);
+
+        std::fs::remove_dir_all(log.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn resumes_after_a_sequence_number() {
+        let log = rotated_log("after");
+        let after = |after: u64, tail: usize| {
+            LogQuery {
+                tail,
+                after: Some(after),
+                ..LogQuery::default()
+            }
+            .run(&log)
+            .unwrap()
+        };
+
+        assert_eq!(seqs(&after(10, 100)), vec![11, 12]);
+        assert_eq!(seqs(&after(2, 100)), (3..=12).collect::<Vec<_>>());
+        assert!(after(12, 100).entries.is_empty());
+
+        // A page that cannot hold everything keeps the newest entries.
+        let page = after(2, 4);
+        assert_eq!(seqs(&page), vec![9, 10, 11, 12]);
+        assert_eq!(page.next_cursor, Some(9));
+
+        std::fs::remove_dir_all(log.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn filters_across_segments() {
+        let log = rotated_log("filters");
+        let run = |query: LogQuery| seqs(&query.run(&log).unwrap());
+
+        assert_eq!(
+            run(LogQuery {
+                tail: 3,
+                levels: vec!["error".to_string()],
+                ..LogQuery::default()
+            }),
+            vec![7, 9, 11]
+        );
+        assert_eq!(
+            run(LogQuery {
+                tail: 100,
+                pattern: Some(Pattern::Regex(Regex::new(r"request [1-3]$").unwrap())),
+                ..LogQuery::default()
+            }),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            run(LogQuery {
+                tail: 100,
+                target: Some("app::db".to_string()),
+                pattern: Some(Pattern::Substring("request 1".to_string())),
+                ..LogQuery::default()
+            }),
+            vec![10, 11, 12]
+        );
+        assert_eq!(
+            run(LogQuery {
+                tail: 100,
+                target: Some("ap".to_string()),
+                ..LogQuery::default()
+            }),
+            Vec::<u64>::new()
+        );
+        assert_eq!(
+            run(LogQuery {
+                tail: 100,
+                since: Some("2026-01-01T00:00:05Z".parse().unwrap()),
+                until: Some("2026-01-01T00:00:07Z".parse().unwrap()),
+                ..LogQuery::default()
+            }),
+            vec![5, 6, 7]
+        );
+
+        std::fs::remove_dir_all(log.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::{LogCompression, LogRotation},
+    registry::parse_size,
+};
+
+/// Rotation limits of one log file, resolved from [`LogRotation`].
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    max_size: u64,
+    max_age: Option<Duration>,
+    keep: usize,
+    compression: LogCompression,
+}
+
+impl RotationPolicy {
+    pub fn new(rotation: &LogRotation) -> Self {
+        let max_size = parse_size(&rotation.max_size)
+            .ok()
+            .filter(|size| *size > 0)
+            .unwrap_or_else(|| {
+                let default = LogRotation::default().max_size;
+                tracing::warn!(
+                    "invalid log_rotation.max_size '{}', using {}",
+                    rotation.max_size,
+                    default
+                );
+                parse_size(&default).unwrap_or(u64::MAX)
+            });
+
+        Self {
+            max_size,
+            max_age: (rotation.max_age_hours > 0)
+                .then(|| Duration::from_secs(rotation.max_age_hours * 3600)),
+            keep: rotation.keep as usize,
+            compression: rotation.compression,
+        }
+    }
+
+    /// Whether a file of `size` bytes started at `started_at` has to be
+    /// rotated before `incoming` more bytes are appended. An empty file is
+    /// never rotated.
+    pub fn is_due(&self, size: u64, incoming: u64, started_at: SystemTime) -> bool {
+        size > 0
+            && (size + incoming > self.max_size
+                || self
+                    .max_age
+                    .is_some_and(|age| started_at.elapsed().unwrap_or_default() >= age))
+    }
+}
+
+/// A rotated log file, named `<log file>.<timestamp>` plus the extension of
+/// its compression.
+pub struct Segment {
+    pub path: PathBuf,
+    stamp: String,
+    compression: LogCompression,
+}
+
+impl Segment {
+    pub fn is_compressed(&self) -> bool {
+        self.compression != LogCompression::None
+    }
+
+    /// Opens the segment for reading, decompressing it on the fly.
+    pub fn open(&self) -> io::Result<Box<dyn BufRead + Send>> {
+        let file = File::open(&self.path)?;
+        Ok(match self.compression {
+            LogCompression::None => Box::new(BufReader::new(file)),
+            LogCompression::Gzip => Box::new(BufReader::new(GzDecoder::new(file))),
+            LogCompression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        })
+    }
+}
+
+/// Rotated files of the log at `log_path`, oldest first.
+pub fn segments(log_path: &Path) -> Vec<Segment> {
+    let (Some(dir), Some(name)) = (log_path.parent(), log_path.file_name()) else {
+        return vec![];
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+
+    let mut segments: Vec<Segment> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let rest = file_name.strip_prefix(&prefix)?;
+            let (stamp, compression) = if let Some(stamp) = rest.strip_suffix(".gz") {
+                (stamp, LogCompression::Gzip)
+            } else if let Some(stamp) = rest.strip_suffix(".zst") {
+                (stamp, LogCompression::Zstd)
+            } else {
+                (rest, LogCompression::None)
+            };
+            // Skips compressions in progress.
+            if stamp.is_empty() || !stamp.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                return None;
+            }
+
+            Some(Segment {
+                path: entry.path(),
+                stamp: stamp.to_string(),
+                compression,
+            })
+        })
+        .collect();
+
+    // While a segment is being compressed both versions may exist for a
+    // moment; the uncompressed one is complete.
+    segments.sort_by(|a, b| {
+        a.stamp.cmp(&b.stamp).then_with(|| {
+            (a.compression != LogCompression::None).cmp(&(b.compression != LogCompression::None))
+        })
+    });
+    segments.dedup_by(|later, earlier| later.stamp == earlier.stamp);
+
+    segments
+}
+
+/// Moves the log at `log_path` aside, then compresses it and deletes rotated
+/// files beyond the policy's `keep` in the background.
+pub fn rotate(log_path: &Path, policy: &RotationPolicy) -> io::Result<()> {
+    let rotated = rotated_path(log_path, chrono::Utc::now());
+    std::fs::rename(log_path, &rotated)?;
+
+    let log_path = log_path.to_path_buf();
+    let policy = policy.clone();
+    tokio::task::spawn_blocking(move || {
+        if policy.compression != LogCompression::None
+            && let Err(e) = compress(&rotated, policy.compression)
+        {
+            tracing::warn!("failed to compress {}: {}", rotated.display(), e);
+        }
+
+        let segments = segments(&log_path);
+        let excess = segments.len().saturating_sub(policy.keep);
+        for segment in &segments[..excess] {
+            let _ = std::fs::remove_file(&segment.path);
+        }
+    });
+
+    Ok(())
+}
+
+/// Name for the log at `log_path` rotated at `now`. A file rotated earlier in
+/// the same millisecond keeps its name, and this one is stamped a millisecond
+/// later.
+fn rotated_path(log_path: &Path, mut now: chrono::DateTime<chrono::Utc>) -> PathBuf {
+    loop {
+        let mut rotated = log_path.as_os_str().to_owned();
+        rotated.push(format!(".{}", now.format("%Y%m%dT%H%M%S%3fZ")));
+        let rotated = PathBuf::from(rotated);
+
+        let taken = [
+            LogCompression::None,
+            LogCompression::Gzip,
+            LogCompression::Zstd,
+        ]
+        .iter()
+        .any(|compression| {
+            let mut path = rotated.as_os_str().to_owned();
+            path.push(compression.extension());
+            Path::new(&path).exists()
+        });
+        if !taken {
+            return rotated;
+        }
+
+        now += chrono::Duration::milliseconds(1);
+    }
+}
+
+/// Removes every rotated file of the log at `log_path`.
+pub fn remove_all(log_path: &Path) {
+    for segment in segments(log_path) {
+        let _ = std::fs::remove_file(segment.path);
+    }
+}
+
+/// Replaces `path` with a compressed copy. The copy is written under a
+/// temporary name, so readers never see it half-written.
+fn compress(path: &Path, compression: LogCompression) -> io::Result<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(compression.extension());
+    let compressed = PathBuf::from(compressed);
+    let mut tmp_path = compressed.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut input = File::open(path)?;
+    let output = File::create(&tmp_path)?;
+    let written = match compression {
+        LogCompression::Gzip => {
+            let mut encoder = GzEncoder::new(output, Compression::default());
+            io::copy(&mut input, &mut encoder)
+                .and_then(|_| encoder.finish())
+                .and_then(|mut file| file.flush())
+        }
+        LogCompression::Zstd => zstd::Encoder::new(output, 0).and_then(|mut encoder| {
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.flush()
+        }),
+        LogCompression::None => Ok(()),
+    };
+
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    std::fs::rename(&tmp_path, &compressed)?;
+    std::fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_size: &str, max_age_hours: u64) -> RotationPolicy {
+        RotationPolicy::new(&LogRotation {
+            max_size: max_size.to_string(),
+            max_age_hours,
+            ..LogRotation::default()
+        })
+    }
+
+    #[test]
+    fn is_due_by_size() {
+        let policy = policy("1K", 0);
+        let max = parse_size("1K").unwrap();
+        let now = SystemTime::now();
+
+        assert!(!policy.is_due(max - 10, 10, now));
+        assert!(policy.is_due(max - 10, 11, now));
+        // A single entry larger than the limit still goes into an empty file.
+        assert!(!policy.is_due(0, max * 2, now));
+    }
+
+    #[test]
+    fn is_due_by_age() {
+        let hourly = policy("1G", 1);
+        let hour = Duration::from_secs(3600);
+
+        assert!(!hourly.is_due(1, 1, SystemTime::now()));
+        assert!(hourly.is_due(1, 1, SystemTime::now() - hour));
+        assert!(!hourly.is_due(0, 1, SystemTime::now() - hour));
+        // 0 disables rotation by age.
+        assert!(!policy("1G", 0).is_due(1, 1, SystemTime::now() - hour * 1000));
+    }
+
+    #[test]
+    fn invalid_max_size_falls_back_to_the_default() {
+        let default = parse_size(&LogRotation::default().max_size).unwrap();
+        assert_eq!(policy("lots", 0).max_size, default);
+        assert_eq!(policy("0", 0).max_size, default);
+    }
+
+    #[test]
+    fn segments_are_sorted_and_deduplicated() {
+        let dir =
+            std::env::temp_dir().join(format!("dockless-log-rotation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("app.log");
+        for name in [
+            "app.log",
+            "app.log.20260102T000000000Z.gz",
+            "app.log.20260101T000000000Z.zst",
+            "app.log.20260103T000000000Z",
+            // Compressed while listed: the uncompressed file wins.
+            "app.log.20260103T000000000Z.gz",
+            "app.log.20260104T000000000Z.gz.tmp",
+            "app.log.",
+            "app.logger.20260101T000000000Z",
+            "other.log.20260101T000000000Z",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let found: Vec<(String, bool)> = segments(&log_path)
+            .iter()
+            .map(|s| {
+                (
+                    s.path.file_name().unwrap().to_string_lossy().into_owned(),
+                    s.is_compressed(),
+                )
+            })
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            found,
+            vec![
+                ("app.log.20260101T000000000Z.zst".to_string(), true),
+                ("app.log.20260102T000000000Z.gz".to_string(), true),
+                ("app.log.20260103T000000000Z".to_string(), false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rotate_compresses_and_keeps_the_newest_files() {
+        let dir = std::env::temp_dir().join(format!(
+            "dockless-log-rotation-{}-rotate",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("app.log");
+        std::fs::write(dir.join("app.log.20200101T000000000Z.gz"), b"").unwrap();
+        std::fs::write(dir.join("app.log.20200102T000000000Z"), b"").unwrap();
+
+        let policy = RotationPolicy::new(&LogRotation {
+            keep: 2,
+            compression: LogCompression::Zstd,
+            ..LogRotation::default()
+        });
+        std::fs::write(&log_path, b"first\nsecond\n").unwrap();
+        rotate(&log_path, &policy).unwrap();
+        assert!(!log_path.exists());
+
+        // Compression and retention run in the background.
+        let mut found = Vec::new();
+        for _ in 0..100 {
+            found = segments(&log_path);
+            if found.len() == 2 && found[1].is_compressed() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let names: Vec<String> = found
+            .iter()
+            .map(|s| s.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 2, "{:?}", names);
+        assert_eq!(names[0], "app.log.20200102T000000000Z");
+        assert!(names[1].ends_with(".zst"), "{:?}", names);
+
+        let lines: Vec<String> = found[1]
+            .open()
+            .unwrap()
+            .lines()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, vec!["first", "second"]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        remove_all(&log_path);
+        assert!(segments(&log_path).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotated_names_do_not_collide() {
+        let dir = std::env::temp_dir().join(format!(
+            "dockless-log-rotation-{}-names",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("app.log");
+        std::fs::write(dir.join("app.log.20260101T000000000Z"), b"").unwrap();
+        std::fs::write(dir.join("app.log.20260101T000000001Z.gz"), b"").unwrap();
+
+        let now = chrono::DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            rotated_path(&log_path, now),
+            dir.join("app.log.20260101T000000002Z")
+        );
+        assert_eq!(
+            rotated_path(&log_path, now + chrono::Duration::seconds(1)),
+            dir.join("app.log.20260101T000001000Z")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,18 @@
+pub mod capabilities;
+pub mod cgroup;
+pub mod credentials;
+pub mod exit;
+pub mod health;
 pub mod log_buffer;
+pub mod log_parser;
+pub mod log_query;
+pub mod log_rotation;
+pub mod notify;
+pub mod process_settings;
+pub mod readiness;
+pub mod reaper;
+pub mod run_state;
+pub mod sandbox;
 pub mod service;
 pub mod supervisor;
 pub mod supervisor_manager;
@@ -0,0 +1,408 @@
+use serde::Serialize;
+use std::{
+    io,
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    io::Interest,
+    net::UnixDatagram,
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Duration, Instant, sleep_until},
+};
+
+use crate::runtime::{cgroup::Cgroup, service::Service};
+
+/// Longest path that fits in `sockaddr_un.sun_path`, minus the trailing NUL.
+const MAX_SOCKET_PATH: usize = 107;
+
+/// What a service has reported over its notify socket during the current run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NotifyState {
+    /// `READY=1` has been received.
+    pub ready: bool,
+    /// Last `STATUS=` text.
+    pub status: Option<String>,
+    /// PID announced with `MAINPID=`.
+    pub main_pid: Option<u32>,
+    pub last_watchdog_at: Option<String>,
+}
+
+/// A bound `NOTIFY_SOCKET` that has not been handed to a listener yet.
+pub struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    pub fn bind(path: &Path) -> std::io::Result<Self> {
+        if path.as_os_str().len() > MAX_SOCKET_PATH {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("notify socket path {} is too long", path.display()),
+            ));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        match std::fs::remove_file(path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let socket = UnixDatagram::bind(path)?;
+        // Have the kernel attach the sender's PID to every datagram.
+        let enable: libc::c_int = 1;
+        let set = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                (&enable as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if set != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            socket,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts reading notifications into the service's notify state. Only
+    /// datagrams from processes of the service, in the process group led by
+    /// `pgid` or in the service's cgroup, are taken. With a watchdog, a
+    /// message is sent on `expired_tx` when no `WATCHDOG=1` ping arrives
+    /// within `watchdog` of the previous one. The watchdog is armed by the
+    /// first `READY=1` or `WATCHDOG=1`.
+    pub fn listen(
+        self,
+        service: Service,
+        pgid: u32,
+        watchdog: Option<Duration>,
+        expired_tx: mpsc::Sender<()>,
+    ) -> NotifyMonitor {
+        let path = self.path.clone();
+
+        NotifyMonitor {
+            handle: tokio::spawn(listen(self.socket, service, pgid, watchdog, expired_tx)),
+            path,
+        }
+    }
+}
+
+/// Listener task for one run of a service process. The task is aborted and
+/// the socket file removed when the monitor is dropped.
+pub struct NotifyMonitor {
+    handle: JoinHandle<()>,
+    path: PathBuf,
+}
+
+impl Drop for NotifyMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn listen(
+    socket: UnixDatagram,
+    service: Service,
+    pgid: u32,
+    watchdog: Option<Duration>,
+    expired_tx: mpsc::Sender<()>,
+) {
+    let mut buf = vec![0u8; 4096];
+    let mut watchdog_deadline: Option<Instant> = None;
+
+    loop {
+        let expired = async move {
+            match watchdog_deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let received = socket.async_io(Interest::READABLE, || {
+            recv_with_sender(socket.as_raw_fd(), &mut buf)
+        });
+        let len = tokio::select! {
+            result = received => match result {
+                // Anyone who can reach the socket file can write to it.
+                Ok((_, sender)) if !sender.is_some_and(|pid| is_service_process(&service, pgid, pid)) => {
+                    tracing::warn!(
+                        "[{}] ignoring notification from PID {}, which is not a process of the service",
+                        service.id,
+                        sender.unwrap_or_default()
+                    );
+                    continue;
+                }
+                Ok((len, _)) => len,
+                Err(e) => {
+                    tracing::warn!("[{}] notify socket read failed: {}", service.id, e);
+                    return;
+                }
+            },
+            _ = expired => {
+                let msg = format!(
+                    "watchdog timeout: no WATCHDOG=1 within {}s",
+                    watchdog.unwrap_or_default().as_secs()
+                );
+                tracing::warn!("[{}] {}", service.id, msg);
+                service.log_buffer.push("error".to_string(), msg).await;
+                let _ = expired_tx.send(()).await;
+                return;
+            }
+        };
+
+        let message = String::from_utf8_lossy(&buf[..len]).into_owned();
+        let mut state = service.get_notify_state().await;
+
+        for line in message.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            match key {
+                "READY" if value == "1" => {
+                    if !state.ready {
+                        tracing::info!("[{}] READY=1 received", service.id);
+                    }
+                    state.ready = true;
+                    if let Some(interval) = watchdog {
+                        watchdog_deadline.get_or_insert(Instant::now() + interval);
+                    }
+                }
+                "STATUS" => state.status = Some(value.to_string()),
+                "MAINPID" => match value.parse::<u32>() {
+                    Ok(pid) if is_service_process(&service, pgid, pid) => {
+                        state.main_pid = Some(pid);
+                        service.set_pid(Some(pid)).await;
+                    }
+                    Ok(pid) => tracing::warn!(
+                        "[{}] ignoring MAINPID={}, which is not a process of the service",
+                        service.id,
+                        pid
+                    ),
+                    Err(_) => {}
+                },
+                "WATCHDOG" if value == "1" => {
+                    state.last_watchdog_at = Some(chrono::Utc::now().to_rfc3339());
+                    if let Some(interval) = watchdog {
+                        watchdog_deadline = Some(Instant::now() + interval);
+                    }
+                }
+                "WATCHDOG" if value == "trigger" => {
+                    watchdog_deadline = Some(Instant::now());
+                }
+                _ => {}
+            }
+        }
+
+        service.set_notify_state(state).await;
+    }
+}
+
+/// Whether `pid` is in the process group led by `pgid` or in the service's
+/// cgroup.
+fn is_service_process(service: &Service, pgid: u32, pid: u32) -> bool {
+    let in_group = unsafe { libc::getpgid(pid as libc::pid_t) } == pgid as libc::pid_t;
+    in_group || (service.resources.is_some() && Cgroup::for_service(&service.id).contains(pid))
+}
+
+/// Receives a datagram into `buf`, along with the PID of its sender when the
+/// kernel attached it. Descriptors sent along are closed.
+fn recv_with_sender(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // Room for the credentials and a few descriptors, aligned for `cmsghdr`.
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut sender = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        unsafe {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) => {
+                    let cred: libc::ucred = std::ptr::read_unaligned(data.cast());
+                    sender = Some(cred.pid as u32);
+                }
+                (libc::SOL_SOCKET, libc::SCM_RIGHTS) => {
+                    let count = ((*cmsg).cmsg_len as usize - (data as usize - cmsg as usize))
+                        / std::mem::size_of::<RawFd>();
+                    for i in 0..count {
+                        let fd: RawFd = std::ptr::read_unaligned(data.cast::<RawFd>().add(i));
+                        libc::close(fd);
+                    }
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((len as usize, sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::LogRotation, registry::ServiceDefinition};
+
+    fn service(name: &str) -> Service {
+        let def: ServiceDefinition =
+            serde_json::from_value(serde_json::json!({ "id": name, "name": name })).unwrap();
+        let working_dir = std::env::temp_dir()
+            .join(format!("dockless-notify-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned();
+        let log_buffer = Service::create_log_buffer(&def, &working_dir, &LogRotation::default());
+        Service::new(&def, def.env.clone(), working_dir, log_buffer)
+    }
+
+    fn own_pgid() -> u32 {
+        (unsafe { libc::getpgrp() }) as u32
+    }
+
+    fn send(socket: &Path, message: &str) {
+        std::os::unix::net::UnixDatagram::unbound()
+            .unwrap()
+            .send_to(message.as_bytes(), socket)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_notifications_from_the_service() {
+        let service = service("ready");
+        let socket = NotifySocket::bind(&service.notify_socket_path()).unwrap();
+        let path = socket.path().to_path_buf();
+        let (expired_tx, _expired_rx) = mpsc::channel(1);
+        let monitor = socket.listen(service.clone(), own_pgid(), None, expired_tx);
+
+        let pid = std::process::id();
+        send(
+            &path,
+            &format!("READY=1\nSTATUS=serving\nMAINPID={}\n", pid),
+        );
+
+        for _ in 0..50 {
+            if service.get_notify_state().await.ready {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let state = service.get_notify_state().await;
+        assert!(state.ready);
+        assert_eq!(state.status.as_deref(), Some("serving"));
+        assert_eq!(state.main_pid, Some(pid));
+        assert_eq!(service.get_pid().await, Some(pid));
+
+        drop(monitor);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn ignores_notifications_from_other_processes() {
+        let service = service("foreign");
+        let socket = NotifySocket::bind(&service.notify_socket_path()).unwrap();
+        let path = socket.path().to_path_buf();
+        let (expired_tx, _expired_rx) = mpsc::channel(1);
+        // A process group this test process is not in.
+        let _monitor = socket.listen(service.clone(), own_pgid() + 1, None, expired_tx);
+
+        send(&path, "READY=1\nSTATUS=spoofed\n");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let state = service.get_notify_state().await;
+        assert!(!state.ready);
+        assert!(state.status.is_none());
+    }
+
+    #[tokio::test]
+    async fn reports_the_sender_of_a_datagram() {
+        let (receiver, sender) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        let enable: libc::c_int = 1;
+        let set = unsafe {
+            libc::setsockopt(
+                receiver.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                (&enable as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(set, 0);
+
+        sender.send(b"WATCHDOG=1").unwrap();
+        let mut buf = [0u8; 64];
+        let (len, pid) = recv_with_sender(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+        assert_eq!(pid, Some(std::process::id()));
+
+        let service = service("sender");
+        assert!(is_service_process(&service, own_pgid(), std::process::id()));
+        assert!(!is_service_process(
+            &service,
+            own_pgid() + 1,
+            std::process::id()
+        ));
+    }
+
+    #[tokio::test]
+    async fn watchdog_expires_without_pings() {
+        let service = service("watchdog");
+        let socket = NotifySocket::bind(&service.notify_socket_path()).unwrap();
+        let path = socket.path().to_path_buf();
+        let (expired_tx, mut expired_rx) = mpsc::channel(1);
+        let _monitor = socket.listen(
+            service.clone(),
+            own_pgid(),
+            Some(Duration::from_millis(500)),
+            expired_tx,
+        );
+
+        send(&path, "READY=1");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        send(&path, "WATCHDOG=1");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        send(&path, "WATCHDOG=1");
+        assert!(expired_rx.try_recv().is_err());
+
+        tokio::time::timeout(Duration::from_secs(2), expired_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(service.get_notify_state().await.last_watchdog_at.is_some());
+    }
+
+    #[test]
+    fn rejects_socket_paths_that_do_not_fit() {
+        let path = PathBuf::from(format!("/tmp/{}/notify.sock", "x".repeat(MAX_SOCKET_PATH)));
+        let error = NotifySocket::bind(&path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
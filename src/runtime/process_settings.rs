@@ -0,0 +1,170 @@
+use anyhow::Result;
+use std::io;
+use tokio::process::Command;
+
+use crate::registry::{IoniceClass, ProcessSettings, parse_rlimit};
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const DEFAULT_IONICE_LEVEL: u8 = 4;
+
+/// Arranges for `settings` to be applied to the process spawned by `cmd`
+/// between fork and exec, so that they are in effect before the service runs
+/// any code. Values are resolved up front, since only async-signal-safe calls
+/// are allowed in the child.
+pub fn configure(cmd: &mut Command, settings: &ProcessSettings) -> Result<()> {
+    let mut rlimits = Vec::new();
+    if let Some(nofile) = settings.rlimit_nofile {
+        rlimits.push((libc::RLIMIT_NOFILE, nofile));
+    }
+    if let Some(core) = &settings.rlimit_core {
+        rlimits.push((libc::RLIMIT_CORE, parse_rlimit(core)?));
+    }
+    if let Some(address_space) = &settings.rlimit_as {
+        rlimits.push((libc::RLIMIT_AS, parse_rlimit(address_space)?));
+    }
+
+    let nice = settings.nice;
+
+    let ioprio = settings.ionice_class.map(|class| {
+        let (class, level) = match class {
+            IoniceClass::Realtime => (1, settings.ionice_level.unwrap_or(DEFAULT_IONICE_LEVEL)),
+            IoniceClass::BestEffort => (2, settings.ionice_level.unwrap_or(DEFAULT_IONICE_LEVEL)),
+            IoniceClass::Idle => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | level as u32
+    });
+
+    let cpu_set = match &settings.cpu_affinity {
+        Some(cpus) => {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for cpu in cpus {
+                // `CPU_SET` panics past the end of the set.
+                if *cpu >= libc::CPU_SETSIZE as usize {
+                    anyhow::bail!(
+                        "process.cpu_affinity: CPU {} is beyond the {} CPUs an affinity mask can hold",
+                        cpu,
+                        libc::CPU_SETSIZE
+                    );
+                }
+                unsafe { libc::CPU_SET(*cpu, &mut set) };
+            }
+            Some(set)
+        }
+        None => None,
+    };
+
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, limit) in &rlimits {
+                let rlimit = libc::rlimit {
+                    rlim_cur: *limit,
+                    rlim_max: *limit,
+                };
+                if libc::setrlimit(*resource, &rlimit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if let Some(nice) = nice
+                && libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(ioprio) = ioprio
+                && libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(set) = &cpu_set
+                && libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(fields: serde_json::Value) -> ProcessSettings {
+        serde_json::from_value(fields).unwrap()
+    }
+
+    #[tokio::test]
+    async fn applies_settings_before_the_service_runs() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(
+            "ulimit -n; ulimit -c; grep Cpus_allowed_list /proc/self/status; cut -d' ' -f19 /proc/self/stat",
+        );
+        configure(
+            &mut cmd,
+            &settings(serde_json::json!({
+                "rlimit_nofile": 256,
+                "rlimit_core": "0",
+                "nice": 5,
+                "ionice_class": "idle",
+                "cpu_affinity": [0],
+            })),
+        )
+        .unwrap();
+
+        let output = cmd.output().await.unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], "256");
+        assert_eq!(lines[1], "0");
+        assert!(lines[2].ends_with("\t0"), "{}", lines[2]);
+        assert_eq!(lines[3], "5");
+    }
+
+    #[test]
+    fn rejects_cpus_an_affinity_mask_cannot_hold() {
+        let mut cmd = Command::new("true");
+        let settings = settings(serde_json::json!({
+            "cpu_affinity": [0, libc::CPU_SETSIZE as usize],
+        }));
+
+        let error = configure(&mut cmd, &settings).unwrap_err();
+        assert!(error.to_string().contains("beyond the"));
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn validates_limits_and_priorities() {
+        assert!(
+            settings(serde_json::json!({
+                "rlimit_core": "unlimited",
+                "rlimit_as": "2G",
+                "nice": -20,
+                "ionice_class": "best-effort",
+                "ionice_level": 7,
+                "cpu_affinity": [0],
+            }))
+            .validate()
+            .is_ok()
+        );
+
+        for invalid in [
+            serde_json::json!({ "rlimit_nofile": 0 }),
+            serde_json::json!({ "rlimit_as": "lots" }),
+            serde_json::json!({ "nice": 20 }),
+            serde_json::json!({ "ionice_level": 3 }),
+            serde_json::json!({ "ionice_class": "idle", "ionice_level": 3 }),
+            serde_json::json!({ "ionice_class": "realtime", "ionice_level": 8 }),
+            serde_json::json!({ "cpu_affinity": [] }),
+            serde_json::json!({ "cpu_affinity": [1023] }),
+        ] {
+            assert!(settings(invalid.clone()).validate().is_err(), "{}", invalid);
+        }
+    }
+}
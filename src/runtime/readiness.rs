@@ -0,0 +1,251 @@
+use std::sync::Arc;
+use tokio::{
+    sync::{RwLock, mpsc},
+    task::JoinHandle,
+    time::{Duration, Instant, sleep},
+};
+
+use crate::{
+    platform::port_manager::PortManager,
+    registry::ReadinessMode,
+    runtime::{
+        health::HealthState,
+        service::{Service, ServiceState},
+    },
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches a freshly spawned process until it is ready, then moves the
+/// service from `Starting` to `Running`. If the process does not become ready
+/// within the start timeout, or binds a port that belongs to another service,
+/// the reason is sent on `failed_tx`. The task is aborted when the monitor is
+/// dropped.
+pub struct ReadinessMonitor {
+    handle: JoinHandle<()>,
+}
+
+impl ReadinessMonitor {
+    pub fn spawn(
+        service: Service,
+        pid: u32,
+        port_manager: Option<Arc<RwLock<PortManager>>>,
+        failed_tx: mpsc::Sender<String>,
+    ) -> Self {
+        Self {
+            handle: tokio::spawn(monitor(service, pid, port_manager, failed_tx)),
+        }
+    }
+}
+
+impl Drop for ReadinessMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn monitor(
+    service: Service,
+    pid: u32,
+    port_manager: Option<Arc<RwLock<PortManager>>>,
+    failed_tx: mpsc::Sender<String>,
+) {
+    let mode = service.readiness.mode;
+
+    if mode == ReadinessMode::None {
+        service.set_state(ServiceState::Running).await;
+        return;
+    }
+
+    let timeout = Duration::from_secs(service.readiness.start_timeout_secs);
+    let deadline = Instant::now() + timeout;
+
+    let allocated_port = match &port_manager {
+        Some(pm) => pm.read().await.get_port(&service.id),
+        None => None,
+    }
+    .or_else(|| service.env.get("PORT").and_then(|p| p.parse().ok()));
+
+    loop {
+        let listening = PortManager::get_listening_ports_for_pid(pid);
+
+        if let Some(pm) = &port_manager
+            && let Some((port, owner)) = pm.read().await.find_conflict(&service.id, &listening)
+        {
+            let _ = failed_tx
+                .send(format!(
+                    "port conflict: port {} is already allocated to service '{}'",
+                    port, owner
+                ))
+                .await;
+            return;
+        }
+
+        let ready = match mode {
+            ReadinessMode::Port => allocated_port.is_some_and(|p| listening.contains(&p)),
+            ReadinessMode::Health => service
+                .get_health()
+                .await
+                .is_some_and(|h| h.status == HealthState::Healthy),
+            ReadinessMode::Notify => service.get_notify_state().await.ready,
+            ReadinessMode::None => true,
+        };
+
+        if ready {
+            tracing::info!(
+                "[{}] ready after {:.1}s",
+                service.id,
+                (timeout - deadline.saturating_duration_since(Instant::now())).as_secs_f64()
+            );
+            service.set_state(ServiceState::Running).await;
+            return;
+        }
+
+        if Instant::now() >= deadline {
+            let waiting_for = match (mode, allocated_port) {
+                (ReadinessMode::Port, Some(port)) => format!("listening on port {}", port),
+                (ReadinessMode::Port, None) => "a port allocation".to_string(),
+                (ReadinessMode::Notify, _) => "READY=1 on the notify socket".to_string(),
+                _ => "a passing health check".to_string(),
+            };
+
+            let _ = failed_tx
+                .send(format!(
+                    "not ready after {}s: still waiting for {}",
+                    timeout.as_secs(),
+                    waiting_for
+                ))
+                .await;
+            return;
+        }
+
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::LogRotation, registry::ServiceDefinition};
+
+    fn service(name: &str, readiness: serde_json::Value, port: Option<u16>) -> Service {
+        let mut definition =
+            serde_json::json!({ "id": name, "name": name, "readiness": readiness });
+        if let Some(port) = port {
+            definition["env"] = serde_json::json!({ "PORT": port.to_string() });
+        }
+        let def: ServiceDefinition = serde_json::from_value(definition).unwrap();
+        let working_dir = std::env::temp_dir()
+            .join(format!(
+                "dockless-readiness-{}-{}",
+                std::process::id(),
+                name
+            ))
+            .to_string_lossy()
+            .into_owned();
+        let log_buffer = Service::create_log_buffer(&def, &working_dir, &LogRotation::default());
+        Service::new(&def, def.env.clone(), working_dir, log_buffer)
+    }
+
+    async fn wait_until_running(service: &Service) {
+        for _ in 0..20 {
+            if matches!(service.get_state().await, ServiceState::Running) {
+                return;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("service never became ready");
+    }
+
+    #[tokio::test]
+    async fn port_mode_waits_for_the_allocated_port() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = service("port", serde_json::json!({ "mode": "port" }), Some(port));
+
+        let (failed_tx, _failed_rx) = mpsc::channel(1);
+        let _monitor =
+            ReadinessMonitor::spawn(service.clone(), std::process::id(), None, failed_tx);
+        wait_until_running(&service).await;
+    }
+
+    #[tokio::test]
+    async fn notify_mode_waits_for_ready() {
+        let service = service("notify", serde_json::json!({ "mode": "notify" }), None);
+
+        let (failed_tx, _failed_rx) = mpsc::channel(1);
+        let _monitor =
+            ReadinessMonitor::spawn(service.clone(), std::process::id(), None, failed_tx);
+        sleep(Duration::from_millis(200)).await;
+        assert!(matches!(service.get_state().await, ServiceState::Stopped));
+
+        let mut notify = service.get_notify_state().await;
+        notify.ready = true;
+        service.set_notify_state(notify).await;
+        wait_until_running(&service).await;
+    }
+
+    #[tokio::test]
+    async fn reports_what_it_waited_for_when_timing_out() {
+        let service = service(
+            "timeout",
+            serde_json::json!({ "mode": "port", "start_timeout_secs": 1 }),
+            Some(1),
+        );
+
+        let (failed_tx, mut failed_rx) = mpsc::channel(1);
+        let _monitor =
+            ReadinessMonitor::spawn(service.clone(), std::process::id(), None, failed_tx);
+        let reason = failed_rx.recv().await.unwrap();
+        assert_eq!(
+            reason,
+            "not ready after 1s: still waiting for listening on port 1"
+        );
+        assert!(matches!(service.get_state().await, ServiceState::Stopped));
+    }
+
+    #[tokio::test]
+    async fn fails_when_binding_a_port_of_another_service() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let ports_path = std::env::temp_dir()
+            .join(format!(
+                "dockless-readiness-{}-ports.json",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+        std::fs::write(
+            &ports_path,
+            serde_json::json!({
+                "version": 1,
+                "port_range_start": 8100,
+                "port_range_end": 8999,
+                "allocations": { "other": port },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let port_manager = Arc::new(RwLock::new(PortManager::load(&ports_path).unwrap()));
+
+        let service = service("conflict", serde_json::json!({ "mode": "port" }), None);
+        let (failed_tx, mut failed_rx) = mpsc::channel(1);
+        let _monitor = ReadinessMonitor::spawn(
+            service.clone(),
+            std::process::id(),
+            Some(port_manager),
+            failed_tx,
+        );
+
+        let reason = failed_rx.recv().await.unwrap();
+        assert_eq!(
+            reason,
+            format!(
+                "port conflict: port {} is already allocated to service 'other'",
+                port
+            )
+        );
+        let _ = std::fs::remove_file(&ports_path);
+    }
+}
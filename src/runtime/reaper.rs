@@ -0,0 +1,354 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    process::{Child, Command},
+    signal::unix::{SignalKind, signal},
+    task::JoinHandle,
+    time::{Duration, interval},
+};
+
+use crate::runtime::{cgroup::Cgroup, service::Service};
+
+/// How often to look for reparented processes. A process is reparented
+/// without dockless being signalled, so SIGCHLD alone is not enough.
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far up the parent chain a process is followed to find the service it
+/// belongs to.
+const MAX_ANCESTRY: usize = 64;
+
+/// Reaps descendants of service processes that are reparented to dockless.
+///
+/// Dockless registers as a child subreaper, so processes that a service
+/// forked and then abandoned (e.g. by double-forking) become children of
+/// dockless instead of init. Service processes themselves are waited on by
+/// their supervisor; everything else that exits is reaped here and reported in
+/// the log buffer of the service it came from.
+///
+/// A reparented process is attributed to a service by its cgroup when the
+/// service has one, then by its process group. Processes that also left the
+/// group, e.g. by calling `setsid`, are found in the service's
+/// [`Service::forked`], which each scan fills in while they still descend
+/// from the service process.
+pub struct Reaper {
+    /// Service processes spawned through [`Reaper::spawn`], by PID. Each leads
+    /// its own process group, so this also maps process groups to services.
+    groups: Mutex<HashMap<u32, Service>>,
+    /// Reparented processes that have already been reported.
+    reported: Mutex<HashSet<u32>>,
+}
+
+impl Reaper {
+    pub fn new() -> Self {
+        Self {
+            groups: Mutex::new(HashMap::new()),
+            reported: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Marks dockless as a child subreaper and starts reaping.
+    pub fn start(self: Arc<Self>) -> io::Result<JoinHandle<()>> {
+        if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut sigchld = signal(SignalKind::child())?;
+
+        Ok(tokio::spawn(async move {
+            let mut ticker = interval(SCAN_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = sigchld.recv() => {}
+                    _ = ticker.tick() => {}
+                }
+                // Reading all of /proc blocks for a while on a busy machine.
+                let reaper = self.clone();
+                match tokio::task::spawn_blocking(move || reaper.scan()).await {
+                    Ok(events) => report(events).await,
+                    Err(e) => tracing::error!(error = ?e, "reaper scan failed"),
+                }
+            }
+        }))
+    }
+
+    /// Spawns the main process of `service`. Registration happens under the
+    /// same lock as scanning, so the reaper never takes a freshly spawned
+    /// process for an orphan.
+    pub fn spawn(&self, cmd: &mut Command, service: &Service) -> io::Result<Child> {
+        let mut groups = self.groups.lock().unwrap();
+        let child = cmd.spawn()?;
+
+        if let Some(pid) = child.id() {
+            service.forked.lock().unwrap().clear();
+            groups.insert(pid, service.clone());
+        }
+
+        Ok(child)
+    }
+
+    /// Forgets a service process once its supervisor has waited on it.
+    pub fn release(&self, pid: u32) {
+        self.groups.lock().unwrap().remove(&pid);
+    }
+
+    /// Reaps and reports reparented processes, and returns what to log for
+    /// which service. Blocking.
+    fn scan(&self) -> Vec<(Option<Service>, String)> {
+        let mut events: Vec<(Option<Service>, String)> = Vec::new();
+
+        // Read before taking the lock, so that `spawn` is not held up. A
+        // process spawned meanwhile is either missing from the list or
+        // registered by the time the lock is taken.
+        let processes: Vec<ProcStat> = list_pids().filter_map(ProcStat::read).collect();
+
+        {
+            let groups = self.groups.lock().unwrap();
+            let mut reported = self.reported.lock().unwrap();
+            let own_pid = std::process::id();
+            let own_pgid = unsafe { libc::getpgrp() } as u32;
+
+            track_forked(&groups, &processes);
+
+            let children: Vec<ProcStat> = processes
+                .iter()
+                .filter(|p| p.ppid == own_pid)
+                // Read again, now that the lock is held: a service process
+                // may have exited and been released since the list was read.
+                .filter_map(|p| ProcStat::read(p.pid))
+                .filter(|p| p.ppid == own_pid)
+                // Service processes are waited on by their supervisor, and
+                // helpers dockless runs itself stay in its own group.
+                .filter(|p| !groups.contains_key(&p.pid) && p.pgid != own_pgid)
+                .collect();
+
+            reported.retain(|pid| children.iter().any(|p| p.pid == *pid));
+
+            for process in children {
+                let service = service_of(&groups, &process).cloned();
+
+                if process.state == 'Z' {
+                    let mut status = 0;
+                    let reaped =
+                        unsafe { libc::waitpid(process.pid as i32, &mut status, libc::WNOHANG) };
+                    if reaped as u32 != process.pid {
+                        continue;
+                    }
+
+                    reported.remove(&process.pid);
+                    events.push((
+                        service,
+                        format!(
+                            "reaped orphaned process {} ({}), {}",
+                            process.pid,
+                            process.comm,
+                            describe_wait_status(status)
+                        ),
+                    ));
+                } else if reported.insert(process.pid) {
+                    events.push((
+                        service,
+                        format!(
+                            "process {} ({}) was reparented to dockless after its parent exited",
+                            process.pid, process.comm
+                        ),
+                    ));
+                }
+            }
+        }
+
+        events
+    }
+}
+
+/// Logs what a scan found, to the service it concerns if known.
+async fn report(events: Vec<(Option<Service>, String)>) {
+    for (service, msg) in events {
+        match service {
+            Some(service) => {
+                tracing::info!("[{}] {}", service.id, msg);
+                service.log_buffer.push("info".to_string(), msg).await;
+            }
+            None => tracing::info!("{}", msg),
+        }
+    }
+}
+
+/// The service `process` belongs to, if any.
+fn service_of<'a>(groups: &'a HashMap<u32, Service>, process: &ProcStat) -> Option<&'a Service> {
+    groups
+        .values()
+        .find(|s| s.resources.is_some() && Cgroup::for_service(&s.id).contains(process.pid))
+        .or_else(|| groups.get(&process.pgid))
+        .or_else(|| {
+            groups
+                .values()
+                .find(|s| s.forked.lock().unwrap().get(&process.pid) == Some(&process.start_time))
+        })
+}
+
+/// Records in [`Service::forked`] every process that descends from a service
+/// process or is in its process group, and forgets those that are gone.
+fn track_forked(groups: &HashMap<u32, Service>, processes: &[ProcStat]) {
+    let parents: HashMap<u32, u32> = processes.iter().map(|p| (p.pid, p.ppid)).collect();
+    let start_times: HashMap<u32, u64> = processes.iter().map(|p| (p.pid, p.start_time)).collect();
+
+    for service in groups.values() {
+        service
+            .forked
+            .lock()
+            .unwrap()
+            .retain(|pid, start_time| start_times.get(pid) == Some(start_time));
+    }
+
+    for process in processes {
+        if groups.contains_key(&process.pid) {
+            continue;
+        }
+
+        let mut ancestor = process.ppid;
+        let mut root = groups.contains_key(&process.pgid).then_some(process.pgid);
+        for _ in 0..MAX_ANCESTRY {
+            if root.is_some() || ancestor <= 1 {
+                break;
+            }
+            if groups.contains_key(&ancestor) {
+                root = Some(ancestor);
+            }
+            ancestor = parents.get(&ancestor).copied().unwrap_or(0);
+        }
+
+        if let Some(service) = root.and_then(|pid| groups.get(&pid)) {
+            service
+                .forked
+                .lock()
+                .unwrap()
+                .insert(process.pid, process.start_time);
+        }
+    }
+}
+
+struct ProcStat {
+    pid: u32,
+    comm: String,
+    state: char,
+    ppid: u32,
+    pgid: u32,
+    /// In clock ticks since boot.
+    start_time: u64,
+}
+
+impl ProcStat {
+    fn read(pid: u32) -> Option<Self> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+        // The command name may contain spaces and parentheses.
+        let (head, rest) = stat.rsplit_once(')')?;
+        let comm = head.split_once('(')?.1.to_string();
+        let mut fields = rest.split_whitespace();
+
+        Some(Self {
+            pid,
+            comm,
+            state: fields.next()?.chars().next()?,
+            ppid: fields.next()?.parse().ok()?,
+            pgid: fields.next()?.parse().ok()?,
+            // Field 22, 17 fields after the process group.
+            start_time: fields.nth(16)?.parse().ok()?,
+        })
+    }
+}
+
+fn list_pids() -> impl Iterator<Item = u32> {
+    std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+}
+
+fn describe_wait_status(status: i32) -> String {
+    if libc::WIFEXITED(status) {
+        format!("exit code {}", libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        format!("killed by signal {}", libc::WTERMSIG(status))
+    } else {
+        format!("wait status {}", status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::LogRotation, registry::ServiceDefinition};
+
+    fn service(id: &str) -> Service {
+        let def: ServiceDefinition =
+            serde_json::from_value(serde_json::json!({ "id": id, "name": id })).unwrap();
+        let working_dir = std::env::temp_dir()
+            .join(format!("dockless-reaper-{}-{}", std::process::id(), id))
+            .to_string_lossy()
+            .into_owned();
+        let log_buffer = Service::create_log_buffer(&def, &working_dir, &LogRotation::default());
+        Service::new(&def, def.env.clone(), working_dir, log_buffer)
+    }
+
+    fn process(pid: u32, ppid: u32, pgid: u32) -> ProcStat {
+        ProcStat {
+            pid,
+            comm: "helper".to_string(),
+            state: 'S',
+            ppid,
+            pgid,
+            start_time: pid as u64 * 10,
+        }
+    }
+
+    #[test]
+    fn reads_its_own_stat() {
+        let own = ProcStat::read(std::process::id()).unwrap();
+        assert_eq!(own.ppid, std::os::unix::process::parent_id());
+        assert_eq!(own.pgid, unsafe { libc::getpgrp() } as u32);
+        assert!(own.start_time > 0);
+    }
+
+    #[tokio::test]
+    async fn tracks_descendants_that_left_the_process_group() {
+        let web = service("web");
+        let groups = HashMap::from([(100, web.clone())]);
+        let processes = [
+            process(1, 0, 1),
+            process(100, 1, 100),
+            // In the group.
+            process(101, 100, 100),
+            // Left the group with setsid, then forked again.
+            process(102, 101, 102),
+            process(103, 102, 102),
+            process(200, 1, 200),
+        ];
+
+        track_forked(&groups, &processes);
+        let mut forked: Vec<u32> = web.forked.lock().unwrap().keys().copied().collect();
+        forked.sort();
+        assert_eq!(forked, vec![101, 102, 103]);
+
+        // Once 102 is reparented, its recorded start time still ties it to
+        // the service, unless the PID was reused.
+        let orphan = process(102, 1, 102);
+        assert_eq!(
+            service_of(&groups, &orphan).map(|s| s.id.as_str()),
+            Some("web")
+        );
+        let reused = ProcStat {
+            start_time: 1,
+            ..process(102, 1, 102)
+        };
+        assert!(service_of(&groups, &reused).is_none());
+
+        // Processes that are gone are forgotten.
+        track_forked(&groups, &processes[..3]);
+        assert_eq!(web.forked.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::fs::MetadataExt,
+    },
+    path::Path,
+};
+use tokio::io::unix::AsyncFd;
+
+/// Identifies a running service process so that a later dockless instance can
+/// tell it apart from an unrelated process that reused its PID. Written to
+/// `run/state.json` while the process is alive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunState {
+    pub pid: u32,
+    /// Start time from `/proc/<pid>/stat`, in clock ticks since boot.
+    pub start_time: u64,
+    /// Inode of the executable the process is running.
+    pub binary_inode: u64,
+    pub started_at: String,
+}
+
+impl RunState {
+    /// Reads the identity of `pid` from `/proc`.
+    pub fn capture(pid: u32) -> io::Result<Self> {
+        Ok(Self {
+            pid,
+            start_time: proc_start_time(pid)?,
+            binary_inode: std::fs::metadata(format!("/proc/{}/exe", pid))?.ino(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn remove(path: &Path) {
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Processes still in the process group led by the recorded process, such
+    /// as helpers it forked, that were started after it.
+    pub fn group_members(&self) -> Vec<u32> {
+        std::fs::read_dir("/proc")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .filter(|pid| {
+                proc_stat_fields(*pid).is_some_and(|fields| {
+                    fields.get(2).and_then(|g| g.parse().ok()) == Some(self.pid)
+                        && fields
+                            .get(19)
+                            .and_then(|t| t.parse::<u64>().ok())
+                            .is_some_and(|t| t >= self.start_time)
+                })
+            })
+            .collect()
+    }
+
+    /// Whether `pid` is still the process this state was captured from.
+    fn matches_running_process(&self) -> bool {
+        proc_start_time(self.pid).is_ok_and(|t| t == self.start_time)
+            && std::fs::metadata(format!("/proc/{}/exe", self.pid))
+                .is_ok_and(|m| m.ino() == self.binary_inode)
+    }
+}
+
+/// A service process started by a previous dockless instance. It is not our
+/// child, so its exit is observed through a pidfd and its exit status is not
+/// available.
+pub struct AdoptedProcess {
+    pid: u32,
+    pidfd: AsyncFd<OwnedFd>,
+}
+
+impl AdoptedProcess {
+    /// Looks up the process recorded in the state file at `path`. Returns
+    /// `None`, and removes the file, when it is gone or its PID now belongs to
+    /// another process.
+    pub fn find(path: &Path) -> Option<Self> {
+        let state = RunState::load(path)?;
+
+        // Open the pidfd before checking identity so that a PID reused after
+        // the check cannot be mistaken for the service.
+        let adopted = Self::open(state.pid)
+            .ok()
+            .filter(|_| state.matches_running_process());
+
+        if adopted.is_none() {
+            RunState::remove(path);
+        }
+
+        adopted
+    }
+
+    fn open(pid: u32) -> io::Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        Ok(Self {
+            pid,
+            pidfd: AsyncFd::new(fd)?,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Whether the process's stdout or stderr is a pipe nobody reads any more,
+    /// as it is when it was the previous dockless instance that read it. The
+    /// process would be killed by `SIGPIPE`, or get `EPIPE`, on its next
+    /// write there.
+    pub fn has_dead_output(&self) -> bool {
+        [1, 2].into_iter().any(|fd| {
+            std::fs::read_link(format!("/proc/{}/fd/{}", self.pid, fd))
+                .ok()
+                .and_then(|target| target.to_str().map(str::to_string))
+                .filter(|target| target.starts_with("pipe:"))
+                .is_some_and(|pipe| !pipe_has_reader(&pipe))
+        })
+    }
+
+    /// Resolves once the process has exited.
+    pub async fn wait(&self) -> io::Result<()> {
+        let _ = self.pidfd.readable().await?;
+        Ok(())
+    }
+
+    pub fn kill(&self) {
+        unsafe {
+            libc::kill(self.pid as i32, libc::SIGKILL);
+        }
+    }
+}
+
+/// Whether any process has the pipe `pipe`, as `/proc/<pid>/fd` shows it,
+/// open for reading.
+fn pipe_has_reader(pipe: &str) -> bool {
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        // Without a way to tell, assume the pipe is fine.
+        return true;
+    };
+
+    procs
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+        })
+        .any(|entry| {
+            let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+                return false;
+            };
+            fds.flatten().any(|fd| {
+                std::fs::read_link(fd.path()).is_ok_and(|target| target.as_os_str() == pipe)
+                    && is_open_for_reading(&entry.path().join("fdinfo").join(fd.file_name()))
+            })
+        })
+}
+
+/// Whether the descriptor whose `/proc/<pid>/fdinfo` entry is at `fdinfo` was
+/// opened read-only.
+fn is_open_for_reading(fdinfo: &Path) -> bool {
+    std::fs::read_to_string(fdinfo)
+        .ok()
+        .and_then(|info| {
+            let flags = info.lines().find_map(|line| line.strip_prefix("flags:"))?;
+            i32::from_str_radix(flags.trim(), 8).ok()
+        })
+        .is_some_and(|flags| flags & libc::O_ACCMODE == libc::O_RDONLY)
+}
+
+/// Start time of `pid` from `/proc/<pid>/stat`, in clock ticks since boot.
+pub fn proc_start_time(pid: u32) -> io::Result<u64> {
+    proc_stat_fields(pid)
+        .and_then(|fields| fields.get(19)?.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such process"))
+}
+
+/// Fields of `/proc/<pid>/stat` from field 3 (state) on, so that start time
+/// (field 22) is at index 19.
+fn proc_stat_fields(pid: u32) -> Option<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The command name in field 2 may contain spaces and parentheses, so
+    // count fields from the last `)`.
+    let (_, rest) = stat.rsplit_once(')')?;
+    Some(rest.split_whitespace().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::process::CommandExt,
+        process::{Command, Stdio},
+    };
+
+    use super::*;
+
+    fn state_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!(
+                "dockless-run-state-{}-{}",
+                std::process::id(),
+                name
+            ))
+            .join("state.json")
+    }
+
+    #[tokio::test]
+    async fn adopts_the_process_it_was_captured_from() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let path = state_path("adopt");
+        let state = RunState::capture(child.id()).unwrap();
+        state.save(&path).unwrap();
+        assert_eq!(RunState::load(&path).unwrap().start_time, state.start_time);
+        assert_eq!(state.group_members(), vec![child.id()]);
+
+        let adopted = AdoptedProcess::find(&path).unwrap();
+        assert_eq!(adopted.id(), child.id());
+        assert!(!adopted.has_dead_output());
+
+        adopted.kill();
+        tokio::time::timeout(std::time::Duration::from_secs(5), adopted.wait())
+            .await
+            .unwrap()
+            .unwrap();
+        child.wait().unwrap();
+
+        assert!(AdoptedProcess::find(&path).is_none());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn does_not_adopt_a_reused_pid() {
+        let path = state_path("reused");
+        let mut state = RunState::capture(std::process::id()).unwrap();
+        state.start_time += 1;
+        state.save(&path).unwrap();
+
+        assert!(AdoptedProcess::find(&path).is_none());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn notices_output_pipes_nobody_reads() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let adopted = AdoptedProcess::open(child.id()).unwrap();
+
+        let stdout = child.stdout.take().unwrap();
+        assert!(!adopted.has_dead_output());
+
+        drop(stdout);
+        assert!(adopted.has_dead_output());
+
+        adopted.kill();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{Context, Result};
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::{fd::RawFd, unix::ffi::OsStrExt},
+    path::Path,
+    ptr,
+};
+use tokio::process::Command;
+
+use crate::registry::Sandbox;
+
+/// Directories of the service that stay writable under `read_only_root`.
+const WRITABLE_DIRS: &[&str] = &["data", "logs"];
+
+/// `MOVE_MOUNT_F_EMPTY_PATH` from `linux/mount.h`, which `libc` lacks.
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+/// Arranges for the process spawned by `cmd` to run inside `sandbox`. The
+/// mounts are made in a mount namespace of its own between fork and exec, so
+/// dockless and other services are not affected. Needs `CAP_SYS_ADMIN`, so it
+/// has to come before privileges are dropped.
+///
+/// Whatever the settings, the node's data directory is replaced by an empty
+/// one holding only the service's own directory, `working_dir`. A service
+/// running as root would otherwise be able to read the admin token and the
+/// TLS key, and the other services' files.
+pub fn configure(cmd: &mut Command, sandbox: &Sandbox, working_dir: &str) -> Result<()> {
+    let hidden = HiddenDataDir::new(working_dir)?;
+
+    let mut writable = Vec::new();
+    if sandbox.read_only_root {
+        for dir in WRITABLE_DIRS {
+            let path = Path::new(working_dir).join(dir);
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("failed to resolve {}", path.display()))?;
+            writable.push(CString::new(path.as_os_str().as_bytes())?);
+        }
+        // Device nodes, `/dev/shm` and `/dev/pts` keep working.
+        writable.push(c"/dev".to_owned());
+    }
+
+    let Sandbox {
+        read_only_root,
+        private_tmp,
+        no_new_privileges,
+    } = *sandbox;
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::unshare(libc::CLONE_NEWNS) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Keep the mounts below from propagating back to the host.
+            mount(None, c"/", None, libc::MS_REC | libc::MS_PRIVATE, None)?;
+
+            hidden.apply()?;
+
+            if read_only_root {
+                // Bind each writable directory onto itself first, so that it
+                // is a mount of its own whose flags can be reset afterwards.
+                for path in &writable {
+                    mount(Some(path), path, None, libc::MS_BIND | libc::MS_REC, None)?;
+                }
+                set_read_only(c"/", true)?;
+                for path in &writable {
+                    set_read_only(path, false)?;
+                }
+            }
+
+            if private_tmp {
+                mount(
+                    Some(c"tmpfs"),
+                    c"/tmp",
+                    Some(c"tmpfs"),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    Some(c"mode=1777"),
+                )?;
+            }
+
+            if no_new_privileges && libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+/// The node's data directory, which the service only gets to see its own
+/// directory of.
+struct HiddenDataDir {
+    data_dir: CString,
+    /// `<data_dir>/services`, which has to be recreated on the empty data
+    /// directory.
+    services_dir: CString,
+    service_dir: CString,
+}
+
+impl HiddenDataDir {
+    /// Services live in `<data_dir>/services/<id>`.
+    fn new(working_dir: &str) -> Result<Self> {
+        let service_dir = Path::new(working_dir)
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {}", working_dir))?;
+        let services_dir = service_dir
+            .parent()
+            .with_context(|| format!("{} is not inside a data directory", working_dir))?;
+        let data_dir = services_dir
+            .parent()
+            .with_context(|| format!("{} is not inside a data directory", working_dir))?;
+
+        let c_path = |path: &Path| CString::new(path.as_os_str().as_bytes());
+        Ok(Self {
+            data_dir: c_path(data_dir)?,
+            services_dir: c_path(services_dir)?,
+            service_dir: c_path(&service_dir)?,
+        })
+    }
+
+    /// Takes a copy of the service's directory, covers the data directory
+    /// with an empty tmpfs and puts the copy back in its place.
+    /// Async-signal-safe.
+    fn apply(&self) -> io::Result<()> {
+        let tree = unsafe {
+            libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                self.service_dir.as_ptr(),
+                libc::OPEN_TREE_CLONE
+                    | libc::O_CLOEXEC as libc::c_uint
+                    | libc::AT_RECURSIVE as libc::c_uint,
+            )
+        };
+        if tree < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let tree = tree as RawFd;
+
+        let result = self.replace_with(tree);
+        unsafe { libc::close(tree) };
+        result
+    }
+
+    fn replace_with(&self, tree: RawFd) -> io::Result<()> {
+        mount(
+            Some(c"tmpfs"),
+            &self.data_dir,
+            Some(c"tmpfs"),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            Some(c"mode=755"),
+        )?;
+        for dir in [&self.services_dir, &self.service_dir] {
+            if unsafe { libc::mkdir(dir.as_ptr(), 0o755) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let moved = unsafe {
+            libc::syscall(
+                libc::SYS_move_mount,
+                tree,
+                c"".as_ptr(),
+                libc::AT_FDCWD,
+                self.service_dir.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            )
+        };
+        if moved != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The working directory was entered before the data directory was
+        // covered, and through its `..` the data directory could still be
+        // reached.
+        if unsafe { libc::chdir(self.service_dir.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// `mount(2)`. Async-signal-safe.
+fn mount(
+    source: Option<&CStr>,
+    target: &CStr,
+    fstype: Option<&CStr>,
+    flags: libc::c_ulong,
+    data: Option<&CStr>,
+) -> io::Result<()> {
+    let ptr_or_null = |s: Option<&CStr>| s.map_or(ptr::null(), CStr::as_ptr);
+    let mounted = unsafe {
+        libc::mount(
+            ptr_or_null(source),
+            target.as_ptr(),
+            ptr_or_null(fstype),
+            flags,
+            ptr_or_null(data).cast(),
+        )
+    };
+    if mounted != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sets or clears the read-only flag of the mount at `path` and every mount
+/// below it. Async-signal-safe.
+fn set_read_only(path: &CStr, read_only: bool) -> io::Result<()> {
+    let (attr_set, attr_clr) = if read_only {
+        (libc::MOUNT_ATTR_RDONLY, 0)
+    } else {
+        (0, libc::MOUNT_ATTR_RDONLY)
+    };
+    let attr = libc::mount_attr {
+        attr_set,
+        attr_clr,
+        propagation: 0,
+        userns_fd: 0,
+    };
+
+    let changed = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_RECURSIVE,
+            &attr,
+            std::mem::size_of::<libc::mount_attr>(),
+        )
+    };
+    if changed != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `<base>/data/services/<id>`, outside `/tmp` so that the private `/tmp`
+    /// does not cover it.
+    fn working_dir(name: &str) -> std::path::PathBuf {
+        let base = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("target")
+            .join(format!("dockless-sandbox-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&base);
+        let working_dir = base.join("data").join("services").join("web");
+        std::fs::create_dir_all(&working_dir).unwrap();
+        std::fs::write(base.join("data").join("admin_token"), "secret").unwrap();
+        std::fs::create_dir_all(base.join("data").join("services").join("other")).unwrap();
+        working_dir
+    }
+
+    async fn run_in_sandbox(sandbox: Sandbox, working_dir: &Path, script: &str) -> String {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script).current_dir(working_dir);
+        configure(&mut cmd, &sandbox, &working_dir.to_string_lossy()).unwrap();
+
+        let output = cmd.output().await.unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[tokio::test]
+    async fn confines_the_service_to_its_own_directories() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let working_dir = working_dir("strict");
+        let data_dir = working_dir
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let marker = std::env::temp_dir().join(format!("dockless-sandbox-{}", std::process::id()));
+        std::fs::write(&marker, "").unwrap();
+
+        let script = format!(
+            "ls {data}; ls {data}/services; echo kept > data/kept; \
+             touch {wd}/outside 2>/dev/null || echo read-only; \
+             test -e {marker} || echo private-tmp; \
+             grep NoNewPrivs /proc/self/status",
+            data = data_dir.display(),
+            wd = working_dir.display(),
+            marker = marker.display(),
+        );
+        let sandbox = Sandbox {
+            read_only_root: true,
+            private_tmp: true,
+            no_new_privileges: true,
+        };
+        let output = run_in_sandbox(sandbox, &working_dir, &script).await;
+
+        assert_eq!(
+            output,
+            "services\nweb\nread-only\nprivate-tmp\nNoNewPrivs:\t1\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(working_dir.join("data").join("kept")).unwrap(),
+            "kept\n"
+        );
+        assert!(data_dir.join("admin_token").exists());
+
+        std::fs::remove_file(&marker).unwrap();
+        std::fs::remove_dir_all(data_dir.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn hides_the_data_directory_even_without_protections() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let working_dir = working_dir("open");
+        let data_dir = working_dir
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let sandbox = Sandbox {
+            read_only_root: false,
+            private_tmp: false,
+            no_new_privileges: false,
+        };
+        let script = format!(
+            "ls {}; touch writable && echo writable; grep NoNewPrivs /proc/self/status",
+            data_dir.display()
+        );
+        let output = run_in_sandbox(sandbox, &working_dir, &script).await;
+
+        assert_eq!(output, "services\nwritable\nNoNewPrivs:\t0\n");
+        assert!(working_dir.join("writable").exists());
+
+        std::fs::remove_dir_all(data_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn needs_a_working_directory_inside_a_data_directory() {
+        let mut cmd = Command::new("true");
+        let sandbox = Sandbox {
+            read_only_root: false,
+            private_tmp: false,
+            no_new_privileges: true,
+        };
+        assert!(configure(&mut cmd, &sandbox, "/").is_err());
+        assert!(configure(&mut cmd, &sandbox, "/nonexistent/services/web").is_err());
+    }
+}
@@ -1,8 +1,19 @@
 use serde::Serialize;
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::sync::RwLock;
 
-use super::log_buffer::LogBuffer;
+use crate::config::LogRotation;
+
+use super::{exit::LastExit, health::HealthStatus, log_buffer::LogBuffer, notify::NotifyState};
+use crate::registry::{
+    HealthCheck, KillMode, LogFormat, OrphanPolicy, ProcessSettings, Readiness, Resources,
+    RestartBackoff, RestartPolicy, RunAs, Sandbox, ServiceDefinition, parse_signal,
+};
 
 #[derive(Debug, Clone, Serialize)]
 pub enum ServiceState {
@@ -11,9 +22,21 @@ pub enum ServiceState {
     Stopping,
     Stopped,
     Crashed,
+    /// The process keeps exiting shortly after being started and the
+    /// supervisor is waiting out the backoff delay.
+    CrashLoop,
     Failed,
 }
 
+/// Progress of the automatic restart backoff, as reported by the API.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestartStatus {
+    /// Exits in a row that happened before the process became stable.
+    pub consecutive_failures: u32,
+    /// When the supervisor will start the process again, if it is waiting.
+    pub next_restart_at: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct Service {
     pub id: String,
@@ -22,46 +45,122 @@ pub struct Service {
 
     pub args: Vec<String>,
     pub env: HashMap<String, String>,
-    pub auto_restart: bool,
+    pub restart_policy: RestartPolicy,
+    /// Whether `SupervisorManager::start_all` starts this service: always
+    /// under the `always` restart policy, and otherwise if the definition's
+    /// desired state was `running` when the service was registered.
+    pub start_on_boot: bool,
     pub restart_limit: Option<u32>,
     pub linux_capabilities: Vec<String>,
+    pub restart_backoff: RestartBackoff,
+    pub health_check: Option<HealthCheck>,
+    pub readiness: Readiness,
+    pub watchdog_secs: Option<u64>,
+    pub stop_signal: i32,
+    pub stop_timeout: Duration,
+    pub kill_mode: KillMode,
+    pub orphan_policy: OrphanPolicy,
+    pub resources: Option<Resources>,
+    pub process: Option<ProcessSettings>,
+    pub run_as: Option<RunAs>,
+    pub sandbox: Option<Sandbox>,
+    pub log_format: LogFormat,
 
     pub working_dir: String,
 
     pub state: Arc<RwLock<ServiceState>>,
     pub log_buffer: LogBuffer,
     pub pid: Arc<RwLock<Option<u32>>>,
+    pub restarts: Arc<RwLock<RestartStatus>>,
+    /// Latest health check result while the process runs. `None` when no
+    /// check is configured or the process is not running.
+    pub health: Arc<RwLock<Option<HealthStatus>>>,
+    pub notify: Arc<RwLock<NotifyState>>,
+    pub last_exit: Arc<RwLock<Option<LastExit>>>,
+    /// Processes the current process forked, by PID, with their start times,
+    /// as the reaper last saw them. Lets helpers that left its process group
+    /// be told apart and killed once they are reparented.
+    pub forked: Arc<Mutex<HashMap<u32, u64>>>,
 }
 
 impl Service {
+    /// Creates the log buffer of a service that does not have one yet.
+    /// There must only ever be one per service: each numbers its entries and
+    /// rotates the log file on its own. `log_rotation` is the node's default
+    /// rotation settings.
+    pub fn create_log_buffer(
+        def: &ServiceDefinition,
+        working_dir: &str,
+        log_rotation: &LogRotation,
+    ) -> LogBuffer {
+        let log_file = PathBuf::from(working_dir).join("logs").join("service.log");
+        LogBuffer::new(log_file, &def.effective_log_rotation(log_rotation))
+    }
+
+    /// Builds the runtime service for `def`. `env` is the definition's
+    /// environment with node-provided variables such as `PORT` added.
+    /// `log_buffer` comes from [`Service::create_log_buffer`] for a new
+    /// service, and is the current service's buffer for one that replaces it.
     pub fn new(
-        id: String,
-        name: String,
-        binary_path: String,
-        args: Vec<String>,
+        def: &ServiceDefinition,
         env: HashMap<String, String>,
-        auto_restart: bool,
-        restart_limit: Option<u32>,
-        linux_capabilities: Vec<String>,
         working_dir: String,
+        log_buffer: LogBuffer,
     ) -> Self {
-        let log_file = PathBuf::from(&working_dir).join("logs").join("service.log");
+        let stop_signal = parse_signal(&def.stop_signal).unwrap_or_else(|e| {
+            tracing::warn!("[{}] {}, using SIGTERM", def.id, e);
+            libc::SIGTERM
+        });
+
+        let restart_policy = def.effective_restart_policy();
         Self {
-            id,
-            name,
-            binary_path,
-            args,
+            id: def.id.clone(),
+            name: def.name.clone(),
+            binary_path: def.binary_path.clone(),
+            args: def.args.clone(),
             env,
-            auto_restart,
-            restart_limit,
-            linux_capabilities,
+            restart_policy,
+            start_on_boot: def.starts_on_boot(),
+            restart_limit: def.restart_limit,
+            linux_capabilities: def.linux_capabilities.clone(),
+            restart_backoff: def.restart_backoff.clone(),
+            health_check: def.health_check.clone(),
+            readiness: def.readiness.clone(),
+            watchdog_secs: def.watchdog_secs,
+            stop_signal,
+            stop_timeout: Duration::from_secs(def.stop_timeout_secs),
+            kill_mode: def.kill_mode,
+            orphan_policy: def.orphan_policy,
+            resources: def.resources.clone(),
+            process: def.process.clone(),
+            run_as: def.run_as.clone(),
+            sandbox: def.sandbox,
+            log_format: def.log_format,
             working_dir,
             state: Arc::new(RwLock::new(ServiceState::Stopped)),
-            log_buffer: LogBuffer::new(log_file),
+            log_buffer,
             pid: Arc::new(RwLock::new(None)),
+            restarts: Arc::new(RwLock::new(RestartStatus::default())),
+            health: Arc::new(RwLock::new(None)),
+            notify: Arc::new(RwLock::new(NotifyState::default())),
+            last_exit: Arc::new(RwLock::new(None)),
+            forked: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Takes over the runtime state of `current`, the service this one
+    /// replaces, so that a supervisor still running `current` keeps reporting
+    /// through it and the new settings only apply from the next start.
+    pub fn keep_runtime_state(&mut self, current: &Service) {
+        self.state = current.state.clone();
+        self.pid = current.pid.clone();
+        self.restarts = current.restarts.clone();
+        self.health = current.health.clone();
+        self.notify = current.notify.clone();
+        self.last_exit = current.last_exit.clone();
+        self.forked = current.forked.clone();
+    }
+
     pub async fn set_state(&self, new_state: ServiceState) {
         let mut state = self.state.write().await;
         *state = new_state;
@@ -79,4 +178,54 @@ impl Service {
         let mut pid = self.pid.write().await;
         *pid = new_pid;
     }
+
+    pub async fn get_restart_status(&self) -> RestartStatus {
+        self.restarts.read().await.clone()
+    }
+
+    pub async fn set_restart_status(&self, status: RestartStatus) {
+        let mut restarts = self.restarts.write().await;
+        *restarts = status;
+    }
+
+    pub async fn get_health(&self) -> Option<HealthStatus> {
+        self.health.read().await.clone()
+    }
+
+    pub async fn set_health(&self, status: Option<HealthStatus>) {
+        let mut health = self.health.write().await;
+        *health = status;
+    }
+
+    pub async fn get_notify_state(&self) -> NotifyState {
+        self.notify.read().await.clone()
+    }
+
+    pub async fn set_notify_state(&self, state: NotifyState) {
+        let mut notify = self.notify.write().await;
+        *notify = state;
+    }
+
+    pub async fn get_last_exit(&self) -> Option<LastExit> {
+        self.last_exit.read().await.clone()
+    }
+
+    pub async fn set_last_exit(&self, last_exit: Option<LastExit>) {
+        let mut current = self.last_exit.write().await;
+        *current = last_exit;
+    }
+
+    /// Path of the `NOTIFY_SOCKET` handed to the service.
+    pub fn notify_socket_path(&self) -> PathBuf {
+        PathBuf::from(&self.working_dir)
+            .join("run")
+            .join("notify.sock")
+    }
+
+    /// Path of the state file that identifies the running process.
+    pub fn run_state_path(&self) -> PathBuf {
+        PathBuf::from(&self.working_dir)
+            .join("run")
+            .join("state.json")
+    }
 }
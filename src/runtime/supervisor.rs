@@ -1,182 +1,609 @@
 use anyhow::{Context, Result};
-use std::process::Stdio;
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+};
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::{Child, Command},
-    sync::broadcast,
-    time::{Duration, sleep, timeout},
+    sync::{RwLock, broadcast, mpsc},
+    time::{Duration, Instant, sleep, timeout},
 };
 use tracing::info;
 
-use crate::runtime::service::{Service, ServiceState};
+use crate::{
+    platform::port_manager::PortManager,
+    registry::{KillMode, OrphanPolicy, RunAs},
+    runtime::{
+        cgroup::Cgroup,
+        credentials::{self, Credentials},
+        exit::{self, LastExit},
+        health::HealthMonitor,
+        notify::{NotifySocket, NotifyState},
+        process_settings,
+        readiness::ReadinessMonitor,
+        reaper::Reaper,
+        run_state::{self, AdoptedProcess, RunState},
+        sandbox,
+        service::{RestartStatus, Service, ServiceState},
+    },
+};
+
+/// Why a supervised process stopped running without a stop request.
+enum Exit {
+    Exited(ExitStatus),
+    /// Killed by the supervisor after failing its health check.
+    Unhealthy,
+    /// Killed by the supervisor after missing its watchdog deadline.
+    WatchdogTimeout,
+    /// An adopted process exited. Its exit status is not available.
+    Vanished,
+}
+
+/// A service process, either spawned by this supervisor or adopted from a
+/// previous dockless instance.
+enum Process {
+    Spawned(Child),
+    Adopted(AdoptedProcess),
+}
+
+impl Process {
+    fn id(&self) -> Option<u32> {
+        match self {
+            Process::Spawned(child) => child.id(),
+            Process::Adopted(adopted) => Some(adopted.id()),
+        }
+    }
+
+    /// Waits for the process to exit. The status is `None` for adopted
+    /// processes.
+    async fn wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        match self {
+            Process::Spawned(child) => child.wait().await.map(Some),
+            Process::Adopted(adopted) => adopted.wait().await.map(|_| None),
+        }
+    }
+
+    async fn kill(&mut self) -> Option<ExitStatus> {
+        match self {
+            Process::Spawned(child) => {
+                let _ = child.kill().await;
+                child.wait().await.ok()
+            }
+            Process::Adopted(adopted) => {
+                adopted.kill();
+                None
+            }
+        }
+    }
+}
 
 pub struct Supervisor {
-    child: Option<Child>,
+    child: Option<Process>,
+    port_manager: Option<Arc<RwLock<PortManager>>>,
+    reaper: Arc<Reaper>,
 }
 
 impl Supervisor {
-    pub fn new() -> Self {
-        Self { child: None }
+    pub fn new(port_manager: Option<Arc<RwLock<PortManager>>>, reaper: Arc<Reaper>) -> Self {
+        Self {
+            child: None,
+            port_manager,
+            reaper,
+        }
     }
 
-    async fn graceful_stop(child: &mut Child) {
+    /// Sends the service's stop signal, waits up to its stop timeout and then
+    /// kills whatever is left of the process group. Returns the exit status
+    /// when it is known.
+    async fn graceful_stop(child: &mut Process, service: &Service) -> Option<ExitStatus> {
         #[cfg(unix)]
         {
             if let Some(pid) = child.id() {
-                info!("Sending SIGTERM to PID {}", pid);
+                let target = match service.kill_mode {
+                    KillMode::Group => -(pid as i32),
+                    KillMode::Main => pid as i32,
+                };
+
+                info!(
+                    "[{}] sending signal {} to {} {}",
+                    service.id,
+                    service.stop_signal,
+                    if service.kill_mode == KillMode::Group {
+                        "process group"
+                    } else {
+                        "PID"
+                    },
+                    pid
+                );
                 unsafe {
-                    libc::kill(pid as i32, libc::SIGTERM);
+                    libc::kill(target, service.stop_signal);
                 }
 
-                let wait_result = timeout(Duration::from_secs(5), child.wait()).await;
+                let wait_result = timeout(service.stop_timeout, child.wait()).await;
 
                 match wait_result {
-                    Ok(Ok(status)) => {
+                    Ok(Ok(Some(status))) => {
                         info!("Process {} exited gracefully with status: {}", pid, status);
-                        return;
+                        Self::kill_leftovers(service, pid);
+                        return Some(status);
+                    }
+                    Ok(Ok(None)) => {
+                        info!("Process {} exited gracefully", pid);
+                        Self::kill_leftovers(service, pid);
+                        return None;
                     }
                     Ok(Err(e)) => {
                         info!("Error waiting for process {}: {}", pid, e);
                     }
                     Err(_) => {
                         info!(
-                            "Process {} did not exit after SIGTERM, sending SIGKILL",
-                            pid
+                            "Process {} did not exit within {}s, sending SIGKILL",
+                            pid,
+                            service.stop_timeout.as_secs()
                         );
                     }
                 }
+
+                Self::kill_leftovers(service, pid);
             }
         }
 
-        let _ = child.kill().await;
-        let _ = child.wait().await;
+        child.kill().await
     }
 
-    pub async fn run_supervised(
-        &mut self,
-        service: Service,
-        mut global_shutdown_rx: broadcast::Receiver<()>,
-        mut service_shutdown_rx: broadcast::Receiver<()>,
-    ) -> Result<()> {
-        let mut restart_count = 0;
-        loop {
-            info!(
-                "starting child process: {} from working_dir: {}",
-                service.binary_path, service.working_dir
-            );
-            service.set_state(ServiceState::Starting).await;
+    async fn stop_child(&mut self, service: &Service) -> Option<ExitStatus> {
+        match &mut self.child {
+            Some(child) => Self::graceful_stop(child, service).await,
+            None => None,
+        }
+    }
 
-            let full_binary_path =
-                std::path::Path::new(&service.working_dir).join(&service.binary_path);
-            if !full_binary_path.exists() {
-                let err_msg = format!(
-                    "Binary not found at: {} (resolved to: {})",
-                    service.binary_path,
-                    full_binary_path.display()
+    /// Classifies how the process ended and stores it as the service's last
+    /// exit.
+    async fn record_exit(
+        service: &Service,
+        status: Option<ExitStatus>,
+        pid: Option<u32>,
+        stopped_by: Option<&str>,
+        started_at: Instant,
+        oom_kills_before: Option<u64>,
+    ) -> LastExit {
+        let ran_for = started_at.elapsed();
+        let oom_kills =
+            oom_kills_before.map(|before| (before, Cgroup::for_service(&service.id).oom_kills()));
+        let oom_killed = exit::oom_killed(status, pid, oom_kills, stopped_by, ran_for).await;
+
+        let last_exit =
+            LastExit::classify(status, oom_killed, stopped_by.map(str::to_string), ran_for);
+        service.set_last_exit(Some(last_exit.clone())).await;
+
+        last_exit
+    }
+
+    /// Stops a process of `service` left running by a previous dockless
+    /// instance, if there is one.
+    pub async fn terminate_orphan(service: &Service) {
+        let path = service.run_state_path();
+        let state = RunState::load(&path);
+
+        if let Some(adopted) = AdoptedProcess::find(&path) {
+            let msg = format!(
+                "stopping PID {} left running by a previous dockless instance",
+                adopted.id()
+            );
+            tracing::warn!("[{}] {}", service.id, msg);
+            service.log_buffer.push("info".to_string(), msg).await;
+            Self::graceful_stop(&mut Process::Adopted(adopted), service).await;
+        } else if let Some(state) = state {
+            // The main process is gone, but processes it forked may not be.
+            let leftovers = state.group_members();
+            if !leftovers.is_empty() {
+                let msg = format!(
+                    "killing {} leftover process(es) of PID {} from a previous dockless instance",
+                    leftovers.len(),
+                    state.pid
                 );
-                tracing::error!("{}", err_msg);
-                service.set_state(ServiceState::Failed).await;
-                service
-                    .log_buffer
-                    .push("error".to_string(), err_msg.clone())
-                    .await;
-                anyhow::bail!("{}", err_msg);
-            }
+                tracing::warn!("[{}] {}", service.id, msg);
+                service.log_buffer.push("info".to_string(), msg).await;
 
-            // Apply Linux capabilities via `setcap` before starting the process.
-            if !service.linux_capabilities.is_empty() {
-                let cap_string = service.linux_capabilities.join(",");
-                let result = std::process::Command::new("setcap")
-                    .arg(&cap_string)
-                    .arg(&full_binary_path)
-                    .output();
-                match result {
-                    Ok(out) if out.status.success() => {
-                        tracing::info!("[{}] setcap '{}' applied", service.id, cap_string);
-                    }
-                    Ok(out) => {
-                        let stderr = String::from_utf8_lossy(&out.stderr);
-                        let err_msg = format!("setcap failed ({}): {}", out.status, stderr.trim());
-                        tracing::error!("[{}] {}", service.id, err_msg);
-                        service
-                            .log_buffer
-                            .push("error".to_string(), err_msg.clone())
-                            .await;
-                        service.set_state(ServiceState::Failed).await;
-                        anyhow::bail!("{}", err_msg);
-                    }
-                    Err(e) => {
-                        let err_msg = format!("failed to run setcap: {}", e);
-                        tracing::error!("[{}] {}", service.id, err_msg);
-                        service
-                            .log_buffer
-                            .push("error".to_string(), err_msg.clone())
-                            .await;
-                        service.set_state(ServiceState::Failed).await;
-                        anyhow::bail!("{}", err_msg);
+                for pid in leftovers {
+                    unsafe {
+                        libc::kill(pid as i32, libc::SIGKILL);
                     }
                 }
             }
+            Cgroup::for_service(&service.id).kill();
+        }
 
-            let mut cmd = Command::new(&service.binary_path);
-            cmd.args(&service.args);
-            cmd.current_dir(&service.working_dir);
-            for (k, v) in &service.env {
-                cmd.env(k, v);
+        RunState::remove(&path);
+    }
+
+    /// Kills every process left of the service process `pid`. Children are
+    /// spawned as group leaders, so killing the group reaches the helpers
+    /// they forked. Helpers that left the group, such as daemons that called
+    /// `setsid`, are found through the service's cgroup if it has one, and
+    /// otherwise through what the reaper saw them descend from.
+    fn kill_leftovers(service: &Service, pid: u32) {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+
+        Cgroup::for_service(&service.id).kill();
+
+        let forked = std::mem::take(&mut *service.forked.lock().unwrap());
+        for (pid, start_time) in forked {
+            if run_state::proc_start_time(pid).is_ok_and(|t| t == start_time) {
+                unsafe {
+                    libc::kill(pid as i32, libc::SIGKILL);
+                }
             }
+        }
+    }
 
-            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    /// Starts a new process for `service` with its output wired to the log
+    /// buffer.
+    async fn spawn_child(
+        service: &Service,
+        reaper: &Reaper,
+    ) -> Result<(Child, Option<NotifySocket>)> {
+        info!(
+            "starting child process: {} from working_dir: {}",
+            service.binary_path, service.working_dir
+        );
+        service.set_state(ServiceState::Starting).await;
+
+        let full_binary_path =
+            std::path::Path::new(&service.working_dir).join(&service.binary_path);
+        if !full_binary_path.exists() {
+            let err_msg = format!(
+                "Binary not found at: {} (resolved to: {})",
+                service.binary_path,
+                full_binary_path.display()
+            );
+            tracing::error!("{}", err_msg);
+            service.set_state(ServiceState::Failed).await;
+            service
+                .log_buffer
+                .push("error".to_string(), err_msg.clone())
+                .await;
+            anyhow::bail!("{}", err_msg);
+        }
 
-            let mut child = match cmd.spawn() {
-                Ok(c) => c,
+        let credentials = match &service.run_as {
+            Some(run_as) => match Self::prepare_credentials(service, run_as) {
+                Ok(credentials) => Some(credentials),
                 Err(e) => {
-                    tracing::error!("failed to spawn child process: {}", e);
-                    service.set_state(ServiceState::Failed).await;
+                    let err_msg = format!("failed to set up run_as user: {:#}", e);
+                    tracing::error!("[{}] {}", service.id, err_msg);
                     service
                         .log_buffer
-                        .push("error".to_string(), format!("Failed to start: {}", e))
+                        .push("error".to_string(), err_msg.clone())
                         .await;
-                    anyhow::bail!(
-                        "failed to spawn child process at {}: {}",
-                        service.binary_path,
-                        e
-                    );
+                    service.set_state(ServiceState::Failed).await;
+                    anyhow::bail!("{}", err_msg);
                 }
-            };
+            },
+            None => None,
+        };
+
+        let mut cmd = Command::new(&service.binary_path);
+        cmd.args(&service.args);
+        cmd.current_dir(&service.working_dir);
+        for (k, v) in &service.env {
+            cmd.env(k, v);
+        }
 
-            if let Some(pid) = child.id() {
-                service.set_pid(Some(pid)).await;
+        let notify_socket = Self::bind_notify_socket(service).await;
+        if let Some(socket) = &notify_socket {
+            if let Some(credentials) = &credentials
+                && let Err(e) = credentials.chown_tree(socket.path())
+            {
+                tracing::warn!("[{}] failed to chown notify socket: {}", service.id, e);
+            }
+            cmd.env("NOTIFY_SOCKET", socket.path());
+            if let Some(secs) = service.watchdog_secs {
+                cmd.env("WATCHDOG_USEC", (secs * 1_000_000).to_string());
             }
+        }
 
-            if let Some(stdout) = child.stdout.take() {
-                let log_buffer = service.log_buffer.clone();
-                let service_id = service.id.clone();
-                tokio::spawn(async move {
-                    let reader = BufReader::new(stdout);
-                    let mut lines = reader.lines();
-                    while let Ok(Some(line)) = lines.next_line().await {
-                        log_buffer.push("info".to_string(), line).await;
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        // Lead a new process group so the whole tree can be signalled.
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        if let Some(settings) = &service.process
+            && let Err(e) = process_settings::configure(&mut cmd, settings)
+        {
+            let err_msg = format!("invalid process settings: {:#}", e);
+            tracing::error!("[{}] {}", service.id, err_msg);
+            service
+                .log_buffer
+                .push("error".to_string(), err_msg.clone())
+                .await;
+            service.set_state(ServiceState::Failed).await;
+            anyhow::bail!("{}", err_msg);
+        }
+
+        if let Some(resources) = &service.resources {
+            let cgroup = Cgroup::for_service(&service.id);
+            if let Err(e) = cgroup.apply(resources) {
+                let err_msg = format!("failed to apply resource limits: {:#}", e);
+                tracing::error!("[{}] {}", service.id, err_msg);
+                service
+                    .log_buffer
+                    .push("error".to_string(), err_msg.clone())
+                    .await;
+                service.set_state(ServiceState::Failed).await;
+                anyhow::bail!("{}", err_msg);
+            }
+
+            // Join the cgroup between fork and exec, so that the limits apply
+            // from the first instruction and to everything the service forks.
+            let procs = CString::new(cgroup.procs_path().as_os_str().as_bytes())?;
+            unsafe {
+                cmd.pre_exec(move || {
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(std::io::Error::last_os_error());
                     }
-                    info!("[{}] stdout reader finished", service_id);
+                    let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                    libc::close(fd);
+                    if written != 1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
                 });
             }
+        }
 
-            if let Some(stderr) = child.stderr.take() {
-                let log_buffer = service.log_buffer.clone();
-                let service_id = service.id.clone();
-                tokio::spawn(async move {
-                    let reader = BufReader::new(stderr);
-                    let mut lines = reader.lines();
-                    while let Ok(Some(line)) = lines.next_line().await {
-                        log_buffer.push("error".to_string(), line).await;
+        if let Some(settings) = &service.sandbox
+            && let Err(e) = sandbox::configure(&mut cmd, settings, &service.working_dir)
+        {
+            let err_msg = format!("failed to set up sandbox: {:#}", e);
+            tracing::error!("[{}] {}", service.id, err_msg);
+            service
+                .log_buffer
+                .push("error".to_string(), err_msg.clone())
+                .await;
+            service.set_state(ServiceState::Failed).await;
+            anyhow::bail!("{}", err_msg);
+        }
+
+        if let Err(e) =
+            credentials::configure(&mut cmd, credentials.as_ref(), &service.linux_capabilities)
+        {
+            let err_msg = format!("invalid linux_capabilities: {:#}", e);
+            tracing::error!("[{}] {}", service.id, err_msg);
+            service
+                .log_buffer
+                .push("error".to_string(), err_msg.clone())
+                .await;
+            service.set_state(ServiceState::Failed).await;
+            anyhow::bail!("{}", err_msg);
+        }
+
+        // Take the process down with dockless unless it is meant to be adopted
+        // by the next instance. The signal fires when the spawning thread
+        // exits, which for a runtime worker thread is when dockless exits.
+        // Changing credentials clears it, so this comes after the privilege
+        // drop.
+        #[cfg(unix)]
+        if service.orphan_policy == OrphanPolicy::Terminate {
+            let parent = std::process::id();
+            let signal = service.stop_signal;
+            unsafe {
+                cmd.pre_exec(move || {
+                    if libc::prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong) != 0 {
+                        return Err(std::io::Error::last_os_error());
                     }
-                    info!("[{}] stderr reader finished", service_id);
+                    // Dockless may have died before the prctl took effect.
+                    if libc::getppid() as u32 != parent {
+                        libc::raise(signal);
+                    }
+                    Ok(())
                 });
             }
+        }
 
-            self.child = Some(child);
+        let mut child = match reaper.spawn(&mut cmd, service) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::error!("failed to spawn child process: {}", e);
+                service.set_state(ServiceState::Failed).await;
+                service
+                    .log_buffer
+                    .push("error".to_string(), format!("Failed to start: {}", e))
+                    .await;
+                anyhow::bail!(
+                    "failed to spawn child process at {}: {}",
+                    service.binary_path,
+                    e
+                );
+            }
+        };
+
+        if let Some(stdout) = child.stdout.take() {
+            let log_buffer = service.log_buffer.clone();
+            let log_format = service.log_format;
+            let service_id = service.id.clone();
+            tokio::spawn(async move {
+                let reader = BufReader::new(stdout);
+                let mut lines = reader.lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log_buffer.push_output("info", line, log_format).await;
+                }
+                info!("[{}] stdout reader finished", service_id);
+            });
+        }
 
-            service.set_state(ServiceState::Running).await;
+        if let Some(stderr) = child.stderr.take() {
+            let log_buffer = service.log_buffer.clone();
+            let log_format = service.log_format;
+            let service_id = service.id.clone();
+            tokio::spawn(async move {
+                let reader = BufReader::new(stderr);
+                let mut lines = reader.lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log_buffer.push_output("error", line, log_format).await;
+                }
+                info!("[{}] stderr reader finished", service_id);
+            });
+        }
+
+        Ok((child, notify_socket))
+    }
+
+    /// Resolves the user `service` runs as and hands its `data/` and `logs/`
+    /// directories over to it.
+    fn prepare_credentials(service: &Service, run_as: &RunAs) -> Result<Credentials> {
+        let credentials = Credentials::resolve(run_as, &service.id, &service.working_dir)?;
+
+        for dir in ["data", "logs"] {
+            let path = std::path::Path::new(&service.working_dir).join(dir);
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            credentials
+                .chown_tree(&path)
+                .with_context(|| format!("failed to chown {}", path.display()))?;
+        }
+
+        Ok(credentials)
+    }
+
+    /// Binds the service's notify socket, resetting what the previous run
+    /// reported.
+    async fn bind_notify_socket(service: &Service) -> Option<NotifySocket> {
+        service.set_notify_state(NotifyState::default()).await;
+
+        match NotifySocket::bind(&service.notify_socket_path()) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                let msg = format!("failed to create notify socket: {}", e);
+                tracing::warn!("[{}] {}", service.id, msg);
+                service.log_buffer.push("error".to_string(), msg).await;
+                None
+            }
+        }
+    }
+
+    pub async fn run_supervised(
+        &mut self,
+        service: Service,
+        mut global_shutdown_rx: broadcast::Receiver<()>,
+        mut service_shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<()> {
+        let mut restart_count = 0;
+        let mut consecutive_failures: u32 = 0;
+        service.set_restart_status(RestartStatus::default()).await;
+
+        let mut survivor = match service.orphan_policy {
+            OrphanPolicy::Adopt => match AdoptedProcess::find(&service.run_state_path()) {
+                // Its output went to the previous instance, and there is no
+                // way to hand it a new stdout or stderr.
+                Some(adopted) if adopted.has_dead_output() => {
+                    let msg = format!(
+                        "restarting PID {} left running by a previous dockless instance, its output pipes are closed",
+                        adopted.id()
+                    );
+                    tracing::warn!("[{}] {}", service.id, msg);
+                    service.log_buffer.push("info".to_string(), msg).await;
+                    Self::graceful_stop(&mut Process::Adopted(adopted), &service).await;
+                    None
+                }
+                adopted => adopted,
+            },
+            OrphanPolicy::Terminate => {
+                Self::terminate_orphan(&service).await;
+                None
+            }
+        };
+
+        let mut last_pid: Option<u32>;
+
+        loop {
+            let (process, notify_socket) = match survivor.take() {
+                Some(adopted) => {
+                    let msg = format!(
+                        "adopted PID {} left running by a previous dockless instance, its output is not captured",
+                        adopted.id()
+                    );
+                    info!("[{}] {}", service.id, msg);
+                    service.log_buffer.push("info".to_string(), msg).await;
+
+                    let notify_socket = Self::bind_notify_socket(&service).await;
+                    (Process::Adopted(adopted), notify_socket)
+                }
+                None => {
+                    let (child, notify_socket) = Self::spawn_child(&service, &self.reaper).await?;
+                    (Process::Spawned(child), notify_socket)
+                }
+            };
+
+            let pid = process.id();
+            let adopted = matches!(process, Process::Adopted(_));
+            last_pid = pid;
+            if let Some(pid) = pid {
+                service.set_pid(Some(pid)).await;
+
+                if !adopted {
+                    match RunState::capture(pid) {
+                        Ok(state) => {
+                            if let Err(e) = state.save(&service.run_state_path()) {
+                                tracing::warn!("[{}] failed to save run state: {}", service.id, e);
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "[{}] failed to read process identity: {}",
+                                service.id,
+                                e
+                            );
+                        }
+                    }
+                }
+            }
+            self.child = Some(process);
+            let started_at = Instant::now();
+            let oom_kills_before = service
+                .resources
+                .as_ref()
+                .and_then(|_| Cgroup::for_service(&service.id).oom_kills());
+
+            // The readiness monitor moves the service to `Running`. An
+            // adopted process was already running before.
+            let (not_ready_tx, mut not_ready_rx) = mpsc::channel(1);
+            if adopted {
+                service.set_state(ServiceState::Running).await;
+            }
+            let _readiness_monitor = pid.filter(|_| !adopted).map(|pid| {
+                ReadinessMonitor::spawn(
+                    service.clone(),
+                    pid,
+                    self.port_manager.clone(),
+                    not_ready_tx,
+                )
+            });
+
+            let (watchdog_tx, mut watchdog_rx) = mpsc::channel(1);
+            let _notify_monitor = notify_socket.zip(pid).map(|(socket, pid)| {
+                socket.listen(
+                    service.clone(),
+                    pid,
+                    service.watchdog_secs.map(Duration::from_secs),
+                    watchdog_tx,
+                )
+            });
+
+            let (unhealthy_tx, mut unhealthy_rx) = mpsc::channel(1);
+            let _health_monitor = service
+                .health_check
+                .clone()
+                .map(|check| HealthMonitor::spawn(service.clone(), check, unhealthy_tx));
 
             let wait_future = async {
                 if let Some(child) = &mut self.child {
@@ -189,14 +616,14 @@ impl Supervisor {
                 }
             };
 
-            tokio::select! {
+            let exit = tokio::select! {
                 _ = global_shutdown_rx.recv() => {
                     info!("[{}] global shutdown received in supervisor", service.id);
                     service.set_state(ServiceState::Stopping).await;
 
-                    if let Some(child) = &mut self.child {
-                        Self::graceful_stop(child).await;
-                    }
+                    let status = self.stop_child(&service).await;
+                    Self::record_exit(&service, status, pid, Some("dockless shutdown"), started_at, oom_kills_before)
+                        .await;
 
                     service.set_pid(None).await;
                     service.set_state(ServiceState::Stopped).await;
@@ -207,9 +634,9 @@ impl Supervisor {
                     info!("[{}] service shutdown received in supervisor", service.id);
                     service.set_state(ServiceState::Stopping).await;
 
-                    if let Some(child) = &mut self.child {
-                        Self::graceful_stop(child).await;
-                    }
+                    let status = self.stop_child(&service).await;
+                    Self::record_exit(&service, status, pid, Some("stop request"), started_at, oom_kills_before)
+                        .await;
 
                     service.set_pid(None).await;
                     service.set_state(ServiceState::Stopped).await;
@@ -219,41 +646,221 @@ impl Supervisor {
                 result = wait_future => {
                     let status = result?;
 
-                    info!("[{}] child exited with status: {}", service.id, status);
+                    if let Some(pid) = pid {
+                        Self::kill_leftovers(&service, pid);
+                    }
+
+                    let last_exit =
+                        Self::record_exit(&service, status, pid, None, started_at, oom_kills_before)
+                            .await;
+                    info!("[{}] child {}", service.id, last_exit.summary);
+                    if !status.is_some_and(|s| s.success()) {
+                        service
+                            .log_buffer
+                            .push("error".to_string(), format!("process {}", last_exit.summary))
+                            .await;
+                    }
+
+                    match status {
+                        Some(status) => Exit::Exited(status),
+                        None => Exit::Vanished,
+                    }
+                }
+
+                Some(reason) = not_ready_rx.recv() => {
+                    tracing::error!("[{}] {}", service.id, reason);
+                    service.log_buffer.push("error".to_string(), reason).await;
+
+                    let status = self.stop_child(&service).await;
+                    Self::record_exit(&service, status, pid, Some("readiness check"), started_at, oom_kills_before)
+                        .await;
 
                     self.child = None;
                     service.set_pid(None).await;
+                    service.set_state(ServiceState::Failed).await;
+                    break;
+                }
 
-                    if global_shutdown_rx.try_recv().is_ok() ||
-                       service_shutdown_rx.try_recv().is_ok() {
-                        break;
-                    }
-                    if status.success() {
-                        restart_count = 0;
-                        service.set_state(ServiceState::Stopped).await;
-                    } else {
-                        service.set_state(ServiceState::Crashed).await;
-                        restart_count += 1;
-                    }
+                Some(()) = unhealthy_rx.recv() => {
+                    let msg = "restarting after repeated health check failures".to_string();
+                    tracing::warn!("[{}] {}", service.id, msg);
+                    service.log_buffer.push("error".to_string(), msg).await;
 
-                    if !service.auto_restart {
-                        break;
-                    }
+                    let status = self.stop_child(&service).await;
+                    Self::record_exit(&service, status, pid, Some("health check"), started_at, oom_kills_before)
+                        .await;
+                    Exit::Unhealthy
+                }
 
-                    if let Some(limit) = service.restart_limit {
-                        if restart_count >= limit {
-                            service.set_state(ServiceState::Failed).await;
-                            break;
-                        }
-                    }
+                Some(()) = watchdog_rx.recv() => {
+                    let status = self.stop_child(&service).await;
+                    Self::record_exit(&service, status, pid, Some("watchdog"), started_at, oom_kills_before)
+                        .await;
+                    Exit::WatchdogTimeout
+                }
+            };
+
+            self.child = None;
+            service.set_pid(None).await;
+            service.set_health(None).await;
+            RunState::remove(&service.run_state_path());
+            Cgroup::for_service(&service.id).remove();
+            if let Some(pid) = pid {
+                self.reaper.release(pid);
+            }
+
+            if global_shutdown_rx.try_recv().is_ok() || service_shutdown_rx.try_recv().is_ok() {
+                break;
+            }
+
+            let stable_after = Duration::from_secs(service.restart_backoff.stable_after_secs);
+            let clean_exit = matches!(exit, Exit::Exited(status) if status.success());
+            consecutive_failures = count_failures(
+                consecutive_failures,
+                clean_exit,
+                started_at.elapsed(),
+                stable_after,
+            );
+
+            if clean_exit {
+                restart_count = 0;
+                service.set_state(ServiceState::Stopped).await;
+            } else {
+                service.set_state(ServiceState::Crashed).await;
+                restart_count += 1;
+            }
 
-                    info!("[{}] restarting child in 3 seconds...", service.id);
-                    sleep(Duration::from_secs(3)).await;
+            // A restart requested by the health check or the watchdog is
+            // honoured whatever the restart policy says.
+            if matches!(exit, Exit::Exited(_) | Exit::Vanished)
+                && !service.restart_policy.should_restart(clean_exit)
+            {
+                info!(
+                    "[{}] not restarting, restart policy is {}",
+                    service.id, service.restart_policy
+                );
+                break;
+            }
+
+            if let Some(limit) = service.restart_limit
+                && restart_count >= limit
+            {
+                service.set_state(ServiceState::Failed).await;
+                break;
+            }
+
+            let delay = service
+                .restart_backoff
+                .delay_for(consecutive_failures.max(1));
+
+            if consecutive_failures >= service.restart_backoff.crash_loop_threshold {
+                if consecutive_failures == service.restart_backoff.crash_loop_threshold {
+                    let msg = format!(
+                        "crash loop detected: exited {} times within {}s of starting, backing off",
+                        consecutive_failures,
+                        stable_after.as_secs()
+                    );
+                    tracing::warn!("[{}] {}", service.id, msg);
+                    service.log_buffer.push("error".to_string(), msg).await;
                 }
+                service.set_state(ServiceState::CrashLoop).await;
+            }
+
+            let next_restart_at =
+                chrono::Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+            service
+                .set_restart_status(RestartStatus {
+                    consecutive_failures,
+                    next_restart_at: Some(next_restart_at.to_rfc3339()),
+                })
+                .await;
+
+            info!(
+                "[{}] restarting child in {:.1}s (attempt {})",
+                service.id,
+                delay.as_secs_f64(),
+                consecutive_failures
+            );
+
+            // Stop requests must not wait for a long backoff to elapse.
+            let interrupted = tokio::select! {
+                _ = sleep(delay) => false,
+                _ = global_shutdown_rx.recv() => true,
+                _ = service_shutdown_rx.recv() => true,
+            };
+
+            service
+                .set_restart_status(RestartStatus {
+                    consecutive_failures,
+                    next_restart_at: None,
+                })
+                .await;
+
+            if interrupted {
+                info!("[{}] shutdown received during restart backoff", service.id);
+                service.set_state(ServiceState::Stopped).await;
+                break;
             }
         }
 
+        service.set_health(None).await;
+        RunState::remove(&service.run_state_path());
+        Cgroup::for_service(&service.id).remove();
+        if let Some(pid) = last_pid {
+            self.reaper.release(pid);
+        }
         info!("supervisor exiting cleanly");
         Ok(())
     }
 }
+
+/// Failures in a row after a run that lasted `ran_for`, given `previous`. A
+/// clean exit is not a failure and ends the streak, as does a run that got
+/// past `stable_after`.
+fn count_failures(
+    previous: u32,
+    clean_exit: bool,
+    ran_for: Duration,
+    stable_after: Duration,
+) -> u32 {
+    if clean_exit {
+        0
+    } else if ran_for >= stable_after {
+        1
+    } else {
+        previous.saturating_add(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn short_failed_runs_add_up() {
+        let mut failures = 0;
+        for expected in 1..=5 {
+            failures = count_failures(failures, false, Duration::from_millis(10), STABLE_AFTER);
+            assert_eq!(failures, expected);
+        }
+    }
+
+    #[test]
+    fn a_stable_run_starts_a_new_streak() {
+        assert_eq!(count_failures(4, false, STABLE_AFTER, STABLE_AFTER), 1);
+    }
+
+    #[test]
+    fn clean_exits_are_not_failures() {
+        // A periodic job that exits 0 right away must never reach the crash
+        // loop threshold under `always`.
+        let mut failures = 0;
+        for _ in 0..100 {
+            failures = count_failures(failures, true, Duration::from_millis(10), STABLE_AFTER);
+        }
+        assert_eq!(failures, 0);
+        assert_eq!(count_failures(3, true, Duration::ZERO, STABLE_AFTER), 0);
+    }
+}
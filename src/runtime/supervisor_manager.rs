@@ -1,15 +1,43 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{RwLock, broadcast};
 use tokio::task::JoinHandle;
 
 use crate::platform::port_manager::PortManager;
+use crate::runtime::cgroup;
+use crate::runtime::reaper::Reaper;
 use crate::runtime::service::Service;
 use crate::runtime::supervisor::Supervisor;
 
+/// Time a supervisor gets beyond its service's `stop_timeout` to finish
+/// after it has killed the process.
+const STOP_MARGIN: Duration = Duration::from_secs(10);
+
 pub struct SupervisorHandle {
     pub shutdown_tx: broadcast::Sender<()>,
     pub join_handle: JoinHandle<()>,
+    /// `stop_timeout` of the service as it was started, which is what the
+    /// supervisor goes by even after the service is updated.
+    pub stop_timeout: Duration,
+}
+
+/// A supervisor that has been told to stop, out of the manager.
+struct StoppingSupervisor {
+    id: String,
+    join_handle: JoinHandle<()>,
+    timeout: Duration,
+}
+
+impl StoppingSupervisor {
+    async fn wait(self) {
+        if tokio::time::timeout(self.timeout, self.join_handle)
+            .await
+            .is_err()
+        {
+            tracing::warn!("[{}] supervisor failed to stop within timeout", self.id);
+        }
+    }
 }
 
 pub struct SupervisorManager {
@@ -17,6 +45,7 @@ pub struct SupervisorManager {
     supervisors: HashMap<String, SupervisorHandle>,
     shutdown_tx: broadcast::Sender<()>,
     port_manager: Option<Arc<RwLock<PortManager>>>,
+    reaper: Arc<Reaper>,
 }
 
 impl SupervisorManager {
@@ -27,6 +56,7 @@ impl SupervisorManager {
             supervisors: HashMap::new(),
             shutdown_tx,
             port_manager: None,
+            reaper: Arc::new(Reaper::new()),
         }
     }
 
@@ -50,10 +80,13 @@ impl SupervisorManager {
         Ok(())
     }
 
-    pub fn update_service(&mut self, service: Service) -> anyhow::Result<()> {
-        if !self.services.contains_key(&service.id) {
+    /// Replaces the settings of a registered service. Its state, PID and
+    /// other runtime state carry over to `service`.
+    pub fn update_service(&mut self, mut service: Service) -> anyhow::Result<()> {
+        let Some(current) = self.services.get(&service.id) else {
             anyhow::bail!("service {} not registered", service.id);
-        }
+        };
+        service.keep_runtime_state(current);
         self.services.insert(service.id.clone(), service);
         Ok(())
     }
@@ -71,6 +104,16 @@ impl SupervisorManager {
     }
 
     pub async fn start(&mut self, id: &str) -> anyhow::Result<()> {
+        // A supervisor that returned because its restart policy said so
+        // leaves its handle behind.
+        if self
+            .supervisors
+            .get(id)
+            .is_some_and(|h| h.join_handle.is_finished())
+        {
+            self.supervisors.remove(id);
+        }
+
         if self.supervisors.contains_key(id) {
             anyhow::bail!("service {} already running", id);
         }
@@ -86,9 +129,11 @@ impl SupervisorManager {
         let service_shutdown_rx = service_shutdown_tx.subscribe();
 
         let service_clone = service.clone();
+        let port_manager = self.port_manager.clone();
+        let reaper = self.reaper.clone();
 
         let handle = tokio::spawn(async move {
-            let mut supervisor = Supervisor::new();
+            let mut supervisor = Supervisor::new(port_manager, reaper);
             if let Err(e) = supervisor
                 .run_supervised(service_clone, global_shutdown_rx, service_shutdown_rx)
                 .await
@@ -97,121 +142,55 @@ impl SupervisorManager {
             }
         });
 
-        // Spawn a one-shot port verification task: after a grace period, read the
-        // process's actual listening ports from /proc and check for conflicts with
-        // ports already allocated to other services.  On conflict, the service is
-        // stopped and the error is written to the log buffer.
-        if let Some(pm) = &self.port_manager {
-            let service_monitor = service.clone();
-            let pm_clone = Arc::clone(pm);
-            let shutdown_tx_clone = service_shutdown_tx.clone();
-
-            tokio::spawn(async move {
-                // Give the process time to bind its listening socket.
-                tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
-
-                let pid = match service_monitor.get_pid().await {
-                    Some(p) => p,
-                    None => {
-                        tracing::warn!(
-                            "[{}] no PID found after grace period, skipping port check",
-                            service_monitor.id
-                        );
-                        return;
-                    }
-                };
-
-                let listening_ports = PortManager::get_listening_ports_for_pid(pid);
-
-                if listening_ports.is_empty() {
-                    tracing::warn!(
-                        "[{}] service (pid {}) is not listening on any TCP ports",
-                        service_monitor.id,
-                        pid
-                    );
-                    return;
-                }
-
-                tracing::info!(
-                    "[{}] service (pid {}) listening on ports: {:?}",
-                    service_monitor.id,
-                    pid,
-                    listening_ports
-                );
-
-                let pm = pm_clone.read().await;
-                if let Some((conflict_port, conflict_service)) =
-                    pm.find_conflict(&service_monitor.id, &listening_ports)
-                {
-                    let msg = format!(
-                        "port conflict: port {} is already allocated to service '{}'",
-                        conflict_port, conflict_service
-                    );
-                    tracing::error!("[{}] {}", service_monitor.id, msg);
-                    service_monitor
-                        .log_buffer
-                        .push("error".to_string(), msg)
-                        .await;
-                    service_monitor
-                        .set_state(crate::runtime::service::ServiceState::Failed)
-                        .await;
-                    let _ = shutdown_tx_clone.send(());
-                }
-            });
-        }
-
         self.supervisors.insert(
             service.id.clone(),
             SupervisorHandle {
                 shutdown_tx: service_shutdown_tx,
                 join_handle: handle,
+                stop_timeout: service.stop_timeout,
             },
         );
 
         Ok(())
     }
 
-    pub async fn stop(&mut self, id: &str) -> anyhow::Result<()> {
-        if let Some(handle) = self.supervisors.remove(id) {
-            let _ = handle.shutdown_tx.send(());
-
-            // Try to kill the process if running
-            if let Some(service) = self.services.get(id) {
-                let pid = service.pid.read().await.clone();
-                if let Some(pid) = pid {
-                    #[cfg(unix)]
-                    unsafe {
-                        libc::kill(pid as i32, libc::SIGTERM);
-                    }
-                    // Wait a bit for graceful exit
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                    // If still running, force kill
-                    #[cfg(unix)]
-                    unsafe {
-                        libc::kill(pid as i32, libc::SIGKILL);
-                    }
-                }
-            }
-
-            match tokio::time::timeout(tokio::time::Duration::from_secs(30), handle.join_handle)
-                .await
-            {
-                Ok(_) => Ok(()),
-                Err(_) => {
-                    tracing::warn!("[{}] supervisor failed to stop within timeout", id);
-                    Ok(())
-                }
+    /// Stops the service `id` and waits for its supervisor to finish. That
+    /// can take the service's whole `stop_timeout`, so the lock on the
+    /// manager is only held to tell the supervisor to stop, and other
+    /// requests go on meanwhile. Returns whether it was running.
+    pub async fn stop(manager: &RwLock<Self>, id: &str) -> bool {
+        let stopping = manager.write().await.begin_stop(id);
+        match stopping {
+            Some(stopping) => {
+                stopping.wait().await;
+                true
             }
-        } else {
-            anyhow::bail!("service {} not running", id);
+            None => false,
         }
     }
 
-    pub async fn restart(&mut self, id: &str) -> anyhow::Result<()> {
-        if self.supervisors.contains_key(id) {
-            self.stop(id).await?;
+    /// Stops the service `id` if it is running, like [`Self::stop`], and
+    /// starts it again.
+    pub async fn restart(manager: &RwLock<Self>, id: &str) -> anyhow::Result<()> {
+        let stopping = manager.write().await.begin_stop(id);
+        if let Some(stopping) = stopping {
+            stopping.wait().await;
         }
-        self.start(&id).await
+
+        manager.write().await.start(id).await
+    }
+
+    fn begin_stop(&mut self, id: &str) -> Option<StoppingSupervisor> {
+        let handle = self.supervisors.remove(id)?;
+        let _ = handle.shutdown_tx.send(());
+
+        // The supervisor signals the process group and escalates to SIGKILL
+        // itself, so only wait for it to finish.
+        Some(StoppingSupervisor {
+            id: id.to_string(),
+            join_handle: handle.join_handle,
+            timeout: handle.stop_timeout + STOP_MARGIN,
+        })
     }
 
     pub async fn list_ids(&self) -> Vec<String> {
@@ -222,7 +201,7 @@ impl SupervisorManager {
     pub async fn list(&self) -> Vec<&Service> {
         let mut result = Vec::new();
 
-        for (_, service) in &self.services {
+        for service in self.services.values() {
             result.push(service);
         }
 
@@ -234,7 +213,26 @@ impl SupervisorManager {
     }
 
     pub async fn start_all(&mut self) -> anyhow::Result<()> {
-        let ids: Vec<String> = self.services.keys().cloned().collect();
+        if let Err(e) = self.reaper.clone().start() {
+            tracing::warn!("failed to become child subreaper: {}", e);
+        }
+        if let Err(e) = cgroup::init() {
+            tracing::warn!(
+                "failed to set up cgroups, resource limits may fail to apply: {:#}",
+                e
+            );
+        }
+
+        let mut ids = Vec::new();
+
+        for service in self.services.values() {
+            if service.start_on_boot {
+                ids.push(service.id.clone());
+            } else {
+                tracing::info!("[{}] desired state is stopped, not starting", service.id);
+                Supervisor::terminate_orphan(service).await;
+            }
+        }
 
         for id in ids {
             if let Err(e) = self.start(&id).await {
@@ -245,20 +243,124 @@ impl SupervisorManager {
         Ok(())
     }
 
+    /// How long [`Self::shutdown_all`] waits for the supervisors at most:
+    /// the longest `stop_timeout` of the running services, plus a margin.
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.supervisors
+            .values()
+            .map(|handle| handle.stop_timeout)
+            .max()
+            .unwrap_or_default()
+            + STOP_MARGIN
+    }
+
     pub async fn shutdown_all(&mut self) {
         let _ = self.shutdown_tx.send(());
 
+        // The supervisors all stop at once, so each one's deadline counts
+        // from now.
+        let started = tokio::time::Instant::now();
         let handles: Vec<_> = self.supervisors.drain().collect();
 
         for (id, handle) in handles {
-            match tokio::time::timeout(tokio::time::Duration::from_secs(30), handle.join_handle)
-                .await
-            {
+            let deadline = started + handle.stop_timeout + STOP_MARGIN;
+            match tokio::time::timeout_at(deadline, handle.join_handle).await {
                 Ok(_) => tracing::info!("[{}] supervisor stopped", id),
                 Err(_) => {
                     tracing::warn!("[{}] supervisor failed to stop within timeout, forcing", id)
                 }
             }
         }
+
+        // Log files are written in batches, so flush what the services wrote
+        // last before the runtime goes away.
+        for service in self.list_cloned().await {
+            service.log_buffer.flush().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::LogRotation, registry::ServiceDefinition, runtime::service::ServiceState};
+
+    fn service(definition: serde_json::Value) -> Service {
+        let def: ServiceDefinition = serde_json::from_value(definition).unwrap();
+        let working_dir = std::env::temp_dir()
+            .join(format!(
+                "dockless-manager-{}-{}",
+                std::process::id(),
+                def.id
+            ))
+            .to_string_lossy()
+            .into_owned();
+        let log_buffer = Service::create_log_buffer(&def, &working_dir, &LogRotation::default());
+        Service::new(&def, def.env.clone(), working_dir, log_buffer)
+    }
+
+    #[tokio::test]
+    async fn updating_a_running_service_keeps_its_runtime_state() {
+        let mut manager = SupervisorManager::new();
+        let running = service(serde_json::json!({ "id": "web", "name": "web", "args": ["-v"] }));
+        running.set_state(ServiceState::Running).await;
+        running.set_pid(Some(4242)).await;
+        running.forked.lock().unwrap().insert(4243, 1);
+        manager.register_service(running).unwrap();
+
+        manager
+            .update_service(service(
+                serde_json::json!({ "id": "web", "name": "web", "args": ["-q"] }),
+            ))
+            .unwrap();
+
+        let updated = manager.get_service("web").unwrap();
+        assert_eq!(updated.args, vec!["-q".to_string()]);
+        assert!(matches!(updated.get_state().await, ServiceState::Running));
+        assert_eq!(updated.get_pid().await, Some(4242));
+        assert!(updated.forked.lock().unwrap().contains_key(&4243));
+    }
+
+    #[tokio::test]
+    async fn stopping_a_service_that_is_not_running_succeeds() {
+        let mut manager = SupervisorManager::new();
+        manager
+            .register_service(service(serde_json::json!({ "id": "idle", "name": "idle" })))
+            .unwrap();
+        let manager = RwLock::new(manager);
+
+        assert!(!SupervisorManager::stop(&manager, "idle").await);
+        assert!(!SupervisorManager::stop(&manager, "ghost").await);
+    }
+
+    fn idle_handle(stop_timeout: Duration) -> SupervisorHandle {
+        SupervisorHandle {
+            shutdown_tx: broadcast::channel(1).0,
+            join_handle: tokio::spawn(std::future::pending()),
+            stop_timeout,
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_the_longest_stop_timeout() {
+        let mut manager = SupervisorManager::new();
+        assert_eq!(manager.shutdown_timeout(), STOP_MARGIN);
+
+        for (id, secs) in [("fast", 5), ("slow", 90), ("default", 10)] {
+            manager
+                .supervisors
+                .insert(id.to_string(), idle_handle(Duration::from_secs(secs)));
+        }
+        assert_eq!(
+            manager.shutdown_timeout(),
+            Duration::from_secs(90) + STOP_MARGIN
+        );
+    }
+
+    #[tokio::test]
+    async fn updating_an_unknown_service_fails() {
+        let mut manager = SupervisorManager::new();
+        let web = service(serde_json::json!({ "id": "ghost", "name": "ghost" }));
+        assert!(manager.update_service(web).is_err());
     }
 }